@@ -0,0 +1,149 @@
+use std::{collections::BTreeSet, fs::File, io::{self, BufWriter, Write}};
+
+use clap::{value_parser, Arg, Command};
+use rinex::{header::Header, observation::EpochFlag, prelude::{Duration, Epoch}, Rinex};
+use rtcmlib::{encoder::{MsmType, RtcmEncoder}, StationInfo};
+
+// cli interface
+
+fn command() -> clap::Command {
+    Command::new("rnx2rtcm")
+        .version("1.0")
+        .author("Urban Traction, Inc.")
+        .about("RINEX OBS to RTCM3 MSM encoder")
+        .arg(
+            Arg::new("msm")
+                .long("msm")
+                .help("MSM type: 4 (DF402 lock time, 1 dB-Hz CNR) or 7 (extended resolution and phase range rate)")
+                .value_parser(["4", "7"])
+                .default_value("7"))
+        .arg(
+            Arg::new("station-id")
+                .long("station-id")
+                .help("Reference station id (DF003) written in every message")
+                .value_parser(value_parser!(u16).range(0..4096))
+                .default_value("0"))
+        .arg(
+            Arg::new("station-interval")
+                .long("station-interval")
+                .help("Seconds between 1005/1033 station messages")
+                .value_parser(value_parser!(u64))
+                .default_value("10"))
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .help("RTCM output path, - for stdout (default: <input>.rtcm)"))
+        .arg(
+            Arg::new("file_path")
+                .help("RINEX OBS input")
+                .required(true)
+                .index(1))
+}
+
+// 1033 descriptors and 1005 position from the RINEX header
+fn station_info(header:&Header) -> (StationInfo, Option<(f64, f64, f64)>) {
+
+    let mut station = StationInfo::default();
+
+    if let Some(antenna) = &header.rcvr_antenna {
+        station.antenna_descriptor = Some(antenna.model.clone());
+        station.antenna_serial_number = Some(antenna.sn.clone());
+    }
+
+    if let Some(receiver) = &header.rcvr {
+        station.receiver_type = Some(receiver.model.clone());
+        station.receiver_firmware_version = Some(receiver.firmware.clone());
+        station.receiver_serial_number = Some(receiver.sn.clone());
+    }
+
+    let position = header.ground_position.as_ref().map(|position| position.to_ecef_wgs84());
+
+    (station, position)
+}
+
+fn main() {
+
+    let matches = command().get_matches();
+
+    let file_path = matches.get_one::<String>("file_path").unwrap();
+    let msm:MsmType = matches.get_one::<String>("msm").unwrap().parse().unwrap();
+    let station_id = *matches.get_one::<u16>("station-id").unwrap();
+    let station_interval = Duration::from_seconds(*matches.get_one::<u64>("station-interval").unwrap() as f64);
+    let output = matches.get_one::<String>("output").cloned().unwrap_or_else(|| format!("{}.rtcm", file_path));
+
+    let rinex = match Rinex::from_file(file_path) {
+        Ok(rinex) => rinex,
+        Err(e) => {
+            eprintln!("unable to read {}: {:?}", file_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    let rtcm_data = match rinex.record.as_obs() {
+        Some(rtcm_data) => rtcm_data,
+        None => {
+            eprintln!("{} is not a RINEX observation file", file_path);
+            std::process::exit(1);
+        }
+    };
+
+    let (station, position) = station_info(&rinex.header);
+    let constellations:BTreeSet<_> = rtcm_data.values().flat_map(|(_, satellites)| satellites.keys().map(|sv| sv.constellation)).collect();
+
+    let mut writer:Box<dyn Write> = if output == "-" {
+        Box::new(BufWriter::new(io::stdout()))
+    }
+    else {
+        match File::create(&output) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("unable to create {}: {}", output, e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let mut encoder = RtcmEncoder::new(station_id, msm);
+    let mut last_station:Option<Epoch> = None;
+
+    for ((epoch, flag), (_, satellites)) in rtcm_data.iter() {
+
+        // event epochs (antenna moved, header records, ...) carry no observations
+        if *flag != EpochFlag::Ok && *flag != EpochFlag::PowerFailure {
+            continue;
+        }
+
+        let mut frames:Vec<Vec<u8>> = Vec::new();
+
+        let station_due = match last_station {
+            Some(last_station) => *epoch - last_station >= station_interval,
+            None => true
+        };
+
+        if station_due {
+            if let Some(position) = position {
+                frames.push(encoder.encode_1005(position, &constellations));
+            }
+            frames.push(encoder.encode_1033(&station));
+            last_station = Some(*epoch);
+        }
+
+        frames.extend(encoder.encode_epoch(*epoch, satellites));
+
+        for frame in frames {
+            if let Err(e) = writer.write_all(&frame) {
+                eprintln!("unable to write {}: {}", output, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Err(e) = writer.flush() {
+        eprintln!("unable to write {}: {}", output, e);
+        std::process::exit(1);
+    }
+
+    for ((constellation, code), count) in encoder.get_skipped_signals() {
+        eprintln!("skipped {} {:?} {} signals without an MSM mapping", count, constellation, code);
+    }
+}
@@ -0,0 +1,261 @@
+// frame by frame listing of an rtcm log (offset, length, message number, crc status, decoded fields)
+// a candidate failing CRC is listed with its claimed length and message number, the other bytes between
+// valid frames (noise, NMEA, a truncated tail) as one garbage range each
+
+use std::{io::{self, Read, Write}, path::Path};
+
+use rtcm_rs::MsgFrameIter;
+use rtcmlib::{compression, framing::{FrameBuffer, GarbageRange, RawFrame}, nmea};
+use serde_json::{json, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    Text,
+    Json,
+    Ndjson
+}
+
+impl DumpFormat {
+    pub fn from_str(format:&str) -> Option<DumpFormat> {
+        match format {
+            "text" => Some(DumpFormat::Text),
+            "json" => Some(DumpFormat::Json),
+            "ndjson" => Some(DumpFormat::Ndjson),
+            _ => None
+        }
+    }
+}
+
+fn decode_fields(frame:&RawFrame) -> Option<rtcm_rs::Message> {
+    if !frame.crc_ok {
+        return None;
+    }
+    MsgFrameIter::new(frame.data.as_slice()).next().map(|message_frame| message_frame.get_message())
+}
+
+fn frame_json(frame:&RawFrame) -> Value {
+    json!({
+        "offset": frame.offset,
+        "length": frame.data.len(),
+        "message_number": frame.message_number(),
+        "crc_ok": frame.crc_ok,
+        "fields": decode_fields(frame).map(|message| serde_json::to_value(&message).unwrap_or(Value::Null))
+    })
+}
+
+fn frame_text(frame:&RawFrame) -> String {
+
+    let message_number = frame.message_number().map(|n| n.to_string()).unwrap_or("-".to_string());
+    let crc = if frame.crc_ok { "ok" } else { "FAIL" };
+
+    let mut line = format!("{:>10} {:>5} {:>5} {:<4}", frame.offset, frame.data.len(), message_number, crc);
+
+    if let Some(message) = decode_fields(frame) {
+        line.push_str(&format!(" {:?}", message));
+    }
+
+    line
+}
+
+fn garbage_json(garbage:&GarbageRange) -> Value {
+    json!({
+        "offset": garbage.offset,
+        "length": garbage.data.len(),
+        "garbage": true,
+        "nmea_sentences": nmea::sentences(&garbage.data).len()
+    })
+}
+
+fn garbage_text(garbage:&GarbageRange) -> String {
+
+    let mut line = format!("{:>10} {:>5} {:>5} {:<4} garbage", garbage.offset, garbage.data.len(), "-", "-");
+
+    let sentences = nmea::sentences(&garbage.data).len();
+    if sentences > 0 {
+        line.push_str(&format!(" ({} NMEA sentences)", sentences));
+    }
+
+    line
+}
+
+// one entry of the listing, comma separated in json
+fn write_entry<W:Write>(out:&mut W, format:DumpFormat, first:&mut bool, text:String, value:Value) -> io::Result<()> {
+    match format {
+        DumpFormat::Text => writeln!(out, "{}", text)?,
+        DumpFormat::Ndjson => writeln!(out, "{}", value)?,
+        DumpFormat::Json => {
+            if !*first {
+                writeln!(out, ",")?;
+            }
+            write!(out, "{}", serde_json::to_string_pretty(&value).unwrap())?;
+        }
+    }
+    *first = false;
+    Ok(())
+}
+
+fn selected(frame:&RawFrame, filter:&Vec<u16>) -> bool {
+    filter.is_empty() || frame.message_number().map(|n| filter.contains(&n)).unwrap_or(false)
+}
+
+// input bytes `start..end` of `garbage`, left out when only some messages are listed
+fn write_garbage_part<W:Write>(out:&mut W, format:DumpFormat, first:&mut bool, garbage:&GarbageRange, start:u64, end:u64, filter:&Vec<u16>) -> io::Result<()> {
+    if start >= end || !filter.is_empty() {
+        return Ok(());
+    }
+    let part = GarbageRange {offset:start, data:garbage.data[(start - garbage.offset) as usize..(end - garbage.offset) as usize].to_vec()};
+    write_entry(out, format, first, garbage_text(&part), garbage_json(&part))
+}
+
+// completed garbage ranges, with the failed candidates inside them cut out and listed in place
+fn write_garbage<W:Write>(out:&mut W, format:DumpFormat, first:&mut bool, frame_buffer:&mut FrameBuffer, failed:&mut Vec<RawFrame>, filter:&Vec<u16>) -> io::Result<()> {
+    for garbage in frame_buffer.take_garbage() {
+
+        let end = garbage.offset + garbage.data.len() as u64;
+        let mut offset = garbage.offset;
+
+        let inside = failed.iter().take_while(|frame| frame.offset < end).count();
+        for frame in failed.drain(..inside) {
+            let start = frame.offset.clamp(offset, end);
+            write_garbage_part(out, format, first, &garbage, offset, start, filter)?;
+            if selected(&frame, filter) {
+                write_entry(out, format, first, frame_text(&frame), frame_json(&frame))?;
+            }
+            offset = (frame.offset + frame.data.len() as u64).clamp(start, end);
+        }
+
+        write_garbage_part(out, format, first, &garbage, offset, end, filter)?;
+    }
+    Ok(())
+}
+
+pub fn dump_file(file_path:&Path, format:DumpFormat, filter:&Vec<u16>) -> io::Result<()> {
+
+    let (_, mut reader) = compression::open_file(file_path)?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    dump(&mut reader, &mut out, format, filter)
+}
+
+fn dump<R:Read, W:Write>(reader:&mut R, out:&mut W, format:DumpFormat, filter:&Vec<u16>) -> io::Result<()> {
+
+    let mut frame_buffer = FrameBuffer::new();
+    let mut buffer = [0u8; 4096];
+    let mut first = true;
+    // failed candidates waiting for the garbage range they're part of to complete
+    let mut failed:Vec<RawFrame> = Vec::new();
+
+    if format == DumpFormat::Json {
+        writeln!(out, "[")?;
+    }
+    else if format == DumpFormat::Text {
+        writeln!(out, "{:>10} {:>5} {:>5} {:<4} fields", "offset", "len", "msg", "crc")?;
+    }
+
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+
+        frame_buffer.extend(&buffer[..n]);
+
+        while let Some(frame) = frame_buffer.next_raw_frame() {
+
+            if !frame.crc_ok {
+                failed.push(frame);
+                continue;
+            }
+
+            write_garbage(out, format, &mut first, &mut frame_buffer, &mut failed, filter)?;
+
+            if !selected(&frame, filter) {
+                continue;
+            }
+
+            write_entry(out, format, &mut first, frame_text(&frame), frame_json(&frame))?;
+        }
+    }
+
+    frame_buffer.finish();
+    write_garbage(out, format, &mut first, &mut frame_buffer, &mut failed, filter)?;
+
+    if format == DumpFormat::Json {
+        writeln!(out, "\n]")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debug_log() -> Vec<u8> {
+        std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm")).unwrap()
+    }
+
+    // an NMEA sentence and a broken frame ahead of the log, a truncated frame after it
+    fn corrupt_log() -> (Vec<u8>, usize, usize) {
+        let rtcm_buffer = debug_log();
+
+        let mut data = b"$GPGGA,092750.000,5321.6802,N,00630.3372,W,1,8,1.03,61.7,M,55.2,M,,*76\r\n".to_vec();
+        data.extend_from_slice(&[0xD3, 0x00, 0x05, 0x01, 0x02, 0x03, 0x04, 0x05, 0x00, 0x00, 0x00]);
+        let prefix_len = data.len();
+        data.extend_from_slice(&rtcm_buffer);
+        data.extend_from_slice(&rtcm_buffer[..20]);
+
+        (data, prefix_len, rtcm_buffer.len())
+    }
+
+    fn dump_lines(data:&[u8], filter:&Vec<u16>) -> Vec<Value> {
+        let mut out:Vec<u8> = Vec::new();
+        dump(&mut &data[..], &mut out, DumpFormat::Ndjson, filter).unwrap();
+        String::from_utf8(out).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn lists_garbage_ranges_between_frames() {
+        let (data, prefix_len, rtcm_len) = corrupt_log();
+        let lines = dump_lines(&data, &Vec::new());
+
+        // the NMEA range, the broken frame (claiming message 16), six frames, one range for the truncated tail
+        let nmea_len = prefix_len - 11;
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[0], json!({"offset": 0, "length": nmea_len, "garbage": true, "nmea_sentences": 1}));
+        assert_eq!(lines[1], json!({"offset": nmea_len, "length": 11, "message_number": 16, "crc_ok": false, "fields": null}));
+        assert!(lines[2..8].iter().all(|line| line["crc_ok"] == json!(true)));
+        assert_eq!(lines[2]["offset"], json!(prefix_len));
+        assert_eq!(lines[2]["message_number"], json!(1019));
+        assert_eq!(lines[8], json!({"offset": prefix_len + rtcm_len, "length": 20, "garbage": true, "nmea_sentences": 0}));
+    }
+
+    #[test]
+    fn lists_corrupted_frame_with_failed_crc() {
+        // a bit flipped in the first 1077 (offset 136, 186 bytes)
+        let mut data = debug_log();
+        data[200] ^= 0x01;
+        let lines = dump_lines(&data, &Vec::new());
+
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[2], json!({"offset": 136, "length": 186, "message_number": 1077, "crc_ok": false, "fields": null}));
+        assert!(lines.iter().all(|line| line.get("garbage").is_none()));
+        assert_eq!(lines.iter().filter(|line| line["crc_ok"] == json!(true)).count(), 5);
+
+        let mut out:Vec<u8> = Vec::new();
+        dump(&mut &data[..], &mut out, DumpFormat::Text, &Vec::new()).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.lines().nth(3).unwrap().trim_end(), format!("{:>10} {:>5} {:>5} {:<4}", 136, 186, 1077, "FAIL").trim_end());
+    }
+
+    #[test]
+    fn filter_lists_only_selected_frames() {
+        let (data, _, _) = corrupt_log();
+        let lines = dump_lines(&data, &vec![1077]);
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line["message_number"] == json!(1077)));
+    }
+}
@@ -0,0 +1,232 @@
+// one pass overview of a log: time span, signals, message rates and station metadata
+// frames are split and only their headers read, station messages are the only ones decoded in full
+
+use std::{collections::{BTreeMap, BTreeSet}, io::{self, Read}, path::Path};
+
+use rinex::prelude::Epoch;
+use rtcm_rs::MsgFrameIter;
+use rtcmlib::{compression, encoder, framing::{self, FrameBuffer, TimeOfWeek}, StationInfo};
+use serde_json::{json, Value};
+
+// station messages, decoded for their descriptors and position
+const STATION_MESSAGES:[u16; 5] = [1005, 1006, 1007, 1008, 1033];
+
+#[derive(Debug, Default)]
+pub struct LogInfo {
+    pub message_counts:BTreeMap<u16, u64>,
+    /// signal codes by constellation, MSM signal ids without a RINEX code as `id<n>`
+    pub signals:BTreeMap<String, BTreeSet<String>>,
+    pub station:StationInfo,
+    /// GPS week of the first MSM, from the first ephemeris message (1019/1042/1046)
+    pub gps_week:Option<u64>,
+    // earliest / latest MSM time (ms): time of week in the first MSM's week, continued across rollovers
+    first_ms:Option<u64>,
+    last_ms:Option<u64>
+}
+
+impl LogInfo {
+
+    /// GLONASS MSMs have no GPS time of week and don't count towards the time span.
+    pub fn scan(data:&[u8]) -> LogInfo {
+
+        let mut info = LogInfo::default();
+        let mut time_of_week = TimeOfWeek::new();
+
+        let mut frame_buffer = FrameBuffer::new();
+        frame_buffer.extend(data);
+
+        while let Some(frame) = frame_buffer.next_raw_frame() {
+
+            if !frame.crc_ok || frame.message_number().is_none() {
+                continue;
+            }
+            let message_number = frame.message_number().unwrap();
+
+            *info.message_counts.entry(message_number).or_insert(0) += 1;
+
+            if let Some(station_id) = frame.station_id() {
+                info.station.station_ids.insert(station_id);
+            }
+
+            // an ephemeris after a week rollover still dates the MSMs before it
+            if info.gps_week.is_none() {
+                info.gps_week = framing::ephemeris_gps_week(&frame.data).map(|week| week.saturating_sub(time_of_week.weeks()));
+            }
+
+            if let Some(time_of_week_ms) = frame.msm_time_of_week_ms() {
+                let time_ms = time_of_week.continuous_ms(time_of_week_ms);
+                info.first_ms = Some(info.first_ms.map_or(time_ms, |first| first.min(time_ms)));
+                info.last_ms = Some(info.last_ms.map_or(time_ms, |last| last.max(time_ms)));
+            }
+
+            if let (Some(constellation), Some(signal_ids)) = (encoder::msm_constellation(message_number), framing::msm_signal_ids(&frame.data)) {
+                let codes = info.signals.entry(constellation.to_string()).or_insert(BTreeSet::new());
+                for signal_id in signal_ids {
+                    codes.insert(encoder::msm_signal_code(constellation, signal_id).map(|code| code.to_string()).unwrap_or(format!("id{}", signal_id)));
+                }
+            }
+
+            if STATION_MESSAGES.contains(&message_number) {
+                if let Some(message_frame) = MsgFrameIter::new(frame.data.as_slice()).next() {
+                    info.station.update(&message_frame.get_message());
+                }
+            }
+        }
+
+        info
+    }
+
+    pub fn scan_file(file_path:&Path) -> io::Result<LogInfo> {
+        let (_, mut reader) = compression::open_file(file_path)?;
+        let mut data:Vec<u8> = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Ok(LogInfo::scan(&data))
+    }
+
+    pub fn span_seconds(&self) -> Option<f64> {
+        match (self.first_ms, self.last_ms) {
+            (Some(first), Some(last)) => Some((last - first) as f64 / 1000.0),
+            _ => None
+        }
+    }
+
+    pub fn first_epoch(&self) -> Option<Epoch> {
+        Some(rtcmlib::rtcm_gps_time2epoch(self.first_ms? as f64, self.gps_week?))
+    }
+
+    pub fn last_epoch(&self) -> Option<Epoch> {
+        Some(rtcmlib::rtcm_gps_time2epoch(self.last_ms? as f64, self.gps_week?))
+    }
+
+    // GPS time of week (s) of the earliest MSM, for logs without ephemeris
+    fn first_time_of_week(&self) -> Option<f64> {
+        self.first_ms.map(|ms| (ms % framing::WEEK_MS) as f64 / 1000.0)
+    }
+}
+
+pub fn info_json(info:&LogInfo) -> Value {
+
+    let station = &info.station;
+    let span = info.span_seconds();
+
+    let messages:serde_json::Map<String, Value> = info.message_counts.iter()
+        .map(|(message_number, count)| {
+            let rate = span.filter(|s| *s > 0.0).map(|s| *count as f64 / s);
+            (message_number.to_string(), json!({"count": count, "rate_hz": rate}))
+        })
+        .collect();
+
+    json!({
+        "first_epoch": info.first_epoch().map(|e| e.to_string()),
+        "last_epoch": info.last_epoch().map(|e| e.to_string()),
+        "first_time_of_week_s": info.first_time_of_week(),
+        "span_seconds": span,
+        "signals": info.signals,
+        "messages": messages,
+        "station_ids": station.station_ids,
+        "reference_position_ecef_m": station.reference_position.map(|(x, y, z)| vec![x, y, z]),
+        "antenna_height_m": station.antenna_height,
+        "antenna_descriptor": station.antenna_descriptor,
+        "antenna_setup_id": station.antenna_setup_id,
+        "antenna_serial_number": station.antenna_serial_number,
+        "receiver_type": station.receiver_type,
+        "receiver_firmware_version": station.receiver_firmware_version,
+        "receiver_serial_number": station.receiver_serial_number
+    })
+}
+
+pub fn info_text(info:&LogInfo) -> String {
+
+    let station = &info.station;
+    let span = info.span_seconds();
+
+    let optional = |value:&Option<String>| value.clone().unwrap_or("-".to_string());
+
+    let mut lines:Vec<String> = Vec::new();
+
+    match (info.first_epoch(), info.first_time_of_week()) {
+        (Some(first), _) => lines.push(format!("first epoch:        {}", first)),
+        (None, Some(time_of_week)) => lines.push(format!("first epoch:        - (no ephemeris for the week, GPS time of week {:.3} s)", time_of_week)),
+        (None, None) => lines.push("first epoch:        -".to_string())
+    }
+    lines.push(format!("last epoch:         {}", info.last_epoch().map(|e| e.to_string()).unwrap_or("-".to_string())));
+    lines.push(format!("span:               {}", span.map(|s| format!("{:.1} s", s)).unwrap_or("-".to_string())));
+
+    lines.push("signals:".to_string());
+    for (constellation, codes) in info.signals.iter() {
+        let codes:Vec<String> = codes.iter().cloned().collect();
+        lines.push(format!("  {:<10} {}", constellation, codes.join(" ")));
+    }
+
+    lines.push("messages:".to_string());
+    for (message_number, count) in info.message_counts.iter() {
+        let rate = span.filter(|s| *s > 0.0).map(|s| format!("{:.3} Hz", *count as f64 / s)).unwrap_or("-".to_string());
+        lines.push(format!("  {:>6} {:>10} {:>12}", message_number, count, rate));
+    }
+
+    let station_ids:Vec<String> = station.station_ids.iter().map(|id| id.to_string()).collect();
+    lines.push(format!("station ids:        {}", station_ids.join(" ")));
+
+    match station.reference_position {
+        Some((x, y, z)) => lines.push(format!("reference position: {:.4} {:.4} {:.4} (ECEF m)", x, y, z)),
+        None => lines.push("reference position: -".to_string())
+    }
+
+    if let Some(height) = station.antenna_height {
+        lines.push(format!("antenna height:     {:.4} m", height));
+    }
+
+    lines.push(format!("antenna:            {} (serial {})", optional(&station.antenna_descriptor), optional(&station.antenna_serial_number)));
+    lines.push(format!("receiver:           {} {} (serial {})", optional(&station.receiver_type), optional(&station.receiver_firmware_version), optional(&station.receiver_serial_number)));
+
+    lines.join("\n")
+}
+
+pub fn print_info(info:&LogInfo, format:&str) {
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&info_json(info)).unwrap()),
+        _ => println!("{}", info_text(info))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rtcmlib::RtcmDecoder;
+
+    const DEBUG_RTCM:&str = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm");
+
+    #[test]
+    fn scan_matches_decoder() {
+        let info = LogInfo::scan_file(Path::new(DEBUG_RTCM)).unwrap();
+
+        let mut rtcm_decoder = RtcmDecoder::new(false);
+        rtcm_decoder.load_file(Path::new(DEBUG_RTCM)).unwrap();
+
+        assert_eq!(info.first_epoch(), rtcm_decoder.get_first_epoch());
+        assert_eq!(info.last_epoch(), rtcm_decoder.get_last_epoch());
+        assert_eq!(&info.message_counts, &rtcm_decoder.get_stats().message_counts);
+        assert_eq!(&info.station, rtcm_decoder.get_station_info());
+
+        let mut signals:BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (constellation, code) in rtcm_decoder.extract_observed_signals() {
+            signals.entry(constellation.to_string()).or_insert(BTreeSet::new()).insert(code);
+        }
+        assert_eq!(info.signals, signals);
+    }
+
+    #[test]
+    fn span_without_ephemeris() {
+        // drop the 1019 and 1046 frames ahead of the MSMs
+        let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+        let with_ephemeris = LogInfo::scan(&rtcm_buffer);
+        let info = LogInfo::scan(&rtcm_buffer[136..]);
+
+        assert_eq!(info.gps_week, None);
+        assert_eq!(info.first_epoch(), None);
+        assert!(info.span_seconds().is_some());
+        assert_eq!(info.span_seconds(), with_ephemeris.span_seconds());
+        assert!(info_text(&info).contains("GPS time of week"));
+        assert_eq!(info_json(&info)["first_time_of_week_s"], info_json(&with_ephemeris)["first_time_of_week_s"]);
+    }
+}
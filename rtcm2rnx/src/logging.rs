@@ -0,0 +1,87 @@
+// stderr logger for the cli -- plain text or one json object per line
+// key/value context attached by the decoder (file, message, epoch) is appended to each record
+
+use std::io::Write;
+
+use log::{kv::{self, Key, Value, VisitSource}, LevelFilter, Log, Metadata, Record};
+use rinex::prelude::Epoch;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json
+}
+
+struct Logger {
+    format:LogFormat
+}
+
+// collects a record's key/values in order
+struct KeyValues(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for KeyValues {
+    fn visit_pair(&mut self, key:Key<'kvs>, value:Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+impl Log for Logger {
+
+    fn enabled(&self, metadata:&Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record:&Record) {
+
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut key_values = KeyValues(Vec::new());
+        let _ = record.key_values().visit(&mut key_values);
+
+        let line = match self.format {
+            LogFormat::Text => {
+                let mut line = format!("[{}] {}", record.level(), record.args());
+                for (key, value) in key_values.0.iter() {
+                    line.push_str(&format!(" {}={}", key, value));
+                }
+                line
+            }
+            LogFormat::Json => {
+                let mut fields = serde_json::Map::new();
+                fields.insert("time".to_string(), Epoch::now().map(|e| e.to_string()).unwrap_or_default().into());
+                fields.insert("level".to_string(), record.level().to_string().into());
+                fields.insert("target".to_string(), record.target().into());
+                fields.insert("msg".to_string(), record.args().to_string().into());
+                for (key, value) in key_values.0.into_iter() {
+                    fields.insert(key, value.into());
+                }
+                serde_json::Value::Object(fields).to_string()
+            }
+        };
+
+        let _ = writeln!(std::io::stderr(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+// info by default, each -v / -q moves one level up or down
+pub fn level_filter(verbose:u8, quiet:u8) -> LevelFilter {
+
+    let levels = [LevelFilter::Off, LevelFilter::Error, LevelFilter::Warn, LevelFilter::Info, LevelFilter::Debug, LevelFilter::Trace];
+    let default:i32 = 3;
+
+    let index = (default + verbose as i32 - quiet as i32).clamp(0, levels.len() as i32 - 1);
+
+    levels[index as usize]
+}
+
+pub fn init(format:LogFormat, level:LevelFilter) {
+    log::set_boxed_logger(Box::new(Logger {format})).expect("logger already initialized");
+    log::set_max_level(level);
+}
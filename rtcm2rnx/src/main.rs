@@ -1,10 +1,26 @@
-#![feature(hash_extract_if)]
 
-use std::{collections::HashMap,path::Path};
+use std::{borrow::Cow,collections::{BTreeMap, HashMap},fs::{File, OpenOptions},io::{self, BufWriter, Write},path::{Path, PathBuf},time::{Duration, Instant}};
 
-use clap::{value_parser, Arg, Command };
-use rinex::{header::Header, observation::HeaderFields, prelude::{Constellation, Observable}, version::Version, Rinex};
-use rtcmlib::RtcmDecoder;
+use clap::{value_parser, Arg, ArgAction, Command };
+use log::{debug, error, info, warn};
+use logging::LogFormat;
+use output::{ClockJumpMode, OutputCompression, OutputFormat, OutputOptions};
+use rinex::{header::Header, observation::{Crinex, HeaderFields}, prelude::{Constellation, Epoch, Observable}, version::Version, Rinex};
+use rtcmlib::{clock::ClockJumpTracker, filter, serial::{self, SerialConfig}, ErrorPolicy, QualityData, RtcmData, RtcmDecoder, SignalFilter, SnrMapping, TimeWindow};
+
+mod dump;
+mod info;
+mod logging;
+mod output;
+mod replay;
+mod report;
+mod split;
+
+// rinex writer path used for `--output -`
+const STDOUT_PATH:&str = "/dev/stdout";
+
+// how often a live conversion saves its --checkpoint between session flushes
+const CHECKPOINT_INTERVAL:Duration = Duration::from_secs(60);
 
 // cli interface
 
@@ -13,6 +29,27 @@ fn command() -> clap::Command {
         .version("1.0")
         .author("Urban Traction, Inc.")
         .about("RTCM3 to RINEX OBS converter")
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("More log output (repeat for more)")
+                .action(ArgAction::Count)
+                .global(true))
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Less log output (repeat for less)")
+                .action(ArgAction::Count)
+                .global(true))
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .help("Log records on stderr as text or one json object per line")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .global(true))
     
         .subcommand(
             Command::new("convert")
@@ -23,6 +60,186 @@ fn command() -> clap::Command {
                         .help("Use the simplifed rtklib lli algo (for diagnostics only)")
                         .value_parser(value_parser!(bool))
                         .default_value("false"))
+                .arg(
+                    Arg::new("snr-table")
+                        .long("snr-table")
+                        .help("CNR to RINEX signal strength indicator (1-9) table: RINEX spec or RTKLIB's floor(CNR/6)")
+                        .value_parser(["rinex", "rtklib"])
+                        .default_value("rinex"))
+                .arg(
+                    Arg::new("phase-align")
+                        .long("phase-align")
+                        .help("Apply RINEX 3 quarter cycle phase shifts to the band reference signal and write SYS / PHASE SHIFT records (RINEX files only)")
+                        .value_parser(value_parser!(bool))
+                        .default_value("false"))
+                .arg(
+                    Arg::new("clock-jumps")
+                        .long("clock-jumps")
+                        .help("Millisecond receiver clock jumps: ignore, detect (log and header comment) or repair code/phase")
+                        .value_parser(["off", "detect", "repair"])
+                        .default_value("off"))
+                .arg(
+                    Arg::new("slip-detect")
+                        .long("slip-detect")
+                        .help("Flag cycle slips the receiver's lock time missed: geometry-free / Melbourne-Wubbena on dual frequency, doppler predicted phase otherwise")
+                        .value_parser(value_parser!(bool))
+                        .default_value("false"))
+                .arg(
+                    Arg::new("on-error")
+                        .long("on-error")
+                        .help("Corrupt frames and unmappable signals: skip and count, or fail the conversion")
+                        .value_parser(["skip", "fail"])
+                        .default_value("skip"))
+                .arg(
+                    Arg::new("serial")
+                        .long("serial")
+                        .help("Read a live stream from a serial device (or pty) instead of a log file")
+                        .value_parser(value_parser!(bool))
+                        .default_value("false"))
+                .arg(
+                    Arg::new("baud")
+                        .long("baud")
+                        .help("Serial baud rate")
+                        .value_parser(value_parser!(u32))
+                        .default_value("115200"))
+                .arg(
+                    Arg::new("parity")
+                        .long("parity")
+                        .help("Serial parity")
+                        .value_parser(["none", "odd", "even"])
+                        .default_value("none"))
+                .arg(
+                    Arg::new("flow-control")
+                        .long("flow-control")
+                        .help("Serial flow control")
+                        .value_parser(["none", "software", "hardware"])
+                        .default_value("none"))
+                .arg(
+                    Arg::new("duration")
+                        .long("duration")
+                        .help("Stop reading a serial stream after this many seconds (default: until the device closes)")
+                        .value_parser(value_parser!(u64)))
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .help("RINEX output path (default: <input>.rnx), '-' for stdout, or output directory with --igs-name"))
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .help("Output format: RINEX, or long format tables (epoch, sv, observable, value, lli, snr) as CSV, NDJSON or Parquet")
+                        .value_parser(["rinex", "csv", "ndjson", "parquet"])
+                        .default_value("rinex"))
+                .arg(
+                    Arg::new("compress")
+                        .long("compress")
+                        .help("Output compression: gzip, Hatanaka (CRINEX) or gzip over CRINEX")
+                        .value_parser(["none", "gz", "crx", "crx.gz"])
+                        .default_value("none"))
+                .arg(
+                    Arg::new("igs-name")
+                        .long("igs-name")
+                        .help("Use the IGS long filename (SSSSMRCCC_R_YYYYDDDHHMM_01D_01S_MO.rnx)")
+                        .value_parser(value_parser!(bool))
+                        .default_value("false"))
+                .arg(
+                    Arg::new("split")
+                        .long("split")
+                        .help("Split output into fixed length sessions aligned to GPS time, e.g. 15m, 1h, 24h")
+                        .value_parser(output::parse_period))
+                .arg(
+                    Arg::new("marker")
+                        .long("marker")
+                        .help("Four character marker name for --igs-name")
+                        .default_value("XXXX"))
+                .arg(
+                    Arg::new("country")
+                        .long("country")
+                        .help("ISO 3166 three letter country code for --igs-name")
+                        .default_value("XXX"))
+                .arg(
+                    Arg::new("systems")
+                        .long("systems")
+                        .help("Only keep these constellations, e.g. G,E,C")
+                        .value_parser(filter::parse_constellation)
+                        .value_delimiter(','))
+                .arg(
+                    Arg::new("exclude-sv")
+                        .long("exclude-sv")
+                        .help("Drop these satellites, e.g. G05,E14")
+                        .value_parser(filter::parse_sv)
+                        .value_delimiter(','))
+                .arg(
+                    Arg::new("signals")
+                        .long("signals")
+                        .help("Only keep these signal codes, e.g. 1C,5Q")
+                        .value_parser(filter::parse_signal)
+                        .value_delimiter(','))
+                .arg(
+                    Arg::new("observables")
+                        .long("observables")
+                        .help("Only keep these observable types: C (code), L (phase), D (doppler), S (signal strength), e.g. C,L")
+                        .value_parser(filter::parse_observable)
+                        .value_delimiter(','))
+                .arg(
+                    Arg::new("start")
+                        .long("start")
+                        .help("Drop epochs before this time, e.g. \"2024-12-30T02:00:00 GPST\"")
+                        .value_parser(filter::parse_epoch))
+                .arg(
+                    Arg::new("end")
+                        .long("end")
+                        .help("Drop epochs after this time")
+                        .value_parser(filter::parse_epoch))
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .help("Decimate to this interval in seconds (GPS time aligned), lock losses in between are flagged on the next kept epoch")
+                        .value_parser(filter::parse_interval))
+                .arg(
+                    Arg::new("expected-interval")
+                        .long("expected-interval")
+                        .help("Receiver output interval in seconds for outage detection (default: learned per signal); a signal back after an outage its lock time can't cover is flagged as lock loss")
+                        .value_parser(filter::parse_interval))
+                .arg(
+                    Arg::new("quality")
+                        .long("quality")
+                        .help("Also write raw MSM lock time indicator, minimum lock time, half-cycle flag and extended satellite info per epoch/satellite/signal to this CSV file"))
+                .arg(
+                    Arg::new("nmea")
+                        .long("nmea")
+                        .help("Also write NMEA sentences interleaved with the RTCM frames (checksum-valid only) to this file"))
+                .arg(
+                    Arg::new("checkpoint")
+                        .long("checkpoint")
+                        .help("Decoder state file: resumed from if it exists and saved during/after conversion, so restarts keep lock history and week numbers"))
+                .arg(
+                    Arg::new("report")
+                        .long("report")
+                        .help("Print a decoding statistics report after conversion")
+                        .value_parser(["text", "json"]))
+                .arg(
+                    Arg::new("file_path")
+                        .help("Log file input(s), merged in order into one continuous RINEX (or serial device path with --serial)")
+                        .required(true)
+                        .num_args(1..)
+                        .index(1),
+                )
+            )
+        .subcommand(
+            Command::new("dump")
+                .about("lists each frame with offset, length, message number, crc status and decoded fields")
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["text", "json", "ndjson"])
+                        .default_value("text"))
+                .arg(
+                    Arg::new("filter")
+                        .long("filter")
+                        .help("Only list these message numbers, e.g. 1077,1097")
+                        .value_parser(value_parser!(u16))
+                        .value_delimiter(','))
                 .arg(
                     Arg::new("file_path")
                         .help("Log file input")
@@ -30,84 +247,456 @@ fn command() -> clap::Command {
                         .index(1),
                 )
             )
+        .subcommand(
+            Command::new("info")
+                .about("summarises a log: time span, signals, message rates, station id, position and receiver/antenna descriptors")
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["text", "json"])
+                        .default_value("text"))
+                .arg(
+                    Arg::new("file_path")
+                        .help("Log file input")
+                        .required(true)
+                        .index(1),
+                )
+            )
+        .subcommand(
+            Command::new("replay")
+                .about("serves a log over TCP (or as an NTRIP caster) in real time, paced by MSM epoch times")
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .help("Address to listen on")
+                        .default_value("0.0.0.0:2101"))
+                .arg(
+                    Arg::new("mountpoint")
+                        .long("mountpoint")
+                        .help("Act as an NTRIP caster serving the log on this mountpoint (no authentication) instead of raw TCP"))
+                .arg(
+                    Arg::new("speed")
+                        .long("speed")
+                        .help("Playback speed multiplier, e.g. 10 for ten times real time")
+                        .value_parser(value_parser!(f64))
+                        .default_value("1"))
+                .arg(
+                    Arg::new("loop")
+                        .long("loop")
+                        .help("Start over at the end of the log")
+                        .value_parser(value_parser!(bool))
+                        .default_value("false"))
+                .arg(
+                    Arg::new("file_path")
+                        .help("Log file input")
+                        .required(true)
+                        .index(1),
+                )
+            )
+        .subcommand(
+            Command::new("filter")
+                .about("copies selected frames of a log byte for byte (no re-encoding), optionally split by GPS hour or message number")
+                .arg(
+                    Arg::new("messages")
+                        .long("messages")
+                        .help("Only keep these message numbers, e.g. 1005,1033,1077")
+                        .value_parser(value_parser!(u16))
+                        .value_delimiter(','))
+                .arg(
+                    Arg::new("stations")
+                        .long("stations")
+                        .help("Only keep messages from these reference station ids (messages without one, e.g. ephemeris, are kept)")
+                        .value_parser(value_parser!(u16))
+                        .value_delimiter(','))
+                .arg(
+                    Arg::new("start")
+                        .long("start")
+                        .help("Drop frames before this time, e.g. \"2024-12-30T02:00:00 GPST\"; non MSM frames go with the MSM epoch before them")
+                        .value_parser(filter::parse_epoch))
+                .arg(
+                    Arg::new("end")
+                        .long("end")
+                        .help("Drop frames after this time")
+                        .value_parser(filter::parse_epoch))
+                .arg(
+                    Arg::new("split")
+                        .long("split")
+                        .help("One output file per GPS hour or per message number, named <output>_<tag>")
+                        .value_parser(["none", "hour", "message"])
+                        .default_value("none"))
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .help("Output path (default: <input>.filtered.rtcm)"))
+                .arg(
+                    Arg::new("file_path")
+                        .help("Log file input")
+                        .required(true)
+                        .index(1),
+                )
+            )
+        .subcommand(
+            Command::new("stats")
+                .about("decodes input files and reports message, signal and error statistics without writing RINEX")
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["text", "json"])
+                        .default_value("text"))
+                .arg(
+                    Arg::new("file_path")
+                        .help("Log file input(s)")
+                        .required(true)
+                        .num_args(1..)
+                        .index(1),
+                )
+            )
 }
 
 
-pub fn convert_file(file_path:&String, use_rtklib_lli:bool) {
-    
-    println!("converting rtcm file: {}", file_path);
+// one decoder for all files so lock history carries across rotated logs
+fn load_files(rtcm_decoder:&mut RtcmDecoder, file_paths:&Vec<String>) {
 
-    let mut rtcm_decoder = RtcmDecoder::new(use_rtklib_lli);
+    for file_path in file_paths.iter() {
+        let rtcm_file_path = Path::new(file_path);
 
-    let rtcm_file_path = Path::new(file_path);
+        if let Err(e) = rtcm_decoder.load_file(rtcm_file_path) {
+            error!(file = file_path.as_str(); "conversion failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-    rtcm_decoder.load_file(rtcm_file_path);
+// resumes from the checkpoint if one was saved, otherwise starts a new decoder
+// settings come from the command line either way, the caller applies the rest after this
+fn new_decoder(use_rtklib_lli:bool, checkpoint:Option<&String>) -> RtcmDecoder {
+
+    if let Some(checkpoint) = checkpoint {
+        if Path::new(checkpoint).exists() {
+            match RtcmDecoder::restore_state(Path::new(checkpoint)) {
+                Ok(mut rtcm_decoder) => {
+                    rtcm_decoder.set_use_rtklib_method(use_rtklib_lli);
+                    return rtcm_decoder;
+                }
+                Err(e) => {
+                    error!(file = checkpoint.as_str(); "unable to restore checkpoint: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
 
-    let mut observed_signals = rtcm_decoder.extract_observed_signals();
+    RtcmDecoder::new(use_rtklib_lli)
+}
 
-    // implement optional compressed rnx ?
-    //let crinex:Option<Crinex> = Some(Crinex {version : Version {major: 3, minor: 0}, prog: "rtcm2rnx".to_string(), date: Epoch::now().unwrap()});  
+fn save_checkpoint(rtcm_decoder:&RtcmDecoder, checkpoint:Option<&String>) {
+    if let Some(checkpoint) = checkpoint {
+        if let Err(e) = rtcm_decoder.save_state(Path::new(checkpoint)) {
+            warn!(file = checkpoint.as_str(); "unable to save checkpoint: {}", e);
+        }
+    }
+}
 
-    let scaling:HashMap<(Constellation, Observable), u16> = HashMap::new();
+// writes (or with append, adds to) the signal quality CSV
+fn write_quality(quality:&QualityData, path:Option<&String>, append:bool) {
 
-    let mut codes:HashMap<Constellation, Vec<Observable>> = HashMap::new();
+    if path.is_none() {
+        return;
+    }
+    let path = path.unwrap();
 
-    let mut gps_codes:Vec<String> = observed_signals.extract_if(|c|c.0 == Constellation::GPS).map(|c: (Constellation, String)|c.1).collect();
-    gps_codes.sort();
+    let header = !append || !Path::new(path).exists();
+    let file = OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(path);
 
+    let result = file.and_then(|file| rtcmlib::export::write_quality_csv(quality, BufWriter::new(file), header));
+    if let Err(e) = result {
+        error!(file = path.as_str(); "unable to write signal quality: {}", e);
+    }
+}
 
-    let mut gps_observables:Vec<Observable> = Vec::new();
+// writes (or with append, adds to) the NMEA side file
+fn write_nmea(sentences:Vec<String>, path:Option<&String>, append:bool) {
 
-    for code in gps_codes.iter() {
-        gps_observables.push(Observable::PseudoRange(format!("C{}", code)));
-        gps_observables.push(Observable::Phase(format!("L{}", code)));
-        gps_observables.push(Observable::Doppler(format!("D{}", code)));
-        gps_observables.push(Observable::SSI(format!("S{}", code)));
+    if path.is_none() || (append && sentences.is_empty()) {
+        return;
     }
+    let path = path.unwrap();
 
-    codes.insert(Constellation::GPS, gps_observables);
+    let file = OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(path);
 
-    let mut galileo_codes:Vec<String> = observed_signals.extract_if(|c|c.0 == Constellation::Galileo).map(|c: (Constellation, String)|c.1).collect();
-    galileo_codes.sort();
+    let result = file.and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        for sentence in sentences.iter() {
+            write!(writer, "{}\r\n", sentence)?;
+        }
+        writer.flush()
+    });
+    if let Err(e) = result {
+        error!(file = path.as_str(); "unable to write NMEA sentences: {}", e);
+    }
+}
 
-    let mut galileo_observables:Vec<Observable> = Vec::new();
+/// What a conversion writes: observation files and the optional side outputs.
+pub struct ConvertOptions {
+    /// output file (or directory with IGS names), - for stdout; next to the input when not set
+    pub output:Option<String>,
+    /// one output per GPS time aligned session of this length
+    pub split:Option<rinex::prelude::Duration>,
+    pub output_options:OutputOptions,
+    /// statistics report format (text or json) printed at the end
+    pub report:Option<String>,
+    pub checkpoint:Option<String>,
+    /// signal quality CSV
+    pub quality:Option<String>,
+    /// NMEA side file
+    pub nmea:Option<String>,
+    /// outputs get a .rtklib suffix so diagnostics runs don't overwrite regular ones
+    pub use_rtklib_lli:bool
+}
+
+pub fn convert_files(mut rtcm_decoder:RtcmDecoder, file_paths:&Vec<String>, options:&ConvertOptions) {
+
+    load_files(&mut rtcm_decoder, file_paths);
+    write_nmea(rtcm_decoder.take_nmea_sentences(), options.nmea.as_ref(), false);
+
+    // outputs are named after the first input
+    let input_stem;
+
+    if options.use_rtklib_lli {
+        input_stem = format!("{}.rtklib", file_paths[0]);
+    }
+    else {
+        input_stem = file_paths[0].clone();
+    }
+
+    write_sessions(&rtcm_decoder.get_rtcm_data(), &input_stem, options, &mut ClockJumpTracker::new());
+    write_quality(rtcm_decoder.get_signal_quality(), options.quality.as_ref(), false);
+
+    if options.report.is_some() {
+        report::print_report(&rtcm_decoder, options.report.as_ref().unwrap());
+    }
+
+    // the next run writes its own files, only carry lock history and week numbers over
+    rtcm_decoder.clear();
+    save_checkpoint(&rtcm_decoder, options.checkpoint.as_ref());
+}
+
+pub fn convert_serial(mut rtcm_decoder:RtcmDecoder, device_path:&String, config:&SerialConfig, duration:Option<Duration>, options:&ConvertOptions) {
 
-    for code in galileo_codes.iter() {
+    // device paths aren't writable locations, default to the device name in the working directory
+    let input_stem = Path::new(device_path).file_name().unwrap().to_str().unwrap().to_string();
 
-        galileo_observables.push(Observable::PseudoRange(format!("C{}", code)));
-        galileo_observables.push(Observable::Phase(format!("L{}", code)));
-        galileo_observables.push(Observable::Doppler(format!("D{}", code)));
-        galileo_observables.push(Observable::SSI(format!("S{}", code)));
+    let mut last_checkpoint = Instant::now();
+    let mut clock_jumps = ClockJumpTracker::new();
 
+    let result = rtcm_decoder.load_serial_with(device_path, config, duration, |rtcm_decoder| {
+
+        write_nmea(rtcm_decoder.take_nmea_sentences(), options.nmea.as_ref(), true);
+
+        // write out sessions as soon as the stream moves past their end
+        if options.split.is_some() && rtcm_decoder.get_last_epoch().is_some() {
+            let current_session = rtcmlib::session_start(rtcm_decoder.get_last_epoch().unwrap(), options.split.unwrap());
+            if rtcm_decoder.get_first_epoch().unwrap() < current_session {
+                let completed = rtcm_decoder.take_data_before(current_session);
+                write_sessions(&completed, &input_stem, options, &mut clock_jumps);
+                write_quality(&rtcm_decoder.take_signal_quality_before(current_session), options.quality.as_ref(), true);
+                save_checkpoint(rtcm_decoder, options.checkpoint.as_ref());
+                last_checkpoint = Instant::now();
+            }
+        }
+
+        if options.checkpoint.is_some() && last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+            save_checkpoint(rtcm_decoder, options.checkpoint.as_ref());
+            last_checkpoint = Instant::now();
+        }
+    });
+
+    if let Err(e) = result {
+        error!(file = device_path.as_str(); "conversion failed: {}", e);
+    }
+
+    // the open session is written as is and kept in the checkpoint, a restart rewrites it with the rest of the session
+    // its quality rows are appended once and not checkpointed, the CSV would repeat them after a restart
+    write_sessions(&rtcm_decoder.get_rtcm_data(), &input_stem, options, &mut clock_jumps);
+    write_quality(&rtcm_decoder.take_signal_quality(), options.quality.as_ref(), true);
+    save_checkpoint(&rtcm_decoder, options.checkpoint.as_ref());
+
+    if options.report.is_some() {
+        report::print_report(&rtcm_decoder, options.report.as_ref().unwrap());
     }
+}
 
-    codes.insert(Constellation::Galileo, galileo_observables);
-        
-    let first_epoch = rtcm_decoder.get_first_epoch();
-    let last_epoch = rtcm_decoder.get_last_epoch();
+// writes decoded data as one output file, or one file per session when splitting
+fn write_sessions(rtcm_data:&RtcmData, input_stem:&String, options:&ConvertOptions, clock_jumps:&mut ClockJumpTracker) {
 
-    let header_fields = HeaderFields {crinex : None, time_of_first_obs: first_epoch, time_of_last_obs: last_epoch, codes:codes, clock_offset_applied: false, scaling: scaling};
+    if rtcm_data.is_empty() {
+        warn!("no observations decoded, nothing to write");
+        return;
+    }
+
+    let output = options.output.as_ref();
+
+    match options.split {
+        Some(period) => {
+            for (session, session_data) in rtcmlib::split_sessions(rtcm_data, period) {
+                let rnx_path = output_path(&session_data, input_stem, output, Some((session, period)), &options.output_options);
+                write_output(&session_data, &rnx_path, &options.output_options, clock_jumps);
+            }
+        }
+        None => {
+            let rnx_path = output_path(rtcm_data, input_stem, output, None, &options.output_options);
+            write_output(rtcm_data, &rnx_path, &options.output_options, clock_jumps);
+        }
+    }
+}
+
+// IGS long name (in --output or the input directory), --output as given, or <input>.rnx
+// split sessions (start and length) get their start time appended so files don't overwrite each other
+fn output_path(rtcm_data:&RtcmData, input_stem:&String, output:Option<&String>, session:Option<(Epoch, rinex::prelude::Duration)>, options:&OutputOptions) -> String {
+
+    if options.igs_name {
+        let directory = match output {
+            Some(output) => PathBuf::from(output),
+            None => Path::new(input_stem).parent().map(|p| p.to_path_buf()).unwrap_or_default()
+        };
+
+        let file_name = output::igs_file_name(options, rtcm_data, session);
+
+        return directory.join(file_name).to_str().unwrap().to_string();
+    }
+
+    if output.is_some() && output.unwrap() == "-" {
+        return STDOUT_PATH.to_string();
+    }
+
+    match (output, session.map(|(start, _)| start)) {
+        (Some(output), None) => output.clone(),
+        (Some(output), Some(session)) => format!("{}_{}.{}", output.trim_end_matches(".rnx").trim_end_matches(".crx").trim_end_matches(&format!(".{}", options.extension())), output::session_tag(session), options.extension()),
+        (None, None) => format!("{}.{}", input_stem, options.extension()),
+        (None, Some(session)) => format!("{}_{}.{}", input_stem, output::session_tag(session), options.extension())
+    }
+}
+
+// header_records: extra records (e.g. comments) inserted before END OF HEADER
+pub fn write_rinex(rtcm_data:&RtcmData, rnx_path:&String, options:&OutputOptions, header_records:&Vec<String>) {
+
+    let mut crinex:Option<Crinex> = None;
+    if options.compression.is_crinex() {
+        crinex = Some(Crinex {version : Version {major: 3, minor: 0}, prog: "rtcm2rnx".to_string(), date: Epoch::now().unwrap()});
+    }
+
+    let scaling:HashMap<(Constellation, Observable), u16> = HashMap::new();
+
+    // header obs types follow what was stored, i.e. after any decoder filters
+    let codes:HashMap<Constellation, Vec<Observable>> = rtcmlib::extract_observables(rtcm_data);
+
+    let first_epoch = rtcm_data.keys().next().map(|k| k.0);
+    let last_epoch = rtcm_data.keys().next_back().map(|k| k.0);
+
+    let header_fields = HeaderFields {crinex : crinex, time_of_first_obs: first_epoch, time_of_last_obs: last_epoch, codes:codes, clock_offset_applied: false, scaling: scaling};
 
     let header : Header = Header::basic_obs();
     let header_obs = header.with_version(Version::new(3, 0)).with_observation_fields(header_fields);
     
-    let record = rinex::record::Record::ObsRecord(rtcm_decoder.get_rtcm_data());
+    let record = rinex::record::Record::ObsRecord(rtcm_data.clone());
     let rinex = Rinex::new(header_obs, record);
 
-
-    let rnx_path;
     
-    if use_rtklib_lli {
-        rnx_path = format!("{}.rtklib.rnx", file_path);
+    rinex.to_file(rnx_path).expect("unable to write file");
+
+    let mut records = header_records.clone();
+    if options.phase_shift {
+        records.extend(rtcmlib::phase::phase_shift_records(rtcm_data));
     }
-    else {
-        rnx_path = format!("{}.rnx", file_path);
+
+    if !records.is_empty() {
+        if rnx_path == STDOUT_PATH {
+            warn!("COMMENT records aren't written to stdout");
+        }
+        else {
+            if let Err(e) = output::insert_header_records(rnx_path, &records) {
+                error!(file = rnx_path.as_str(); "unable to write header records: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+pub fn write_table(rtcm_data:&RtcmData, path:&String, format:OutputFormat) -> io::Result<()> {
+
+    let writer = BufWriter::new(File::create(path)?);
+
+    match format {
+        OutputFormat::Csv => rtcmlib::export::write_csv(rtcm_data, writer),
+        OutputFormat::Ndjson => rtcmlib::export::write_ndjson(rtcm_data, writer),
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => rtcmlib::export::write_parquet(rtcm_data, writer),
+        #[cfg(not(feature = "parquet"))]
+        OutputFormat::Parquet => Err(io::Error::new(io::ErrorKind::Unsupported, "parquet output requires building with --features parquet")),
+        OutputFormat::Rinex => unreachable!()
+    }
+}
+
+// writes one output file in the selected format, then gzips it when requested
+// clock_jumps carries jump detection and repair over from the previous output of the run
+fn write_output(rtcm_data:&RtcmData, path:&String, options:&OutputOptions, clock_jumps:&mut ClockJumpTracker) {
+
+    let mut rtcm_data = Cow::Borrowed(rtcm_data);
+    let mut header_records:Vec<String> = Vec::new();
+
+    if options.clock_jumps != ClockJumpMode::Off {
+        let jumps = clock_jumps.detect(&rtcm_data);
+        for jump in jumps.iter() {
+            warn!(file = path.as_str(), epoch = jump.epoch.to_string().as_str(); "receiver clock jump of {} ms", jump.milliseconds);
+        }
+
+        let repair = options.clock_jumps == ClockJumpMode::Repair;
+        if repair {
+            clock_jumps.repair(rtcm_data.to_mut(), &jumps);
+        }
+        header_records.extend(rtcmlib::clock::comment_records(&jumps, repair));
     }
-    
-    rinex.to_file(&rnx_path).expect("unable to write file");
 
-    println!("complete! RINEX file output: {}", rnx_path);
+    // after clock jump repair, a code jump would otherwise show up as a Melbourne-Wubbena slip on every satellite
+    if options.slip_detection {
+        let slips = rtcmlib::slip::SlipDetector::default().detect(rtcm_data.to_mut());
 
+        let mut by_source:BTreeMap<String, u64> = BTreeMap::new();
+        for slip in slips.iter() {
+            debug!(file = path.as_str(), epoch = slip.epoch.to_string().as_str(), sv = slip.sv.to_string().as_str(), code = slip.code.as_str(), source = format!("{:?}", slip.source).as_str(); "cycle slip missed by receiver lock indicator");
+            *by_source.entry(format!("{:?}", slip.source)).or_insert(0) += 1;
+        }
+
+        info!(file = path.as_str(); "cycle slips flagged from data: {} {:?}", slips.len(), by_source);
+    }
+
+    match options.format {
+        OutputFormat::Rinex => write_rinex(&rtcm_data, path, options, &header_records),
+        format => {
+            if let Err(e) = write_table(&rtcm_data, path, format) {
+                error!(file = path.as_str(); "export failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut output_path = path.clone();
+    if options.compression.is_gzip() {
+        if path == STDOUT_PATH {
+            warn!("gzip isn't applied when writing to stdout");
+        }
+        else {
+            output_path = match output::gzip_file(path) {
+                Ok(gz_path) => gz_path,
+                Err(e) => {
+                    error!(file = path.as_str(); "unable to compress file: {}", e);
+                    std::process::exit(1);
+                }
+            };
+        }
+    }
+
+    info!(file = output_path.as_str(); "complete! {:?} file output", options.format);
 }
 
 
@@ -117,12 +706,161 @@ fn main() {
 
     let matches = command.get_matches();
 
+    let log_format = match matches.get_one::<String>("log-format").unwrap().as_str() {
+        "json" => LogFormat::Json,
+        _ => LogFormat::Text
+    };
+    logging::init(log_format, logging::level_filter(matches.get_count("verbose"), matches.get_count("quiet")));
+
     match matches.subcommand() {
 
         Some(("convert", client_matches)) => {
-            let file_path = client_matches.get_one::<String>("file_path").unwrap();
+            let file_paths:Vec<String> = client_matches.get_many::<String>("file_path").unwrap().cloned().collect();
             let use_rtklib_lli= client_matches.get_one::<bool>("use-rtklib-lli").unwrap();
-            convert_file(file_path, *use_rtklib_lli);
+            let error_policy = match client_matches.get_one::<String>("on-error").unwrap().as_str() {
+                "fail" => ErrorPolicy::FailFast,
+                _ => ErrorPolicy::Skip
+            };
+            let checkpoint = client_matches.get_one::<String>("checkpoint");
+            let quality = client_matches.get_one::<String>("quality");
+            let nmea = client_matches.get_one::<String>("nmea");
+
+            let mut rtcm_decoder = new_decoder(*use_rtklib_lli, checkpoint);
+            rtcm_decoder.set_error_policy(error_policy);
+            rtcm_decoder.set_phase_alignment(*client_matches.get_one::<bool>("phase-align").unwrap());
+            rtcm_decoder.set_collect_quality(quality.is_some());
+            rtcm_decoder.set_extract_nmea(nmea.is_some());
+            rtcm_decoder.set_snr_mapping(SnrMapping::from_str(client_matches.get_one::<String>("snr-table").unwrap()).unwrap());
+            rtcm_decoder.set_filter(SignalFilter {
+                systems: client_matches.get_many::<Constellation>("systems").map(|s| s.copied().collect()).unwrap_or_default(),
+                exclude_sv: client_matches.get_many::<rinex::prelude::SV>("exclude-sv").map(|s| s.copied().collect()).unwrap_or_default(),
+                signals: client_matches.get_many::<String>("signals").map(|s| s.cloned().collect()).unwrap_or_default(),
+                observables: client_matches.get_many::<char>("observables").map(|s| s.copied().collect()).unwrap_or_default()
+            });
+            rtcm_decoder.set_expected_interval(client_matches.get_one::<rinex::prelude::Duration>("expected-interval").copied());
+            rtcm_decoder.set_time_window(TimeWindow {
+                start: client_matches.get_one::<Epoch>("start").copied(),
+                end: client_matches.get_one::<Epoch>("end").copied(),
+                interval: client_matches.get_one::<rinex::prelude::Duration>("interval").copied()
+            });
+
+            let options = ConvertOptions {
+                output: client_matches.get_one::<String>("output").cloned(),
+                split: client_matches.get_one::<rinex::prelude::Duration>("split").copied(),
+                output_options: OutputOptions {
+                    format: OutputFormat::from_str(client_matches.get_one::<String>("format").unwrap()).unwrap(),
+                    compression: OutputCompression::from_str(client_matches.get_one::<String>("compress").unwrap()).unwrap(),
+                    igs_name: *client_matches.get_one::<bool>("igs-name").unwrap(),
+                    phase_shift: *client_matches.get_one::<bool>("phase-align").unwrap(),
+                    slip_detection: *client_matches.get_one::<bool>("slip-detect").unwrap(),
+                    clock_jumps: ClockJumpMode::from_str(client_matches.get_one::<String>("clock-jumps").unwrap()).unwrap(),
+                    marker: client_matches.get_one::<String>("marker").unwrap().clone(),
+                    country: client_matches.get_one::<String>("country").unwrap().clone()
+                },
+                report: client_matches.get_one::<String>("report").cloned(),
+                checkpoint: checkpoint.cloned(),
+                quality: quality.cloned(),
+                nmea: nmea.cloned(),
+                use_rtklib_lli: *use_rtklib_lli
+            };
+
+            // shifted phases need their SYS / PHASE SHIFT records, which only RINEX files written to disk get
+            let to_stdout = options.output.as_deref() == Some("-") && !options.output_options.igs_name;
+            if options.output_options.phase_shift && (to_stdout || options.output_options.format != OutputFormat::Rinex) {
+                error!("--phase-align needs RINEX output to a file");
+                std::process::exit(1);
+            }
+
+            // the repair offset lives in the clock jump tracker, which the checkpoint doesn't keep
+            if options.checkpoint.is_some() && options.output_options.clock_jumps == ClockJumpMode::Repair {
+                error!("--clock-jumps repair can't resume from a --checkpoint");
+                std::process::exit(1);
+            }
+
+            if *client_matches.get_one::<bool>("serial").unwrap() {
+                let config = SerialConfig {
+                    baud_rate: *client_matches.get_one::<u32>("baud").unwrap(),
+                    parity: serial::parse_parity(client_matches.get_one::<String>("parity").unwrap()).unwrap(),
+                    flow_control: serial::parse_flow_control(client_matches.get_one::<String>("flow-control").unwrap()).unwrap(),
+                    ..SerialConfig::default()
+                };
+                let duration = client_matches.get_one::<u64>("duration").map(|d| Duration::from_secs(*d));
+                convert_serial(rtcm_decoder, &file_paths[0], &config, duration, &options);
+            }
+            else {
+                convert_files(rtcm_decoder, &file_paths, &options);
+            }
+        }
+
+        Some(("dump", client_matches)) => {
+            let file_path = client_matches.get_one::<String>("file_path").unwrap();
+            let format = dump::DumpFormat::from_str(client_matches.get_one::<String>("format").unwrap()).unwrap();
+            let filter:Vec<u16> = client_matches.get_many::<u16>("filter").map(|f| f.copied().collect()).unwrap_or_default();
+
+            if let Err(e) = dump::dump_file(Path::new(file_path), format, &filter) {
+                error!(file = file_path.as_str(); "dump failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+
+        Some(("info", client_matches)) => {
+            let file_path = client_matches.get_one::<String>("file_path").unwrap().clone();
+            let format = client_matches.get_one::<String>("format").unwrap();
+
+            match info::LogInfo::scan_file(Path::new(&file_path)) {
+                Ok(log_info) => info::print_info(&log_info, format),
+                Err(e) => {
+                    error!(file = file_path.as_str(); "unable to read log: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Some(("replay", client_matches)) => {
+            let file_path = client_matches.get_one::<String>("file_path").unwrap();
+            let options = replay::ReplayOptions {
+                listen: client_matches.get_one::<String>("listen").unwrap().clone(),
+                mountpoint: client_matches.get_one::<String>("mountpoint").cloned(),
+                speed: *client_matches.get_one::<f64>("speed").unwrap(),
+                looping: *client_matches.get_one::<bool>("loop").unwrap()
+            };
+
+            if !(options.speed > 0.0) {
+                error!("--speed has to be greater than 0");
+                std::process::exit(1);
+            }
+
+            if let Err(e) = replay::replay(Path::new(file_path), &options) {
+                error!(file = file_path.as_str(); "replay failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+
+        Some(("filter", client_matches)) => {
+            let file_path = client_matches.get_one::<String>("file_path").unwrap();
+            let output = client_matches.get_one::<String>("output").cloned().unwrap_or(format!("{}.filtered.rtcm", file_path));
+            let options = split::SplitOptions {
+                messages: client_matches.get_many::<u16>("messages").map(|m| m.copied().collect()).unwrap_or_default(),
+                stations: client_matches.get_many::<u16>("stations").map(|s| s.copied().collect()).unwrap_or_default(),
+                start: client_matches.get_one::<Epoch>("start").copied(),
+                end: client_matches.get_one::<Epoch>("end").copied(),
+                split: split::SplitMode::from_str(client_matches.get_one::<String>("split").unwrap()).unwrap()
+            };
+
+            if let Err(e) = split::split_file(Path::new(file_path), &output, &options) {
+                error!(file = file_path.as_str(); "filter failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+
+        Some(("stats", client_matches)) => {
+            let file_paths:Vec<String> = client_matches.get_many::<String>("file_path").unwrap().cloned().collect();
+            let format = client_matches.get_one::<String>("format").unwrap();
+
+            let mut rtcm_decoder = RtcmDecoder::new(false);
+            load_files(&mut rtcm_decoder, &file_paths);
+
+            report::print_report(&rtcm_decoder, format);
         }
 
         _ => {
@@ -133,4 +871,59 @@ fn main() {
 
 }
 
+#[cfg(test)]
+mod tests {
+
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    const DEBUG_RTCM:&str = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm");
+
+    fn rtcm_data() -> RtcmData {
+        let mut rtcm_decoder = RtcmDecoder::new(false);
+        rtcm_decoder.load_file(Path::new(DEBUG_RTCM)).unwrap();
+        rtcm_decoder.get_rtcm_data()
+    }
 
+    #[test]
+    fn crinex_output_is_hatanaka_compressed() {
+        let rtcm_data = rtcm_data();
+        let rnx_path = std::env::temp_dir().join("rtcm2rnx_plain.rnx").to_str().unwrap().to_string();
+        let crx_path = std::env::temp_dir().join("rtcm2rnx_compressed.crx").to_str().unwrap().to_string();
+
+        write_rinex(&rtcm_data, &rnx_path, &OutputOptions::default(), &Vec::new());
+        write_rinex(&rtcm_data, &crx_path, &OutputOptions {compression: OutputCompression::Crinex, ..OutputOptions::default()}, &Vec::new());
+
+        let plain = std::fs::read_to_string(&rnx_path).unwrap();
+        let compressed = std::fs::read_to_string(&crx_path).unwrap();
+        assert!(!plain.contains("CRINEX"));
+        assert!(compressed.lines().next().unwrap().ends_with("CRINEX VERS   / TYPE"));
+        assert!(compressed.lines().nth(1).unwrap().ends_with("CRINEX PROG / DATE"));
+
+        // observations are written as differences, not F14.3 values
+        let (_, (_, satellites)) = rtcm_data.iter().next().unwrap();
+        let code = satellites.values().flat_map(|observations| observations.iter())
+            .find(|(observable, _)| matches!(observable, Observable::PseudoRange(_)))
+            .unwrap().1.obs;
+        let formatted = format!("{:14.3}", code);
+        assert!(plain.contains(&formatted));
+        assert!(!compressed.contains(&formatted));
+    }
+
+    #[test]
+    fn gzip_output_replaces_rinex() {
+        let rnx_path = std::env::temp_dir().join("rtcm2rnx_output.rnx").to_str().unwrap().to_string();
+        let options = OutputOptions {compression: OutputCompression::Gzip, ..OutputOptions::default()};
+
+        write_output(&rtcm_data(), &rnx_path, &options, &mut ClockJumpTracker::new());
+
+        assert!(!Path::new(&rnx_path).exists());
+        let mut content = String::new();
+        GzDecoder::new(File::open(format!("{}.gz", rnx_path)).unwrap()).read_to_string(&mut content).unwrap();
+        assert!(content.lines().next().unwrap().contains("OBSERVATION DATA"));
+        assert!(content.contains("END OF HEADER"));
+    }
+}
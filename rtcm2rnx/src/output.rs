@@ -0,0 +1,353 @@
+// output file naming and compression
+
+use std::{collections::HashMap, fs::{self, File}, io::{self, BufReader, BufWriter}};
+
+use flate2::{write::GzEncoder, Compression};
+use rinex::prelude::{Constellation, Duration, Epoch, TimeScale};
+use rtcmlib::RtcmData;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputCompression {
+    None,
+    Gzip,
+    Crinex,
+    CrinexGzip
+}
+
+impl OutputCompression {
+    pub fn from_str(compression:&str) -> Option<OutputCompression> {
+        match compression {
+            "none" => Some(OutputCompression::None),
+            "gz" => Some(OutputCompression::Gzip),
+            "crx" => Some(OutputCompression::Crinex),
+            "crx.gz" => Some(OutputCompression::CrinexGzip),
+            _ => None
+        }
+    }
+
+    pub fn is_crinex(&self) -> bool {
+        matches!(self, OutputCompression::Crinex | OutputCompression::CrinexGzip)
+    }
+
+    pub fn is_gzip(&self) -> bool {
+        matches!(self, OutputCompression::Gzip | OutputCompression::CrinexGzip)
+    }
+
+    // RINEX 3 long name extensions (.rnx / .crx, .gz appended by gzip_file)
+    pub fn extension(&self) -> &str {
+        if self.is_crinex() { "crx" } else { "rnx" }
+    }
+}
+
+// RINEX observation file, or long format tables (epoch, sv, observable, value, lli, snr) for dataframe tools
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Rinex,
+    Csv,
+    Ndjson,
+    Parquet
+}
+
+impl OutputFormat {
+    pub fn from_str(format:&str) -> Option<OutputFormat> {
+        match format {
+            "rinex" => Some(OutputFormat::Rinex),
+            "csv" => Some(OutputFormat::Csv),
+            "ndjson" => Some(OutputFormat::Ndjson),
+            "parquet" => Some(OutputFormat::Parquet),
+            _ => None
+        }
+    }
+}
+
+// receiver clock jump handling at write time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockJumpMode {
+    Off,
+    Detect,
+    Repair
+}
+
+impl ClockJumpMode {
+    pub fn from_str(mode:&str) -> Option<ClockJumpMode> {
+        match mode {
+            "off" => Some(ClockJumpMode::Off),
+            "detect" => Some(ClockJumpMode::Detect),
+            "repair" => Some(ClockJumpMode::Repair),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OutputOptions {
+    pub format:OutputFormat,
+    pub compression:OutputCompression,
+    pub igs_name:bool,
+    /// phase was aligned by the decoder, write SYS / PHASE SHIFT records
+    pub phase_shift:bool,
+    pub clock_jumps:ClockJumpMode,
+    /// data driven cycle slip detection before writing
+    pub slip_detection:bool,
+    pub marker:String,
+    pub country:String
+}
+
+impl OutputOptions {
+    // table formats keep their own extension, CRINEX only applies to RINEX output
+    pub fn extension(&self) -> &str {
+        match self.format {
+            OutputFormat::Rinex => self.compression.extension(),
+            OutputFormat::Csv => "csv",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Parquet => "parquet"
+        }
+    }
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {format:OutputFormat::Rinex, compression:OutputCompression::None, igs_name:false, phase_shift:false, clock_jumps:ClockJumpMode::Off, slip_detection:false, marker:"XXXX".to_string(), country:"XXX".to_string()}
+    }
+}
+
+/// Most common spacing between consecutive epochs.
+pub fn sampling_interval(rtcm_data:&RtcmData) -> Option<Duration> {
+
+    let mut counts:HashMap<i64, u32> = HashMap::new();
+
+    let epochs:Vec<Epoch> = rtcm_data.keys().map(|k| k.0).collect();
+    for pair in epochs.windows(2) {
+        let dt_ms = ((pair[1] - pair[0]).to_seconds() * 1000.0).round() as i64;
+        if dt_ms > 0 {
+            *counts.entry(dt_ms).or_insert(0) += 1;
+        }
+    }
+
+    counts.into_iter()
+        .max_by_key(|(dt_ms, count)| (*count, -dt_ms))
+        .map(|(dt_ms, _)| Duration::from_milliseconds(dt_ms as f64))
+}
+
+// period field of the long filename, e.g. 15M, 01H, 01D
+fn format_period(period:Duration) -> String {
+    let seconds = period.to_seconds().ceil() as u64;
+    if seconds >= 86400 {
+        format!("{:02}D", (seconds + 86399) / 86400)
+    }
+    else if seconds >= 3600 {
+        format!("{:02}H", (seconds + 3599) / 3600)
+    }
+    else {
+        format!("{:02}M", ((seconds + 59) / 60).max(1))
+    }
+}
+
+// sampling field of the long filename, e.g. 05Z (5Hz), 01S, 30S
+fn format_interval(interval:Duration) -> String {
+    let seconds = interval.to_seconds();
+    if seconds < 1.0 {
+        format!("{:02}Z", (1.0 / seconds).round() as u64)
+    }
+    else if seconds < 60.0 {
+        format!("{:02}S", seconds.round() as u64)
+    }
+    else if seconds < 3600.0 {
+        format!("{:02}M", (seconds / 60.0).round() as u64)
+    }
+    else {
+        format!("{:02}H", (seconds / 3600.0).round() as u64)
+    }
+}
+
+fn day_of_year(year:i32, month:u8, day:u8) -> u16 {
+    const CUMULATIVE_DAYS:[u16;12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let mut doy = CUMULATIVE_DAYS[(month - 1) as usize] + day as u16;
+    if leap && month > 2 {
+        doy += 1;
+    }
+    doy
+}
+
+/// Session length from the command line, e.g. 15m, 1h, 24h, 1d.
+pub fn parse_period(period:&str) -> Result<Duration, String> {
+
+    let period = period.trim().to_lowercase();
+    let (value, unit) = period.split_at(period.len().saturating_sub(1));
+
+    let value:f64 = value.parse().map_err(|_| format!("invalid session length: {}", period))?;
+
+    let seconds = match unit {
+        "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        "d" => value * 86400.0,
+        _ => return Err(format!("invalid session length unit (expected s, m, h or d): {}", period))
+    };
+
+    if seconds <= 0.0 {
+        return Err(format!("session length must be positive: {}", period));
+    }
+
+    Ok(Duration::from_seconds(seconds))
+}
+
+// YYYYDDDHHMM start time tag (GPS time) used in long filenames and split session names
+pub fn session_tag(epoch:Epoch) -> String {
+    let (year, month, day, hour, minute, _, _) = epoch.to_time_scale(TimeScale::GPST).to_gregorian(TimeScale::GPST);
+    format!("{:04}{:03}{:02}{:02}", year, day_of_year(year, month, day), hour, minute)
+}
+
+/// IGS RINEX 3 long filename: SSSSMRCCC_R_YYYYDDDHHMM_PPU_IIU_TO.ext
+/// Start and period are the session's nominal ones for split output, otherwise those of the data.
+pub fn igs_file_name(options:&OutputOptions, rtcm_data:&RtcmData, session:Option<(Epoch, Duration)>) -> String {
+
+    let interval = sampling_interval(rtcm_data).unwrap_or(Duration::from_seconds(1.0));
+
+    let (start, period) = session.unwrap_or_else(|| {
+        let first_epoch = rtcm_data.keys().next().unwrap().0;
+        let last_epoch = rtcm_data.keys().next_back().unwrap().0;
+        (first_epoch, last_epoch - first_epoch + interval)
+    });
+
+    let mut marker:String = options.marker.to_uppercase().chars().take(4).collect();
+    while marker.len() < 4 {
+        marker.push('X');
+    }
+
+    let mut country:String = options.country.to_uppercase().chars().take(3).collect();
+    while country.len() < 3 {
+        country.push('X');
+    }
+
+    let mut constellations:Vec<Constellation> = Vec::new();
+    for epoch in rtcm_data.values() {
+        for sv in epoch.1.keys() {
+            if !constellations.contains(&sv.constellation) {
+                constellations.push(sv.constellation);
+            }
+        }
+    }
+
+    let data_type = match constellations.as_slice() {
+        [Constellation::GPS] => "GO",
+        [Constellation::Galileo] => "EO",
+        [Constellation::BeiDou] => "CO",
+        _ => "MO"
+    };
+
+    format!("{}00{}_R_{}_{}_{}_{}.{}",
+        marker,
+        country,
+        session_tag(start),
+        format_period(period),
+        format_interval(interval),
+        data_type,
+        options.extension())
+}
+
+/// Inserts header records just before END OF HEADER, for records the rinex writer doesn't produce.
+pub fn insert_header_records(path:&String, records:&Vec<String>) -> io::Result<()> {
+
+    let content = fs::read_to_string(path)?;
+
+    let end_of_header = content.lines()
+        .position(|line| line.get(60..).map(|label| label.trim_end() == "END OF HEADER").unwrap_or(false))
+        .ok_or(io::Error::new(io::ErrorKind::InvalidData, "END OF HEADER not found"))?;
+
+    let mut lines:Vec<&str> = content.lines().collect();
+    for (i, record) in records.iter().enumerate() {
+        lines.insert(end_of_header + i, record.as_str());
+    }
+
+    let mut patched = lines.join("\n");
+    patched.push('\n');
+
+    fs::write(path, patched)
+}
+
+/// Compresses `path` into `path.gz` and removes the uncompressed file.
+pub fn gzip_file(path:&String) -> io::Result<String> {
+
+    let gz_path = format!("{}.gz", path);
+
+    let mut input = BufReader::new(File::open(path)?);
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(&gz_path)?), Compression::default());
+
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+
+    fs::remove_file(path)?;
+
+    Ok(gz_path)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{collections::BTreeMap, io::Read};
+
+    use flate2::read::GzDecoder;
+    use rinex::{observation::{EpochFlag, ObservationData}, prelude::{Observable, SV}};
+
+    use super::*;
+
+    // one epoch per second from 2024-09-16 20:15 GPST
+    fn rtcm_data(constellations:&[Constellation], epochs:u32) -> RtcmData {
+        let start = Epoch::from_gregorian(2024, 9, 16, 20, 15, 0, 0, TimeScale::GPST);
+
+        let mut rtcm_data:RtcmData = BTreeMap::new();
+        for i in 0..epochs {
+            let mut satellites = BTreeMap::new();
+            for constellation in constellations {
+                let mut observations:HashMap<Observable, ObservationData> = HashMap::new();
+                observations.insert(Observable::PseudoRange("C1C".to_string()), ObservationData {obs: 21000000.0, lli: None, snr: None});
+                satellites.insert(SV {constellation: *constellation, prn: 1}, observations);
+            }
+            rtcm_data.insert((start + Duration::from_seconds(i as f64), EpochFlag::Ok), (None, satellites));
+        }
+        rtcm_data
+    }
+
+    #[test]
+    fn igs_long_name() {
+        let options = OutputOptions {igs_name: true, marker: "abc".to_string(), country: "de".to_string(), ..OutputOptions::default()};
+
+        let mixed = rtcm_data(&[Constellation::GPS, Constellation::Galileo], 60);
+        assert_eq!(igs_file_name(&options, &mixed, None), "ABCX00DEX_R_20242602015_01M_01S_MO.rnx");
+
+        let gps = rtcm_data(&[Constellation::GPS], 7200);
+        let crinex = OutputOptions {compression: OutputCompression::CrinexGzip, ..options.clone()};
+        assert_eq!(igs_file_name(&crinex, &gps, None), "ABCX00DEX_R_20242602015_02H_01S_GO.crx");
+
+        // a split session that only has data for its first minute is still named after the whole hour
+        let session_start = Epoch::from_gregorian(2024, 9, 16, 20, 0, 0, 0, TimeScale::GPST);
+        assert_eq!(igs_file_name(&options, &mixed, Some((session_start, Duration::from_hours(1.0)))), "ABCX00DEX_R_20242602000_01H_01S_MO.rnx");
+    }
+
+    #[test]
+    fn period_and_interval_fields() {
+        assert_eq!(format_period(Duration::from_seconds(900.0)), "15M");
+        assert_eq!(format_period(Duration::from_seconds(3600.0)), "01H");
+        assert_eq!(format_period(Duration::from_seconds(86400.0)), "01D");
+        assert_eq!(format_interval(Duration::from_milliseconds(200.0)), "05Z");
+        assert_eq!(format_interval(Duration::from_seconds(30.0)), "30S");
+        assert_eq!(day_of_year(2024, 3, 1), 61);
+        assert_eq!(day_of_year(2023, 3, 1), 60);
+    }
+
+    #[test]
+    fn gzip_replaces_file() {
+        let path = std::env::temp_dir().join("rtcm2rnx_gzip.rnx").to_str().unwrap().to_string();
+        fs::write(&path, "     3.00           OBSERVATION DATA\n").unwrap();
+
+        let gz_path = gzip_file(&path).unwrap();
+        assert_eq!(gz_path, format!("{}.gz", path));
+        assert!(!std::path::Path::new(&path).exists());
+
+        let mut content = String::new();
+        GzDecoder::new(File::open(&gz_path).unwrap()).read_to_string(&mut content).unwrap();
+        assert_eq!(content, "     3.00           OBSERVATION DATA\n");
+    }
+}
@@ -0,0 +1,346 @@
+// serves a logged rtcm file over TCP in real time, paced by MSM epoch times
+// plain TCP (every connected client gets the stream) or a minimal NTRIP caster with a single mountpoint
+
+use std::{io::{self, BufRead, BufReader, Read, Write}, net::{TcpListener, TcpStream}, path::Path, sync::{Arc, Mutex}, thread, time::{Duration, Instant}};
+
+use log::{debug, info, warn};
+use rtcmlib::{compression, framing::{FrameBuffer, TimeOfWeek}};
+
+// how long an NTRIP client has to send its request
+const REQUEST_TIMEOUT:Duration = Duration::from_secs(10);
+
+// poll interval while waiting for the first client
+const CLIENT_POLL:Duration = Duration::from_millis(100);
+
+// a client that doesn't take data for this long is dropped instead of holding up the others
+const WRITE_TIMEOUT:Duration = Duration::from_secs(5);
+
+pub struct ReplayOptions {
+    /// address to listen on, e.g. 0.0.0.0:2101
+    pub listen:String,
+    /// serve as an NTRIP caster with this mountpoint instead of raw TCP
+    pub mountpoint:Option<String>,
+    /// playback speed multiplier, 2.0 replays twice as fast
+    pub speed:f64,
+    pub looping:bool
+}
+
+// bytes up to and including a frame, with the frame's GPS time of week when it's an MSM
+struct Chunk {
+    data:Vec<u8>,
+    time_of_week_ms:Option<u64>
+}
+
+// splits the log at frame ends so bytes between frames (garbage, failed CRC) are replayed as logged
+fn chunks(file_path:&Path) -> io::Result<Vec<Chunk>> {
+
+    let (_, mut reader) = compression::open_file(file_path)?;
+    let mut data:Vec<u8> = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    let mut frame_buffer = FrameBuffer::new();
+    frame_buffer.extend(&data);
+
+    let mut chunks:Vec<Chunk> = Vec::new();
+    let mut start:usize = 0;
+
+    while let Some(frame) = frame_buffer.next_raw_frame() {
+        if frame.crc_ok {
+            let end = frame.offset as usize + frame.data.len();
+            chunks.push(Chunk {data: data[start..end].to_vec(), time_of_week_ms: frame.msm_time_of_week_ms()});
+            start = end;
+        }
+    }
+
+    if start < data.len() {
+        chunks.push(Chunk {data: data[start..].to_vec(), time_of_week_ms: None});
+    }
+
+    Ok(chunks)
+}
+
+// a connected client, NTRIP 2 clients get the stream as HTTP chunks
+struct Client {
+    stream:TcpStream,
+    chunked:bool
+}
+
+impl Client {
+
+    fn send(&mut self, data:&[u8]) -> io::Result<()> {
+        // an empty chunk would end the stream
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.chunked {
+            self.stream.write_all(format!("{:x}\r\n", data.len()).as_bytes())?;
+            self.stream.write_all(data)?;
+            self.stream.write_all(b"\r\n")
+        }
+        else {
+            self.stream.write_all(data)
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.chunked {
+            self.stream.write_all(b"0\r\n\r\n")?;
+        }
+        self.stream.flush()
+    }
+
+    fn peer(&self) -> String {
+        self.stream.peer_addr().map(|a| a.to_string()).unwrap_or_default()
+    }
+}
+
+// reads the request line and headers; mountpoint clients get the stream, others the sourcetable or a 404
+fn ntrip_handshake(mut stream:TcpStream, mountpoint:&String) -> io::Result<Option<Client>> {
+
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    let mut ntrip_v2 = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if header.to_lowercase().starts_with("ntrip-version: ntrip/2") {
+            ntrip_v2 = true;
+        }
+    }
+
+    stream.set_read_timeout(None)?;
+
+    let path = request.split_whitespace().nth(1).unwrap_or("/").trim_start_matches('/');
+
+    if path == mountpoint {
+        if ntrip_v2 {
+            stream.write_all(b"HTTP/1.1 200 OK\r\nNtrip-Version: Ntrip/2.0\r\nContent-Type: gnss/data\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n")?;
+        }
+        else {
+            stream.write_all(b"ICY 200 OK\r\n\r\n")?;
+        }
+        return Ok(Some(Client {stream, chunked: ntrip_v2}));
+    }
+
+    if path.is_empty() {
+        let table = format!("STR;{};{};RTCM 3;;2;GNSS;rtcm2rnx;;0.00;0.00;0;0;rtcm2rnx replay;none;N;N;0;\r\nENDSOURCETABLE\r\n", mountpoint, mountpoint);
+        stream.write_all(format!("SOURCETABLE 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}", table.len(), table).as_bytes())?;
+    }
+    else {
+        stream.write_all(b"HTTP/1.0 404 Not Found\r\n\r\n")?;
+    }
+
+    Ok(None)
+}
+
+// accepts clients in the background, adding them to `clients` once they're ready for data
+fn accept_clients(listener:TcpListener, mountpoint:Option<String>, clients:Arc<Mutex<Vec<Client>>>) {
+
+    for stream in listener.incoming() {
+
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("accept failed: {}", e);
+                continue;
+            }
+        };
+
+        let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+        let clients = clients.clone();
+        let mountpoint = mountpoint.clone();
+
+        thread::spawn(move || {
+            let client = stream.set_write_timeout(Some(WRITE_TIMEOUT)).and_then(|_| match &mountpoint {
+                Some(mountpoint) => ntrip_handshake(stream, mountpoint),
+                None => Ok(Some(Client {stream, chunked: false}))
+            });
+            match client {
+                Ok(Some(client)) => {
+                    info!("client {} connected", peer);
+                    clients.lock().unwrap().push(client);
+                }
+                Ok(None) => debug!("client {} didn't request the mountpoint", peer),
+                Err(e) => warn!("client {}: {}", peer, e)
+            }
+        });
+    }
+}
+
+// sends to every client, dropping the ones that went away or stalled for WRITE_TIMEOUT
+// the list isn't locked while sending, clients connecting meanwhile get the next chunk
+fn broadcast(clients:&Arc<Mutex<Vec<Client>>>, data:&[u8]) {
+
+    let mut sending = std::mem::take(&mut *clients.lock().unwrap());
+
+    sending.retain_mut(|client| {
+        match client.send(data) {
+            Ok(()) => true,
+            Err(e) => {
+                info!("client {} disconnected: {}", client.peer(), e);
+                false
+            }
+        }
+    });
+
+    clients.lock().unwrap().extend(sending);
+}
+
+/// Replays the log, starting once the first client has connected. Chunks without an MSM epoch go out
+/// right after the preceding one; MSM chunks wait until their epoch, relative to the first, divided by `speed`.
+pub fn replay(file_path:&Path, options:&ReplayOptions) -> io::Result<()> {
+
+    let chunks = chunks(file_path)?;
+    if !chunks.iter().any(|chunk| chunk.time_of_week_ms.is_some()) {
+        warn!(file = file_path.to_str().unwrap_or_default(); "no MSM epochs found, replaying without pacing");
+    }
+
+    let listener = TcpListener::bind(&options.listen)?;
+    info!("listening on {}{}", options.listen, options.mountpoint.as_ref().map(|m| format!(" (NTRIP mountpoint {})", m)).unwrap_or_default());
+
+    serve(listener, &chunks, options);
+
+    info!(file = file_path.to_str().unwrap_or_default(); "end of log");
+    Ok(())
+}
+
+// paces the chunks out to the listener's clients, which are disconnected at the end
+fn serve(listener:TcpListener, chunks:&Vec<Chunk>, options:&ReplayOptions) {
+
+    let clients:Arc<Mutex<Vec<Client>>> = Arc::new(Mutex::new(Vec::new()));
+    let accept_clients_list = clients.clone();
+    let mountpoint = options.mountpoint.clone();
+    thread::spawn(move || accept_clients(listener, mountpoint, accept_clients_list));
+
+    while clients.lock().unwrap().is_empty() {
+        thread::sleep(CLIENT_POLL);
+    }
+
+    loop {
+        let start = Instant::now();
+        let mut first_ms:Option<u64> = None;
+        let mut time_of_week = TimeOfWeek::new();
+
+        for chunk in chunks.iter() {
+
+            if let Some(time_of_week_ms) = chunk.time_of_week_ms {
+                let time_ms = time_of_week.continuous_ms(time_of_week_ms);
+                let first = *first_ms.get_or_insert(time_ms);
+
+                // epochs out of order (e.g. a merged log) are sent immediately
+                let due = start + Duration::from_secs_f64(time_ms.saturating_sub(first) as f64 / 1000.0 / options.speed);
+                let now = Instant::now();
+                if due > now {
+                    thread::sleep(due - now);
+                }
+            }
+
+            broadcast(&clients, &chunk.data);
+        }
+
+        if !options.looping {
+            break;
+        }
+        info!("end of log, restarting");
+    }
+
+    for mut client in clients.lock().unwrap().drain(..) {
+        if let Err(e) = client.finish() {
+            debug!("client {}: {}", client.peer(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEBUG_RTCM:&str = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm");
+
+    // replays debug.rtcm on a loopback port
+    fn replay_debug_log(mountpoint:&str, speed:f64) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let options = ReplayOptions {listen: address.clone(), mountpoint: Some(mountpoint.to_string()), speed, looping: false};
+
+        let handle = thread::spawn(move || {
+            let chunks = chunks(Path::new(DEBUG_RTCM)).unwrap();
+            serve(listener, &chunks, &options);
+        });
+
+        (address, handle)
+    }
+
+    fn request(address:&String, request:&str) -> Vec<u8> {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response:Vec<u8> = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        response
+    }
+
+    fn split_response(response:&[u8]) -> (String, Vec<u8>) {
+        let header_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        (String::from_utf8(response[..header_end].to_vec()).unwrap(), response[header_end..].to_vec())
+    }
+
+    // removes the chunked transfer encoding, which has to end with an empty chunk
+    fn dechunk(body:&[u8]) -> Vec<u8> {
+        let mut data:Vec<u8> = Vec::new();
+        let mut rest = body;
+        loop {
+            let line_end = rest.windows(2).position(|w| w == b"\r\n").unwrap();
+            let len = usize::from_str_radix(std::str::from_utf8(&rest[..line_end]).unwrap(), 16).unwrap();
+            rest = &rest[line_end + 2..];
+            if len == 0 {
+                break;
+            }
+            data.extend_from_slice(&rest[..len]);
+            assert_eq!(&rest[len..len + 2], b"\r\n");
+            rest = &rest[len + 2..];
+        }
+        assert_eq!(rest, b"\r\n");
+        data
+    }
+
+    #[test]
+    fn ntrip_v2_stream_is_chunked_and_paced() {
+        let (address, handle) = replay_debug_log("TEST", 4.0);
+
+        let start = Instant::now();
+        let response = request(&address, "GET /TEST HTTP/1.1\r\nHost: localhost\r\nNtrip-Version: Ntrip/2.0\r\n\r\n");
+        let elapsed = start.elapsed();
+        handle.join().unwrap();
+
+        let (header, body) = split_response(&response);
+        assert!(header.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(header.contains("Transfer-Encoding: chunked\r\n"));
+        assert_eq!(dechunk(&body), std::fs::read(DEBUG_RTCM).unwrap());
+
+        // MSM epochs 1 s apart, replayed 4 times faster
+        assert!(elapsed >= Duration::from_millis(250), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn ntrip_v1_sourcetable_and_stream() {
+        let (address, handle) = replay_debug_log("TEST", 100.0);
+
+        // neither starts the replay
+        let (header, body) = split_response(&request(&address, "GET / HTTP/1.0\r\n\r\n"));
+        assert!(header.starts_with("SOURCETABLE 200 OK\r\n"));
+        assert!(String::from_utf8(body).unwrap().starts_with("STR;TEST;"));
+        assert!(request(&address, "GET /OTHER HTTP/1.0\r\n\r\n").starts_with(b"HTTP/1.0 404"));
+
+        let (header, body) = split_response(&request(&address, "GET /TEST HTTP/1.0\r\nUser-Agent: NTRIP test\r\n\r\n"));
+        handle.join().unwrap();
+
+        assert_eq!(header, "ICY 200 OK\r\n\r\n");
+        assert_eq!(body, std::fs::read(DEBUG_RTCM).unwrap());
+    }
+}
@@ -0,0 +1,157 @@
+// decoding statistics report (text or json)
+
+use rtcmlib::RtcmDecoder;
+use serde_json::{json, Value};
+
+pub fn stats_json(rtcm_decoder:&RtcmDecoder) -> Value {
+
+    let stats = rtcm_decoder.get_stats();
+
+    let message_counts:serde_json::Map<String, Value> = stats.message_counts.iter()
+        .map(|(message_number, count)| (message_number.to_string(), json!(count)))
+        .collect();
+
+    let signal_epochs:serde_json::Map<String, Value> = stats.signal_epochs.iter()
+        .map(|(sv, signals)| (sv.to_string(), json!(signals)))
+        .collect();
+
+    let unmapped_signals:Vec<Value> = stats.unmapped_signals.iter()
+        .map(|((constellation, code), count)| json!({"constellation": constellation.to_string(), "code": code, "count": count}))
+        .collect();
+
+    let gaps:Vec<Value> = stats.gaps.iter()
+        .map(|gap| json!({"sv": gap.sv.to_string(), "code": gap.code, "start": gap.start.to_string(), "end": gap.end.to_string()}))
+        .collect();
+
+    let discarded_ranges:Vec<Value> = stats.discarded_ranges.iter()
+        .map(|range| json!({"file": range.file, "offset": range.offset, "len": range.len, "nmea_sentences": range.nmea_sentences}))
+        .collect();
+
+    json!({
+        "first_epoch": rtcm_decoder.get_first_epoch().map(|e| e.to_string()),
+        "last_epoch": rtcm_decoder.get_last_epoch().map(|e| e.to_string()),
+        "epochs": rtcm_decoder.get_rtcm_data().len(),
+        "frames": stats.frames,
+        "crc_failures": stats.crc_failures,
+        "corrupt_messages": stats.corrupt_messages,
+        "msm_waiting_for_week": stats.msm_waiting_for_week,
+        "duplicate_signals": stats.duplicate_signals,
+        "missing_satellites": stats.missing_satellites,
+        "late_signals": stats.late_signals,
+        "filtered_signals": stats.filtered_signals,
+        "skipped_epoch_signals": stats.skipped_epoch_signals,
+        "garbage_bytes": stats.garbage_bytes,
+        "discarded_range_count": stats.discarded_range_count,
+        "message_counts": message_counts,
+        "signal_epochs": signal_epochs,
+        "unmapped_signals": unmapped_signals,
+        "gaps": gaps,
+        "discarded_ranges": discarded_ranges
+    })
+}
+
+pub fn stats_text(rtcm_decoder:&RtcmDecoder) -> String {
+
+    let stats = rtcm_decoder.get_stats();
+
+    let mut lines:Vec<String> = Vec::new();
+
+    lines.push(format!("first epoch:          {}", rtcm_decoder.get_first_epoch().map(|e| e.to_string()).unwrap_or("-".to_string())));
+    lines.push(format!("last epoch:           {}", rtcm_decoder.get_last_epoch().map(|e| e.to_string()).unwrap_or("-".to_string())));
+    lines.push(format!("epochs:               {}", rtcm_decoder.get_rtcm_data().len()));
+    lines.push(format!("frames:               {}", stats.frames));
+    lines.push(format!("crc failures:         {}", stats.crc_failures));
+    lines.push(format!("corrupt messages:     {}", stats.corrupt_messages));
+    lines.push(format!("msm without week:     {}", stats.msm_waiting_for_week));
+    lines.push(format!("duplicate signals:    {}", stats.duplicate_signals));
+    lines.push(format!("missing satellites:   {}", stats.missing_satellites));
+    lines.push(format!("late signals:         {}", stats.late_signals));
+    lines.push(format!("filtered signals:     {}", stats.filtered_signals));
+    lines.push(format!("skipped epoch sigs:   {}", stats.skipped_epoch_signals));
+    lines.push(format!("garbage bytes:        {}", stats.garbage_bytes));
+
+    lines.push("messages:".to_string());
+    for (message_number, count) in stats.message_counts.iter() {
+        lines.push(format!("  {:>6} {:>10}", message_number, count));
+    }
+
+    lines.push("signal epochs:".to_string());
+    for (sv, signals) in stats.signal_epochs.iter() {
+        let signals:Vec<String> = signals.iter().map(|(code, count)| format!("{}:{}", code, count)).collect();
+        lines.push(format!("  {} {}", sv, signals.join(" ")));
+    }
+
+    if !stats.unmapped_signals.is_empty() {
+        lines.push("unmapped signals:".to_string());
+        for ((constellation, code), count) in stats.unmapped_signals.iter() {
+            lines.push(format!("  {} {} {}", constellation, code, count));
+        }
+    }
+
+    if !stats.gaps.is_empty() {
+        lines.push("gaps:".to_string());
+        for gap in stats.gaps.iter() {
+            lines.push(format!("  {} {} {} -> {}", gap.sv, gap.code, gap.start, gap.end));
+        }
+    }
+
+    if !stats.discarded_ranges.is_empty() {
+        if stats.discarded_range_count > stats.discarded_ranges.len() as u64 {
+            lines.push(format!("discarded ranges (first {} of {}):", stats.discarded_ranges.len(), stats.discarded_range_count));
+        }
+        else {
+            lines.push("discarded ranges:".to_string());
+        }
+        for range in stats.discarded_ranges.iter() {
+            let nmea = if range.nmea_sentences > 0 { format!(" ({} NMEA)", range.nmea_sentences) } else { String::new() };
+            lines.push(format!("  {} {:>10} {:>6}{}", range.file, range.offset, range.len, nmea));
+        }
+    }
+
+    lines.join("\n")
+}
+
+pub fn print_report(rtcm_decoder:&RtcmDecoder, format:&str) {
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&stats_json(rtcm_decoder)).unwrap()),
+        _ => println!("{}", stats_text(rtcm_decoder))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoder() -> RtcmDecoder {
+        let mut rtcm_decoder = RtcmDecoder::new(false);
+        rtcm_decoder.load_file(std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm"))).unwrap();
+        rtcm_decoder
+    }
+
+    #[test]
+    fn json_report_counts_frames() {
+        let report = stats_json(&decoder());
+
+        // round trips through text, as printed
+        let report:Value = serde_json::from_str(&serde_json::to_string_pretty(&report).unwrap()).unwrap();
+        assert_eq!(report["frames"], json!(6));
+        assert_eq!(report["crc_failures"], json!(0));
+        assert_eq!(report["garbage_bytes"], json!(0));
+        assert_eq!(report["discarded_range_count"], json!(0));
+        assert_eq!(report["message_counts"]["1077"], json!(2));
+        assert_eq!(report["message_counts"]["1097"], json!(2));
+        assert!(report["first_epoch"].is_string());
+    }
+
+    #[test]
+    fn text_report_matches_json() {
+        let rtcm_decoder = decoder();
+        let text = stats_text(&rtcm_decoder);
+        let report = stats_json(&rtcm_decoder);
+
+        assert!(text.contains("frames:               6"));
+        assert!(text.contains(&format!("epochs:               {}", report["epochs"])));
+        assert!(text.contains(&format!("  {:>6} {:>10}", 1019, 1)));
+        assert!(!text.contains("discarded ranges:"));
+    }
+}
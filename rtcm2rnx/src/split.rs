@@ -0,0 +1,247 @@
+// byte exact trimming of an rtcm log: keep selected messages, stations or a time window, optionally split
+// into one file per GPS hour or per message number; frames are copied as logged, never re-encoded
+
+use std::{collections::{BTreeMap, HashMap}, fs::File, io::{self, BufWriter, Read, Write}, path::Path};
+
+use log::{info, warn};
+use rinex::prelude::{Duration, Epoch};
+use rtcmlib::{compression, framing::{self, FrameBuffer, RawFrame, TimeOfWeek}};
+
+use crate::output::session_tag;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitMode {
+    None,
+    Hour,
+    Message
+}
+
+impl SplitMode {
+    pub fn from_str(split:&str) -> Option<SplitMode> {
+        match split {
+            "none" => Some(SplitMode::None),
+            "hour" => Some(SplitMode::Hour),
+            "message" => Some(SplitMode::Message),
+            _ => None
+        }
+    }
+}
+
+pub struct SplitOptions {
+    /// message numbers to keep, all when empty
+    pub messages:Vec<u16>,
+    /// station ids to keep, all when empty; messages without a station id (e.g. ephemeris) are always kept
+    pub stations:Vec<u16>,
+    pub start:Option<Epoch>,
+    pub end:Option<Epoch>,
+    pub split:SplitMode
+}
+
+// GPS time of week closest to `reference`
+fn nearest_epoch(time_of_week_ms:u64, reference:Epoch) -> Epoch {
+    let week = (reference.to_gpst_seconds() / 604800.0).floor() as u64;
+    [week.saturating_sub(1), week, week + 1].iter()
+        .map(|week| rtcmlib::rtcm_gps_time2epoch(time_of_week_ms as f64, *week))
+        .min_by(|a, b| (*a - reference).abs().to_seconds().total_cmp(&(*b - reference).abs().to_seconds()))
+        .unwrap()
+}
+
+/// Epoch of every frame: MSMs from their header (week from the log's first ephemeris, or the time window
+/// when there is none), other messages the epoch of the MSM before them (the first MSM for a log's leading frames).
+fn frame_epochs(frames:&Vec<RawFrame>, reference:Option<Epoch>) -> Vec<Option<Epoch>> {
+
+    let week = frames.iter().find_map(|frame| framing::ephemeris_gps_week(&frame.data));
+
+    let mut epochs:Vec<Option<Epoch>> = Vec::new();
+    let mut time_of_week = TimeOfWeek::new();
+
+    for frame in frames.iter() {
+        let epoch = frame.msm_time_of_week_ms().and_then(|time_of_week_ms| {
+            let time_ms = time_of_week.continuous_ms(time_of_week_ms);
+            match (week, reference) {
+                (Some(week), _) => Some(rtcmlib::rtcm_gps_time2epoch(time_ms as f64, week)),
+                (None, Some(reference)) => Some(nearest_epoch(time_of_week_ms, reference)),
+                (None, None) => None
+            }
+        });
+        epochs.push(epoch);
+    }
+
+    let first = epochs.iter().find_map(|epoch| *epoch);
+    let mut last = first;
+    for epoch in epochs.iter_mut() {
+        if epoch.is_some() {
+            last = *epoch;
+        }
+        else {
+            *epoch = last;
+        }
+    }
+
+    epochs
+}
+
+fn keep(frame:&RawFrame, epoch:Option<Epoch>, options:&SplitOptions) -> bool {
+
+    if !options.messages.is_empty() && !frame.message_number().map(|n| options.messages.contains(&n)).unwrap_or(false) {
+        return false;
+    }
+
+    if !options.stations.is_empty() && frame.station_id().is_some() && !options.stations.contains(&frame.station_id().unwrap()) {
+        return false;
+    }
+
+    if epoch.is_some() {
+        if options.start.is_some() && epoch.unwrap() < options.start.unwrap() {
+            return false;
+        }
+        if options.end.is_some() && epoch.unwrap() > options.end.unwrap() {
+            return false;
+        }
+    }
+
+    true
+}
+
+// <output>_<tag>.<ext> for split output
+fn split_path(output:&String, tag:&String) -> String {
+    let path = Path::new(output);
+    let stem = path.with_extension("");
+    match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem.display(), tag, extension.to_str().unwrap_or_default()),
+        None => format!("{}_{}", stem.display(), tag)
+    }
+}
+
+pub fn split_file(file_path:&Path, output:&String, options:&SplitOptions) -> io::Result<()> {
+
+    let (_, mut reader) = compression::open_file(file_path)?;
+    let mut data:Vec<u8> = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    let mut frame_buffer = FrameBuffer::new();
+    frame_buffer.extend(&data);
+
+    let mut frames:Vec<RawFrame> = Vec::new();
+    let mut dropped_bytes:usize = data.len();
+    while let Some(frame) = frame_buffer.next_raw_frame() {
+        if frame.crc_ok {
+            dropped_bytes -= frame.data.len();
+            frames.push(frame);
+        }
+    }
+
+    if dropped_bytes > 0 {
+        warn!(file = file_path.to_str().unwrap_or_default(); "{} bytes outside valid frames not copied", dropped_bytes);
+    }
+
+    let epochs = frame_epochs(&frames, options.start.or(options.end));
+
+    if (options.start.is_some() || options.end.is_some() || options.split == SplitMode::Hour) && epochs.iter().all(|epoch| epoch.is_none()) {
+        warn!(file = file_path.to_str().unwrap_or_default(); "no MSM epochs with a known week, time window and hourly split ignored");
+    }
+
+    let mut writers:BTreeMap<String, BufWriter<File>> = BTreeMap::new();
+    let mut counts:HashMap<String, (u64, u64)> = HashMap::new();
+
+    for (frame, epoch) in frames.iter().zip(epochs.iter()) {
+
+        if !keep(frame, *epoch, options) {
+            continue;
+        }
+
+        let path = match options.split {
+            SplitMode::None => output.clone(),
+            SplitMode::Message => split_path(output, &frame.message_number().map(|n| n.to_string()).unwrap_or_default()),
+            SplitMode::Hour => match epoch {
+                Some(epoch) => split_path(output, &session_tag(rtcmlib::session_start(*epoch, Duration::from_hours(1.0)))),
+                None => output.clone()
+            }
+        };
+
+        if !writers.contains_key(&path) {
+            writers.insert(path.clone(), BufWriter::new(File::create(&path)?));
+        }
+        writers.get_mut(&path).unwrap().write_all(&frame.data)?;
+
+        let count = counts.entry(path).or_insert((0, 0));
+        count.0 += 1;
+        count.1 += frame.data.len() as u64;
+    }
+
+    for (path, writer) in writers.iter_mut() {
+        writer.flush()?;
+        let (frames, bytes) = counts[path];
+        info!(file = path.as_str(); "{} frames, {} bytes", frames, bytes);
+    }
+
+    if writers.is_empty() {
+        warn!(file = file_path.to_str().unwrap_or_default(); "no frames matched, nothing written");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rtcmlib::RtcmDecoder;
+
+    const DEBUG_RTCM:&str = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm");
+
+    fn options(split:SplitMode) -> SplitOptions {
+        SplitOptions {messages: Vec::new(), stations: Vec::new(), start: None, end: None, split}
+    }
+
+    fn frames(data:&[u8]) -> Vec<RawFrame> {
+        let mut frame_buffer = FrameBuffer::new();
+        frame_buffer.extend(data);
+        let mut frames:Vec<RawFrame> = Vec::new();
+        while let Some(frame) = frame_buffer.next_raw_frame() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[test]
+    fn unfiltered_copy_is_byte_identical() {
+        let output = std::env::temp_dir().join("rtcm2rnx_split_copy.rtcm").to_str().unwrap().to_string();
+        split_file(Path::new(DEBUG_RTCM), &output, &options(SplitMode::None)).unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), std::fs::read(DEBUG_RTCM).unwrap());
+    }
+
+    #[test]
+    fn message_split_keeps_frames_as_logged() {
+        let data = std::fs::read(DEBUG_RTCM).unwrap();
+        let output = std::env::temp_dir().join("rtcm2rnx_split.rtcm").to_str().unwrap().to_string();
+        split_file(Path::new(DEBUG_RTCM), &output, &options(SplitMode::Message)).unwrap();
+
+        let mut expected:BTreeMap<u16, Vec<u8>> = BTreeMap::new();
+        for frame in frames(&data) {
+            expected.entry(frame.message_number().unwrap()).or_insert(Vec::new()).extend(&frame.data);
+        }
+
+        for (message_number, bytes) in expected.iter() {
+            assert_eq!(&std::fs::read(split_path(&output, &message_number.to_string())).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn frame_epochs_match_decoder() {
+        let data = std::fs::read(DEBUG_RTCM).unwrap();
+        let frames = frames(&data);
+        let epochs = frame_epochs(&frames, None);
+
+        let mut rtcm_decoder = RtcmDecoder::new(false);
+        rtcm_decoder.decode_bytes(&data).unwrap();
+        let decoded:Vec<Epoch> = rtcm_decoder.get_rtcm_data().keys().map(|k| k.0).collect();
+
+        for (frame, epoch) in frames.iter().zip(epochs.iter()) {
+            if frame.msm_time_of_week_ms().is_some() {
+                assert!(decoded.contains(&epoch.unwrap()), "{:?}", epoch);
+            }
+        }
+        // ephemeris ahead of the first MSM take its epoch
+        assert_eq!(epochs[0], Some(decoded[0]));
+    }
+}
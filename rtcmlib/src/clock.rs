@@ -0,0 +1,308 @@
+// receiver clock jump detection and repair, post-processing on decoded data
+// receivers that steer their clock in whole milliseconds step every pseudorange by ~299792.458 m at once
+
+use std::collections::{BTreeMap, HashMap};
+
+use rinex::{observation::{EpochFlag, LliFlags, ObservationData}, prelude::{Carrier, Constellation, Epoch, Observable, SV}};
+use nyx_space::cosmic::SPEED_OF_LIGHT;
+
+use crate::{RtcmData, RANGE_MS};
+
+// a step counts as whole milliseconds when it's within this of k * 1 ms of range (m)
+const JUMP_TOLERANCE_M:f64 = 300.0;
+
+// fewer signals than this can't tell a clock jump from outliers
+const MIN_SIGNALS:usize = 2;
+
+/// Millisecond receiver clock jump between the previous epoch and `epoch`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClockJump {
+    pub epoch:Epoch,
+    pub milliseconds:i64,
+    /// phase stepped along with code (only detectable with doppler)
+    pub phase_jumped:bool
+}
+
+fn wavelength(constellation:Constellation, observable:&Observable) -> Option<f64> {
+    Carrier::from_observable(constellation, observable).ok().map(|carrier| SPEED_OF_LIGHT / carrier.frequency())
+}
+
+// whole milliseconds shared by every step, if there are enough of them and it isn't 0
+fn common_jump(steps:&Vec<f64>) -> Option<i64> {
+
+    if steps.len() < MIN_SIGNALS {
+        return None;
+    }
+
+    let milliseconds = (steps[0] / RANGE_MS).round() as i64;
+    if milliseconds == 0 {
+        return None;
+    }
+
+    let common = steps.iter().all(|step| (step - milliseconds as f64 * RANGE_MS).abs() < JUMP_TOLERANCE_M);
+
+    if common { Some(milliseconds) } else { None }
+}
+
+/// Finds epochs where all pseudoranges step by the same whole number of milliseconds.
+/// Satellite motion is taken out with doppler when both epochs have it, otherwise with carrier phase
+/// (which only reveals jumps that don't also step the phase).
+pub fn detect_clock_jumps(rtcm_data:&RtcmData) -> Vec<ClockJump> {
+    let epochs:Vec<_> = rtcm_data.iter().collect();
+    detect(&epochs)
+}
+
+// clock offset and observations of one RtcmData epoch
+type EpochData = (Option<f64>, BTreeMap<SV, HashMap<Observable, ObservationData>>);
+
+fn detect(epochs:&Vec<(&(Epoch, EpochFlag), &EpochData)>) -> Vec<ClockJump> {
+
+    let mut jumps:Vec<ClockJump> = Vec::new();
+
+    for pair in epochs.windows(2) {
+
+        let ((previous_epoch, _), (_, previous)) = pair[0];
+        let ((epoch, _), (_, current)) = pair[1];
+
+        let dt = (*epoch - *previous_epoch).to_seconds();
+
+        let mut code_steps:Vec<f64> = Vec::new();
+        let mut phase_steps:Vec<f64> = Vec::new();
+
+        for (sv, observations) in current.iter() {
+
+            let previous_observations = match previous.get(sv) {
+                Some(previous_observations) => previous_observations,
+                None => continue
+            };
+
+            for (observable, code) in observations.iter() {
+
+                let previous_code = match (observable, previous_observations.get(observable)) {
+                    (Observable::PseudoRange(_), Some(previous_code)) => previous_code,
+                    _ => continue
+                };
+
+                let signal = observable.code().unwrap();
+                let phase = Observable::Phase(format!("L{}", signal));
+                let doppler = Observable::Doppler(format!("D{}", signal));
+
+                let lambda = match wavelength(sv.constellation, &phase) {
+                    Some(lambda) => lambda,
+                    None => continue
+                };
+
+                let code_step = code.obs - previous_code.obs;
+
+                let phase_step = match (observations.get(&phase), previous_observations.get(&phase)) {
+                    (Some(l), Some(previous_l)) if !l.lli.map(|lli| lli.intersects(LliFlags::LOCK_LOSS)).unwrap_or(false) => Some((l.obs - previous_l.obs) * lambda),
+                    _ => None
+                };
+
+                match (observations.get(&doppler), previous_observations.get(&doppler)) {
+                    (Some(d), Some(previous_d)) => {
+                        // doppler (Hz) is the negative range rate in cycles
+                        let motion = -(d.obs + previous_d.obs) / 2.0 * lambda * dt;
+                        code_steps.push(code_step - motion);
+                        if phase_step.is_some() {
+                            phase_steps.push(phase_step.unwrap() - motion);
+                        }
+                    }
+                    _ => {
+                        if phase_step.is_some() {
+                            code_steps.push(code_step - phase_step.unwrap());
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(milliseconds) = common_jump(&code_steps) {
+            let phase_jumped = common_jump(&phase_steps) == Some(milliseconds);
+            jumps.push(ClockJump {epoch: *epoch, milliseconds, phase_jumped});
+        }
+    }
+
+    jumps
+}
+
+/// Removes detected jumps so code (and phase, where it stepped too) stay continuous with each other.
+pub fn repair_clock_jumps(rtcm_data:&mut RtcmData, jumps:&Vec<ClockJump>) {
+    repair(rtcm_data, jumps, &mut 0, &mut 0);
+}
+
+// code_ms / phase_ms: offsets (ms) already accumulated before the first epoch, left at the last epoch's
+fn repair(rtcm_data:&mut RtcmData, jumps:&Vec<ClockJump>, code_ms:&mut i64, phase_ms:&mut i64) {
+
+    for ((epoch, _), (_, satellites)) in rtcm_data.iter_mut() {
+
+        for jump in jumps.iter().filter(|jump| jump.epoch == *epoch) {
+            *code_ms += jump.milliseconds;
+            if jump.phase_jumped {
+                *phase_ms += jump.milliseconds;
+            }
+        }
+
+        if *code_ms == 0 && *phase_ms == 0 {
+            continue;
+        }
+
+        for (sv, observations) in satellites.iter_mut() {
+            for (observable, observation) in observations.iter_mut() {
+                match observable {
+                    Observable::PseudoRange(_) => observation.obs -= *code_ms as f64 * RANGE_MS,
+                    Observable::Phase(_) if *phase_ms != 0 => {
+                        if let Some(lambda) = wavelength(sv.constellation, observable) {
+                            observation.obs -= *phase_ms as f64 * RANGE_MS / lambda;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Detection and repair carried from one output file to the next (split sessions, serial flushes),
+/// so a jump between two files is found and its repair keeps applying to the files after it.
+/// It isn't part of the decoder checkpoint, a resumed run starts over.
+#[derive(Clone, Debug, Default)]
+pub struct ClockJumpTracker {
+    // last epoch of the previous file, as decoded
+    previous:Option<((Epoch, EpochFlag), EpochData)>,
+    code_ms:i64,
+    phase_ms:i64
+}
+
+impl ClockJumpTracker {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Jumps in the next file, including one between the previous file and its first epoch.
+    pub fn detect(&mut self, rtcm_data:&RtcmData) -> Vec<ClockJump> {
+
+        let mut epochs:Vec<_> = Vec::new();
+        if let Some((key, data)) = self.previous.as_ref() {
+            if rtcm_data.keys().next().map_or(false, |first| key < first) {
+                epochs.push((key, data));
+            }
+        }
+        epochs.extend(rtcm_data.iter());
+
+        let jumps = detect(&epochs);
+
+        if let Some((key, data)) = rtcm_data.last_key_value() {
+            self.previous = Some((*key, data.clone()));
+        }
+
+        jumps
+    }
+
+    /// Repairs the file's jumps on top of the offsets of the previous files' jumps.
+    pub fn repair(&mut self, rtcm_data:&mut RtcmData, jumps:&Vec<ClockJump>) {
+        repair(rtcm_data, jumps, &mut self.code_ms, &mut self.phase_ms);
+    }
+}
+
+/// Header `COMMENT` records listing the jumps, as teqc / convbin do.
+pub fn comment_records(jumps:&Vec<ClockJump>, repaired:bool) -> Vec<String> {
+    let action = if repaired { "REPAIRED" } else { "DETECTED" };
+    jumps.iter()
+        .map(|jump| {
+            let text:String = format!("CLOCK JUMP {:+} MS {} AT {}", jump.milliseconds, action, jump.epoch).chars().take(60).collect();
+            format!("{:<60}COMMENT", text)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use rinex::prelude::Duration;
+
+    use super::*;
+
+    const RANGE_M:f64 = 20_000_000.0;
+
+    fn epoch(seconds:f64) -> Epoch {
+        Epoch::from_gpst_seconds(1_400_000_000.0) + Duration::from_seconds(seconds)
+    }
+
+    // static GPS satellites G01.. on L1 with zero doppler, the first `jumped` of them stepping by `milliseconds` from `jump_at` s
+    fn rtcm_data(seconds:std::ops::Range<u32>, satellites:u8, jumped:u8, jump_at:u32, milliseconds:i64) -> RtcmData {
+        let lambda = wavelength(Constellation::GPS, &Observable::Phase("L1C".to_string())).unwrap();
+        let mut rtcm_data:RtcmData = BTreeMap::new();
+        for second in seconds {
+            let mut epoch_data = BTreeMap::new();
+            for prn in 1..=satellites {
+                let step = if prn <= jumped && second >= jump_at { milliseconds as f64 * RANGE_MS } else { 0.0 };
+                let mut observations:HashMap<Observable, ObservationData> = HashMap::new();
+                observations.insert(Observable::PseudoRange("C1C".to_string()), ObservationData {obs: RANGE_M + step, lli: None, snr: None});
+                observations.insert(Observable::Phase("L1C".to_string()), ObservationData {obs: RANGE_M / lambda, lli: None, snr: None});
+                observations.insert(Observable::Doppler("D1C".to_string()), ObservationData {obs: 0.0, lli: None, snr: None});
+                epoch_data.insert(SV {constellation: Constellation::GPS, prn}, observations);
+            }
+            rtcm_data.insert((epoch(second as f64), EpochFlag::Ok), (None, epoch_data));
+        }
+        rtcm_data
+    }
+
+    fn assert_code_continuous(rtcm_data:&RtcmData) {
+        for (_, (_, satellites)) in rtcm_data.iter() {
+            for observations in satellites.values() {
+                let code = observations.get(&Observable::PseudoRange("C1C".to_string())).unwrap().obs;
+                assert!((code - RANGE_M).abs() < 1e-6, "code {} not repaired", code);
+            }
+        }
+    }
+
+    #[test]
+    fn detects_and_repairs_millisecond_step() {
+        let mut rtcm_data = rtcm_data(0..4, 4, 4, 2, 1);
+
+        let jumps = detect_clock_jumps(&rtcm_data);
+        assert_eq!(jumps, vec![ClockJump {epoch: epoch(2.0), milliseconds: 1, phase_jumped: false}]);
+
+        repair_clock_jumps(&mut rtcm_data, &jumps);
+        assert_code_continuous(&rtcm_data);
+    }
+
+    #[test]
+    fn step_on_min_signals_satellites() {
+        let rtcm_data_min = rtcm_data(0..4, MIN_SIGNALS as u8, MIN_SIGNALS as u8, 2, -1);
+        assert_eq!(detect_clock_jumps(&rtcm_data_min), vec![ClockJump {epoch: epoch(2.0), milliseconds: -1, phase_jumped: false}]);
+
+        let rtcm_data_fewer = rtcm_data(0..4, MIN_SIGNALS as u8 - 1, MIN_SIGNALS as u8 - 1, 2, -1);
+        assert!(detect_clock_jumps(&rtcm_data_fewer).is_empty());
+
+        // satellites that didn't step make it an outlier rather than a clock jump
+        let rtcm_data_partial = rtcm_data(0..4, MIN_SIGNALS as u8 + 1, MIN_SIGNALS as u8, 2, -1);
+        assert!(detect_clock_jumps(&rtcm_data_partial).is_empty());
+    }
+
+    #[test]
+    fn tracker_carries_jump_between_files() {
+        let mut tracker = ClockJumpTracker::new();
+
+        let mut first = rtcm_data(0..2, 4, 4, 2, 1);
+        let jumps = tracker.detect(&first);
+        assert!(jumps.is_empty());
+        tracker.repair(&mut first, &jumps);
+
+        // the jump lands on the first epoch of the second file
+        let mut second = rtcm_data(2..4, 4, 4, 2, 1);
+        let jumps = tracker.detect(&second);
+        assert_eq!(jumps, vec![ClockJump {epoch: epoch(2.0), milliseconds: 1, phase_jumped: false}]);
+        tracker.repair(&mut second, &jumps);
+        assert_code_continuous(&second);
+
+        let mut third = rtcm_data(4..6, 4, 4, 2, 1);
+        let jumps = tracker.detect(&third);
+        assert!(jumps.is_empty());
+        tracker.repair(&mut third, &jumps);
+        assert_code_continuous(&third);
+    }
+}
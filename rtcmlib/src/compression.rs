@@ -0,0 +1,126 @@
+// transparent decompression of archived rtcm logs (.gz, .zst, .bz2), detected by magic bytes rather than file extension
+
+use std::{fs::File, io::{self, BufRead, BufReader, Read}, path::Path};
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+
+const GZIP_MAGIC:[u8;2] = [0x1F, 0x8B];
+const ZSTD_MAGIC:[u8;4] = [0x28, 0xB5, 0x2F, 0xFD];
+const BZIP2_MAGIC:[u8;3] = [0x42, 0x5A, 0x68];  // "BZh"
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2
+}
+
+impl Compression {
+    pub fn detect(magic:&[u8]) -> Compression {
+        if magic.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        }
+        else if magic.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        }
+        else if magic.starts_with(&BZIP2_MAGIC) {
+            Compression::Bzip2
+        }
+        else {
+            Compression::None
+        }
+    }
+}
+
+/// Wraps `reader` in the matching decompressor. Raw rtcm starts with the 0xD3 preamble so never collides with the magic bytes above.
+pub fn decompress<R:Read + 'static>(reader:R) -> io::Result<(Compression, Box<dyn Read>)> {
+
+    let mut reader = BufReader::new(reader);
+    let compression = Compression::detect(reader.fill_buf()?);
+
+    let reader:Box<dyn Read> = match compression {
+        Compression::None => Box::new(reader),
+        // multi-member decoders so concatenated archives (e.g. cat a.gz b.gz) decode fully
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+        Compression::Bzip2 => Box::new(MultiBzDecoder::new(reader))
+    };
+
+    Ok((compression, reader))
+}
+
+pub fn open_file(file_path:&Path) -> io::Result<(Compression, Box<dyn Read>)> {
+    decompress(File::open(file_path)?)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::{Cursor, Write};
+
+    use crate::{RtcmData, RtcmDecoder};
+
+    use super::*;
+
+    const DEBUG_RTCM:&str = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm");
+
+    fn gzip(data:&[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn decode(compressed:Vec<u8>, expected:Compression) -> RtcmData {
+        let (compression, reader) = decompress(Cursor::new(compressed)).unwrap();
+        assert_eq!(compression, expected);
+
+        let mut rtcm_decoder = RtcmDecoder::new(false);
+        rtcm_decoder.load_reader(reader).unwrap();
+        rtcm_decoder.get_rtcm_data()
+    }
+
+    fn raw() -> (Vec<u8>, RtcmData) {
+        let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+        let mut rtcm_decoder = RtcmDecoder::new(false);
+        rtcm_decoder.decode_bytes(&rtcm_buffer).unwrap();
+        (rtcm_buffer, rtcm_decoder.get_rtcm_data())
+    }
+
+    #[test]
+    fn each_codec_matches_raw() {
+        let (rtcm_buffer, rtcm_data) = raw();
+        assert!(!rtcm_data.is_empty());
+
+        assert_eq!(decode(rtcm_buffer.clone(), Compression::None), rtcm_data);
+        assert_eq!(decode(gzip(&rtcm_buffer), Compression::Gzip), rtcm_data);
+        assert_eq!(decode(zstd::encode_all(rtcm_buffer.as_slice(), 0).unwrap(), Compression::Zstd), rtcm_data);
+
+        let mut bzip2_encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2_encoder.write_all(&rtcm_buffer).unwrap();
+        assert_eq!(decode(bzip2_encoder.finish().unwrap(), Compression::Bzip2), rtcm_data);
+    }
+
+    #[test]
+    fn concatenated_gzip_members() {
+        // cat a.gz b.gz, split in the middle of the first 1077 frame
+        let (rtcm_buffer, rtcm_data) = raw();
+        let mut concatenated = gzip(&rtcm_buffer[..200]);
+        concatenated.extend(gzip(&rtcm_buffer[200..]));
+
+        assert_eq!(decode(concatenated, Compression::Gzip), rtcm_data);
+    }
+
+    #[test]
+    fn zst_file_is_detected() {
+        let (rtcm_buffer, rtcm_data) = raw();
+        let path = std::env::temp_dir().join("rtcmlib_compression_test.rtcm.zst");
+        std::fs::write(&path, zstd::encode_all(rtcm_buffer.as_slice(), 0).unwrap()).unwrap();
+
+        let mut rtcm_decoder = RtcmDecoder::new(false);
+        rtcm_decoder.load_file(&path).unwrap();
+        assert_eq!(rtcm_decoder.get_rtcm_data(), rtcm_data);
+    }
+}
@@ -0,0 +1,624 @@
+// RTCM 3 encoding of observation data: MSM4/MSM7 for GPS, Galileo and BeiDou plus 1005/1033 station messages
+// see RTCM 10403.3 section 3.5.12 for the MSM layout; modeled on RTKLIB rtcm3e.c
+
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, str::FromStr};
+
+use hifitime::Unit;
+use nyx_space::cosmic::SPEED_OF_LIGHT;
+use rinex::{observation::{LliFlags, ObservationData}, prelude::{Carrier, Constellation, Epoch, Observable, SV}};
+
+use crate::{framing, quality, station::StationInfo, LockStatus, RANGE_MS, SECONDS_PER_WEEK};
+
+// satellite mask bits x signal mask bits of one message
+const MAX_CELLS:usize = 64;
+
+// DF397 value for "rough range not available"
+const INVALID_ROUGH_RANGE:u64 = 0xFF;
+
+// DF029/DF032/DF228/DF230/DF232 descriptors are at most 31 characters
+const MAX_DESCRIPTOR_LEN:usize = 31;
+
+/// MSB first bit packer for message payloads.
+pub struct BitWriter {
+    data:Vec<u8>,
+    // bits written
+    len:usize
+}
+
+impl Default for BitWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BitWriter {
+
+    pub fn new() -> Self {
+        Self {data:Vec::new(), len:0}
+    }
+
+    pub fn put_unsigned(&mut self, value:u64, bits:usize) {
+        for i in (0..bits).rev() {
+            if self.len % 8 == 0 {
+                self.data.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                *self.data.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+
+    /// Two's complement, `value` has to fit in `bits`.
+    pub fn put_signed(&mut self, value:i64, bits:usize) {
+        let mask = if bits >= 64 { u64::MAX } else { (1u64 << bits) - 1 };
+        self.put_unsigned(value as u64 & mask, bits);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Payload bytes, the last one zero padded.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MsmType {
+    /// full pseudorange and phase, DF402 lock time, 1 dB-Hz CNR
+    Msm4,
+    /// extended resolution, DF407 lock time, phase range rate
+    Msm7
+}
+
+impl FromStr for MsmType {
+    type Err = String;
+
+    fn from_str(msm:&str) -> Result<Self, Self::Err> {
+        match msm {
+            "4" | "msm4" => Ok(MsmType::Msm4),
+            "7" | "msm7" => Ok(MsmType::Msm7),
+            _ => Err(format!("unknown MSM type {}", msm))
+        }
+    }
+}
+
+fn message_number(constellation:Constellation, msm:MsmType) -> Option<u16> {
+    let base = match constellation {
+        Constellation::GPS => 1070,
+        Constellation::Galileo => 1090,
+        Constellation::BeiDou => 1120,
+        _ => return None
+    };
+    match msm {
+        MsmType::Msm4 => Some(base + 4),
+        MsmType::Msm7 => Some(base + 7)
+    }
+}
+
+// (constellation, RINEX signal code, MSM signal id), see RTCM 10403.3 tables 3.5-91 (GPS), 3.5-100 (Galileo) and 3.5-108 (BeiDou)
+const MSM_SIGNALS:&[(Constellation, &str, u8)] = &[
+    (Constellation::GPS, "1C", 2),
+    (Constellation::GPS, "1P", 3),
+    (Constellation::GPS, "1W", 4),
+    (Constellation::GPS, "2C", 8),
+    (Constellation::GPS, "2P", 9),
+    (Constellation::GPS, "2W", 10),
+    (Constellation::GPS, "2S", 15),
+    (Constellation::GPS, "2L", 16),
+    (Constellation::GPS, "2X", 17),
+    (Constellation::GPS, "5I", 22),
+    (Constellation::GPS, "5Q", 23),
+    (Constellation::GPS, "5X", 24),
+    (Constellation::GPS, "1S", 30),
+    (Constellation::GPS, "1L", 31),
+    (Constellation::GPS, "1X", 32),
+    (Constellation::Galileo, "1C", 2),
+    (Constellation::Galileo, "1A", 3),
+    (Constellation::Galileo, "1B", 4),
+    (Constellation::Galileo, "1X", 5),
+    (Constellation::Galileo, "1Z", 6),
+    (Constellation::Galileo, "6C", 8),
+    (Constellation::Galileo, "6A", 9),
+    (Constellation::Galileo, "6B", 10),
+    (Constellation::Galileo, "6X", 11),
+    (Constellation::Galileo, "6Z", 12),
+    (Constellation::Galileo, "7I", 14),
+    (Constellation::Galileo, "7Q", 15),
+    (Constellation::Galileo, "7X", 16),
+    (Constellation::Galileo, "8I", 18),
+    (Constellation::Galileo, "8Q", 19),
+    (Constellation::Galileo, "8X", 20),
+    (Constellation::Galileo, "5I", 22),
+    (Constellation::Galileo, "5Q", 23),
+    (Constellation::Galileo, "5X", 24),
+    (Constellation::BeiDou, "2I", 2),
+    (Constellation::BeiDou, "2Q", 3),
+    (Constellation::BeiDou, "2X", 4),
+    (Constellation::BeiDou, "6I", 8),
+    (Constellation::BeiDou, "6Q", 9),
+    (Constellation::BeiDou, "6X", 10),
+    (Constellation::BeiDou, "7I", 14),
+    (Constellation::BeiDou, "7Q", 15),
+    (Constellation::BeiDou, "7X", 16),
+    (Constellation::BeiDou, "5D", 22),
+    (Constellation::BeiDou, "5P", 23),
+    (Constellation::BeiDou, "5X", 24),
+    (Constellation::BeiDou, "7D", 25),
+    (Constellation::BeiDou, "1D", 30),
+    (Constellation::BeiDou, "1P", 31),
+    (Constellation::BeiDou, "1X", 32),
+];
+
+/// MSM signal id (1-32) of a RINEX signal code.
+pub fn msm_signal_id(constellation:Constellation, code:&str) -> Option<u8> {
+    MSM_SIGNALS.iter().find(|(c, s, _)| *c == constellation && *s == code).map(|(_, _, id)| *id)
+}
+
+/// RINEX signal code of an MSM signal id, the inverse of `msm_signal_id`.
+pub fn msm_signal_code(constellation:Constellation, signal_id:u8) -> Option<&'static str> {
+    MSM_SIGNALS.iter().find(|(c, _, id)| *c == constellation && *id == signal_id).map(|(_, code, _)| *code)
+}
+
+/// Constellation of an MSM message number (1071-1137).
+pub fn msm_constellation(message_number:u16) -> Option<Constellation> {
+    match message_number {
+        1071..=1077 => Some(Constellation::GPS),
+        1081..=1087 => Some(Constellation::Glonass),
+        1091..=1097 => Some(Constellation::Galileo),
+        1101..=1107 => Some(Constellation::SBAS),
+        1111..=1117 => Some(Constellation::QZSS),
+        1121..=1127 => Some(Constellation::BeiDou),
+        _ => None
+    }
+}
+
+/// DF402 indicator for a lock time (ms), the inverse of `quality::df402_minimum_lock_time`.
+pub fn df402_indicator(lock_time_ms:u64) -> u16 {
+    (0..=15).rev().find(|i| quality::df402_minimum_lock_time(*i) <= lock_time_ms).unwrap_or(0)
+}
+
+/// DF407 indicator for a lock time (ms), the inverse of the DF407 minimum lock time table.
+pub fn df407_indicator(lock_time_ms:u64) -> u16 {
+    (0..=704).rev().find(|i| LockStatus::calculate_minimum_lock_time(*i) <= lock_time_ms).unwrap_or(0)
+}
+
+// epoch time field of the MSM header (ms of week in the constellation's time scale)
+fn epoch_time_ms(constellation:Constellation, epoch:Epoch) -> u64 {
+    let seconds = match constellation {
+        Constellation::Galileo => epoch.to_gst_seconds(),
+        Constellation::BeiDou => epoch.to_bdt_seconds(),
+        _ => epoch.to_gpst_seconds()
+    };
+    ((seconds * 1000.0).round() as u64) % (SECONDS_PER_WEEK * 1000)
+}
+
+// value in units of `lsb` as a `bits` wide signed field, the field's "not available" value when missing or out of range
+fn scaled(value:Option<f64>, lsb:f64, bits:usize) -> i64 {
+    let invalid = -(1i64 << (bits - 1));
+    match value {
+        Some(value) => {
+            let counts = (value / lsb).round() as i64;
+            if counts > invalid && counts < -invalid { counts } else { invalid }
+        }
+        None => invalid
+    }
+}
+
+// one signal of a satellite, in MSM units
+struct Cell {
+    signal_id:u8,
+    // ms
+    pseudo_range:Option<f64>,
+    // ms
+    phase_range:Option<f64>,
+    // m/s
+    phase_range_rate:Option<f64>,
+    // dB-Hz
+    cnr:Option<f64>,
+    lock_time_ms:u64,
+    half_cycle_ambiguity:bool
+}
+
+struct Satellite {
+    prn:u8,
+    // ms, multiple of 2^-10
+    rough_range:Option<f64>,
+    // m/s, whole
+    rough_phase_range_rate:Option<f64>,
+    cells:Vec<Cell>
+}
+
+// lock tracking per signal for the lock time indicator
+struct Lock {
+    start:Epoch,
+    // start moved back by the first observation interval
+    anchored:bool
+}
+
+/// Encodes observation epochs as MSM frames. Keeps per signal lock history (for the lock time indicator)
+/// and phase offsets, so epochs have to be passed in order.
+pub struct RtcmEncoder {
+    station_id:u16,
+    msm:MsmType,
+    locks:HashMap<(SV, String), Lock>,
+    // whole cycles taken off phase so it fits the fine phase range field
+    phase_offsets:HashMap<(SV, String), f64>,
+    skipped_signals:BTreeMap<(Constellation, String), u64>
+}
+
+impl RtcmEncoder {
+
+    pub fn new(station_id:u16, msm:MsmType) -> Self {
+        Self {station_id, msm, locks:HashMap::new(), phase_offsets:HashMap::new(), skipped_signals:BTreeMap::new()}
+    }
+
+    /// Signals left out because the constellation or signal has no MSM mapping here (e.g. GLONASS, QZSS).
+    pub fn get_skipped_signals(&self) -> &BTreeMap<(Constellation, String), u64> {
+        &self.skipped_signals
+    }
+
+    // ms since lock (re)start; the start is moved back by one observation interval once the next
+    // observation arrives, so decoders applying the DF407 rules don't read the second epoch as a possible slip
+    fn lock_time_ms(&mut self, key:(SV, String), epoch:Epoch, lock_loss:bool) -> u64 {
+
+        if lock_loss || !self.locks.contains_key(&key) {
+            self.locks.insert(key, Lock {start:epoch, anchored:false});
+            return 0;
+        }
+
+        let lock = self.locks.get_mut(&key).unwrap();
+        if !lock.anchored {
+            lock.start = lock.start - (epoch - lock.start);
+            lock.anchored = true;
+        }
+
+        (epoch - lock.start).to_unit(Unit::Millisecond).round() as u64
+    }
+
+    fn satellite(&mut self, sv:SV, epoch:Epoch, observations:&HashMap<Observable, ObservationData>) -> Option<Satellite> {
+
+        if sv.prn < 1 || sv.prn > 64 {
+            return None;
+        }
+
+        // signals sorted by MSM signal id, with their wavelength (m)
+        let mut signals:BTreeMap<u8, (String, f64)> = BTreeMap::new();
+        for observable in observations.keys() {
+            let code = match observable.code() {
+                Some(code) => code,
+                None => continue
+            };
+            let carrier = Carrier::from_observable(sv.constellation, &Observable::Phase(format!("L{}", code)));
+            match (msm_signal_id(sv.constellation, &code), carrier) {
+                (Some(signal_id), Ok(carrier)) => {
+                    signals.insert(signal_id, (code, SPEED_OF_LIGHT / carrier.frequency()));
+                }
+                _ => {
+                    if let Observable::PseudoRange(_) = observable {
+                        *self.skipped_signals.entry((sv.constellation, code)).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        if signals.is_empty() {
+            return None;
+        }
+
+        let observation = |prefix:char, code:&String| observations.get(&match prefix {
+            'C' => Observable::PseudoRange(format!("C{}", code)),
+            'L' => Observable::Phase(format!("L{}", code)),
+            'D' => Observable::Doppler(format!("D{}", code)),
+            _ => Observable::SSI(format!("S{}", code))
+        });
+
+        // rough range from the first signal with code (or phase), rounded to the DF398 resolution
+        let rough_range = signals.values()
+            .find_map(|(code, lambda)| observation('C', code).map(|c| c.obs).or(observation('L', code).map(|l| l.obs * lambda)))
+            .map(|range| (range / RANGE_MS * 1024.0).round() / 1024.0)
+            .filter(|range| *range >= 0.0 && *range < INVALID_ROUGH_RANGE as f64);
+
+        // doppler (Hz) is the negative range rate in cycles
+        let rough_phase_range_rate = signals.values()
+            .find_map(|(code, lambda)| observation('D', code).map(|d| (-d.obs * lambda).round()));
+
+        let mut cells:Vec<Cell> = Vec::new();
+
+        for (signal_id, (code, lambda)) in signals.iter() {
+
+            let key = (sv, code.clone());
+            let phase = observation('L', code);
+
+            let mut lock_loss = phase.and_then(|l| l.lli).map(|lli| lli.intersects(LliFlags::LOCK_LOSS)).unwrap_or(false);
+            let half_cycle_ambiguity = phase.and_then(|l| l.lli).map(|lli| lli.intersects(LliFlags::HALF_CYCLE_SLIP)).unwrap_or(false);
+
+            let mut phase_range:Option<f64> = None;
+            if let (Some(phase), Some(rough_range)) = (phase, rough_range) {
+                let cycles = phase.obs;
+                let offset = *self.phase_offsets.get(&key).unwrap_or(&0.0);
+                let mut fine = (cycles - offset) * lambda / RANGE_MS - rough_range;
+                // arbitrary RINEX phase ambiguity (or code/phase divergence) outside the fine phase range:
+                // move phase next to the rough range by whole cycles, which restarts the lock
+                if fine.abs() >= 1.0 / 256.0 {
+                    let offset = ((cycles * lambda - rough_range * RANGE_MS) / lambda).round();
+                    self.phase_offsets.insert(key.clone(), offset);
+                    fine = (cycles - offset) * lambda / RANGE_MS - rough_range;
+                    lock_loss = true;
+                }
+                phase_range = Some(rough_range + fine);
+            }
+
+            let lock_time_ms = if phase.is_some() { self.lock_time_ms(key, epoch, lock_loss) } else { 0 };
+
+            cells.push(Cell {
+                signal_id: *signal_id,
+                pseudo_range: observation('C', code).map(|c| c.obs / RANGE_MS),
+                phase_range,
+                phase_range_rate: observation('D', code).map(|d| -d.obs * lambda),
+                cnr: observation('S', code).map(|s| s.obs),
+                lock_time_ms,
+                half_cycle_ambiguity
+            });
+        }
+
+        Some(Satellite {prn: sv.prn, rough_range, rough_phase_range_rate, cells})
+    }
+
+    /// Frames for one epoch: one MSM per constellation (more when the cell mask would exceed 64 cells),
+    /// with the multiple message bit set on all but the last.
+    pub fn encode_epoch(&mut self, epoch:Epoch, satellites:&BTreeMap<SV, HashMap<Observable, ObservationData>>) -> Vec<Vec<u8>> {
+
+        let mut by_constellation:BTreeMap<u16, (Constellation, Vec<Satellite>)> = BTreeMap::new();
+
+        for (sv, observations) in satellites.iter() {
+            let message_number = match message_number(sv.constellation, self.msm) {
+                Some(message_number) => message_number,
+                None => {
+                    for observable in observations.keys().filter(|o| matches!(o, Observable::PseudoRange(_))) {
+                        *self.skipped_signals.entry((sv.constellation, observable.code().unwrap_or_default())).or_insert(0) += 1;
+                    }
+                    continue;
+                }
+            };
+            if let Some(satellite) = self.satellite(*sv, epoch, observations) {
+                by_constellation.entry(message_number).or_insert((sv.constellation, Vec::new())).1.push(satellite);
+            }
+        }
+
+        let mut messages:Vec<(u16, Constellation, Vec<Satellite>)> = Vec::new();
+        for (message_number, (constellation, satellites)) in by_constellation {
+            for chunk in cell_mask_chunks(satellites) {
+                messages.push((message_number, constellation, chunk));
+            }
+        }
+
+        let count = messages.len();
+        messages.iter().enumerate()
+            .map(|(i, (message_number, constellation, satellites))| {
+                let payload = self.msm_payload(*message_number, epoch_time_ms(*constellation, epoch), satellites, i + 1 < count);
+                framing::encode_frame(&payload)
+            })
+            .collect()
+    }
+
+    fn msm_payload(&self, message_number:u16, epoch_time_ms:u64, satellites:&[Satellite], multiple_message:bool) -> Vec<u8> {
+
+        let signal_ids:BTreeSet<u8> = satellites.iter().flat_map(|s| s.cells.iter().map(|c| c.signal_id)).collect();
+
+        let mut w = BitWriter::new();
+
+        w.put_unsigned(message_number as u64, 12);
+        w.put_unsigned(self.station_id as u64, 12);
+        w.put_unsigned(epoch_time_ms, 30);
+        w.put_unsigned(multiple_message as u64, 1);
+        w.put_unsigned(0, 3);  // IODS
+        w.put_unsigned(0, 7);  // reserved
+        w.put_unsigned(0, 2);  // clock steering
+        w.put_unsigned(0, 2);  // external clock
+        w.put_unsigned(0, 1);  // divergence free smoothing
+        w.put_unsigned(0, 3);  // smoothing interval
+
+        let satellite_mask = satellites.iter().fold(0u64, |mask, s| mask | 1u64 << (64 - s.prn as u64));
+        w.put_unsigned(satellite_mask, 64);
+
+        let signal_mask = signal_ids.iter().fold(0u64, |mask, id| mask | 1u64 << (32 - *id as u64));
+        w.put_unsigned(signal_mask, 32);
+
+        // cells in satellite then signal order
+        let mut cells:Vec<(&Satellite, &Cell)> = Vec::new();
+        for satellite in satellites.iter() {
+            for signal_id in signal_ids.iter() {
+                let cell = satellite.cells.iter().find(|c| c.signal_id == *signal_id);
+                w.put_unsigned(cell.is_some() as u64, 1);
+                if let Some(cell) = cell {
+                    cells.push((satellite, cell));
+                }
+            }
+        }
+
+        let extended = self.msm == MsmType::Msm7;
+
+        // satellite data
+        for satellite in satellites.iter() {
+            w.put_unsigned(satellite.rough_range.map(|r| r.floor() as u64).unwrap_or(INVALID_ROUGH_RANGE), 8);
+        }
+        if extended {
+            for _ in satellites.iter() {
+                w.put_unsigned(0, 4);  // extended satellite info
+            }
+        }
+        for satellite in satellites.iter() {
+            w.put_unsigned(satellite.rough_range.map(|r| ((r - r.floor()) * 1024.0).round() as u64).unwrap_or(0), 10);
+        }
+        if extended {
+            for satellite in satellites.iter() {
+                w.put_signed(scaled(satellite.rough_phase_range_rate, 1.0, 14), 14);
+            }
+        }
+
+        // signal data, fine values relative to the satellite's rough values
+        let fine = |value:Option<f64>, rough:Option<f64>| match (value, rough) {
+            (Some(value), Some(rough)) => Some(value - rough),
+            _ => None
+        };
+
+        if extended {
+            for (s, c) in cells.iter() {
+                w.put_signed(scaled(fine(c.pseudo_range, s.rough_range), 2f64.powi(-29), 20), 20);
+            }
+            for (s, c) in cells.iter() {
+                w.put_signed(scaled(fine(c.phase_range, s.rough_range), 2f64.powi(-31), 24), 24);
+            }
+            for (_, c) in cells.iter() {
+                w.put_unsigned(df407_indicator(c.lock_time_ms) as u64, 10);
+            }
+            for (_, c) in cells.iter() {
+                w.put_unsigned(c.half_cycle_ambiguity as u64, 1);
+            }
+            for (_, c) in cells.iter() {
+                w.put_unsigned(c.cnr.map(|cnr| (cnr * 16.0).round().clamp(0.0, 1023.0) as u64).unwrap_or(0), 10);
+            }
+            for (s, c) in cells.iter() {
+                w.put_signed(scaled(fine(c.phase_range_rate, s.rough_phase_range_rate), 0.0001, 15), 15);
+            }
+        }
+        else {
+            for (s, c) in cells.iter() {
+                w.put_signed(scaled(fine(c.pseudo_range, s.rough_range), 2f64.powi(-24), 15), 15);
+            }
+            for (s, c) in cells.iter() {
+                w.put_signed(scaled(fine(c.phase_range, s.rough_range), 2f64.powi(-29), 22), 22);
+            }
+            for (_, c) in cells.iter() {
+                w.put_unsigned(df402_indicator(c.lock_time_ms) as u64, 4);
+            }
+            for (_, c) in cells.iter() {
+                w.put_unsigned(c.half_cycle_ambiguity as u64, 1);
+            }
+            for (_, c) in cells.iter() {
+                w.put_unsigned(c.cnr.map(|cnr| cnr.round().clamp(0.0, 63.0) as u64).unwrap_or(0), 6);
+            }
+        }
+
+        w.into_bytes()
+    }
+
+    /// Stationary reference station ARP (ECEF, m).
+    pub fn encode_1005(&self, position:(f64, f64, f64), constellations:&BTreeSet<Constellation>) -> Vec<u8> {
+
+        let mut w = BitWriter::new();
+
+        w.put_unsigned(1005, 12);
+        w.put_unsigned(self.station_id as u64, 12);
+        w.put_unsigned(0, 6);  // ITRF realization year
+        w.put_unsigned(constellations.contains(&Constellation::GPS) as u64, 1);
+        w.put_unsigned(constellations.contains(&Constellation::Glonass) as u64, 1);
+        w.put_unsigned(constellations.contains(&Constellation::Galileo) as u64, 1);
+        w.put_unsigned(0, 1);  // reference station (not a virtual one)
+        w.put_signed((position.0 / 0.0001).round() as i64, 38);
+        w.put_unsigned(0, 1);  // single receiver oscillator
+        w.put_unsigned(0, 1);  // reserved
+        w.put_signed((position.1 / 0.0001).round() as i64, 38);
+        w.put_unsigned(0, 2);  // quarter cycle indicator
+        w.put_signed((position.2 / 0.0001).round() as i64, 38);
+
+        framing::encode_frame(&w.into_bytes())
+    }
+
+    /// Receiver and antenna descriptors; missing descriptors are sent empty.
+    pub fn encode_1033(&self, station:&StationInfo) -> Vec<u8> {
+
+        let mut w = BitWriter::new();
+
+        w.put_unsigned(1033, 12);
+        w.put_unsigned(self.station_id as u64, 12);
+        put_descriptor(&mut w, &station.antenna_descriptor);
+        w.put_unsigned(station.antenna_setup_id.unwrap_or(0) as u64, 8);
+        put_descriptor(&mut w, &station.antenna_serial_number);
+        put_descriptor(&mut w, &station.receiver_type);
+        put_descriptor(&mut w, &station.receiver_firmware_version);
+        put_descriptor(&mut w, &station.receiver_serial_number);
+
+        framing::encode_frame(&w.into_bytes())
+    }
+}
+
+// counter followed by the ASCII characters
+fn put_descriptor(w:&mut BitWriter, descriptor:&Option<String>) {
+    let bytes:Vec<u8> = descriptor.as_deref().unwrap_or("").bytes().filter(|b| b.is_ascii()).take(MAX_DESCRIPTOR_LEN).collect();
+    w.put_unsigned(bytes.len() as u64, 8);
+    for b in bytes {
+        w.put_unsigned(b as u64, 8);
+    }
+}
+
+// satellites grouped so satellites x signals stays within the 64 bit cell mask
+fn cell_mask_chunks(satellites:Vec<Satellite>) -> Vec<Vec<Satellite>> {
+
+    let mut chunks:Vec<Vec<Satellite>> = Vec::new();
+    let mut chunk:Vec<Satellite> = Vec::new();
+    let mut signal_ids:BTreeSet<u8> = BTreeSet::new();
+
+    for satellite in satellites {
+        let mut with:BTreeSet<u8> = signal_ids.clone();
+        with.extend(satellite.cells.iter().map(|c| c.signal_id));
+
+        if !chunk.is_empty() && (chunk.len() + 1) * with.len() > MAX_CELLS {
+            chunks.push(std::mem::take(&mut chunk));
+            signal_ids = satellite.cells.iter().map(|c| c.signal_id).collect();
+        }
+        else {
+            signal_ids = with;
+        }
+        chunk.push(satellite);
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn bit_writer_packs_msb_first() {
+        let mut w = BitWriter::new();
+        w.put_unsigned(1005, 12);
+        w.put_signed(-1, 4);
+        w.put_unsigned(1, 1);
+        assert_eq!(w.len(), 17);
+        assert_eq!(w.into_bytes(), vec![0x3E, 0xDF, 0x80]);
+    }
+
+    #[test]
+    fn lock_time_indicators_invert_tables() {
+        for i in 0..=704 {
+            assert_eq!(df407_indicator(LockStatus::calculate_minimum_lock_time(i)), i);
+        }
+        for i in 1..=15 {
+            assert_eq!(df402_indicator(quality::df402_minimum_lock_time(i)), i);
+        }
+        assert_eq!(df402_indicator(31), 0);
+        assert_eq!(df407_indicator(1000), 190);
+    }
+
+    #[test]
+    fn out_of_range_fields_are_invalid() {
+        assert_eq!(scaled(Some(1.0), 0.0001, 15), 10000);
+        assert_eq!(scaled(Some(2.0), 0.0001, 15), -16384);
+        assert_eq!(scaled(None, 1.0, 14), -8192);
+    }
+}
@@ -0,0 +1,60 @@
+use std::{fmt, io};
+
+use rinex::prelude::Constellation;
+
+#[derive(Debug)]
+pub enum RtcmError {
+    /// input could not be opened or read
+    Io(io::Error),
+    /// frame failed the CRC-24Q check
+    CrcMismatch { message_number:Option<u16> },
+    /// frame passed CRC but the message body could not be decoded
+    CorruptMessage { message_number:Option<u16> },
+    /// MSM signal id without a RINEX carrier mapping
+    UnknownSignal { constellation:Constellation, code:String },
+    /// MSM signal cell referencing a satellite missing from the satellite data
+    MissingSatellite { constellation:Constellation, satellite_id:u8 }
+}
+
+impl fmt::Display for RtcmError {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RtcmError::Io(e) => write!(f, "i/o error: {}", e),
+            RtcmError::CrcMismatch { message_number } => write!(f, "crc mismatch (message {:?})", message_number),
+            RtcmError::CorruptMessage { message_number } => write!(f, "corrupt message {:?}", message_number),
+            RtcmError::UnknownSignal { constellation, code } => write!(f, "unknown {:?} signal {}", constellation, code),
+            RtcmError::MissingSatellite { constellation, satellite_id } => write!(f, "missing {:?} satellite data for id {}", constellation, satellite_id)
+        }
+    }
+}
+
+impl std::error::Error for RtcmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RtcmError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for RtcmError {
+    fn from(e:io::Error) -> Self {
+        RtcmError::Io(e)
+    }
+}
+
+/// What the decoder does with corrupt frames and unmappable signals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorPolicy {
+    /// drop the frame/signal, count it and keep decoding
+    Skip,
+    /// stop decoding and return the error
+    FailFast
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        ErrorPolicy::Skip
+    }
+}
@@ -0,0 +1,237 @@
+// long format (one row per epoch/sv/observable) exports of decoded observations for dataframe tools
+
+use std::io::{self, Write};
+
+use rinex::observation::LliFlags;
+
+use crate::{QualityData, RtcmData};
+
+pub const COLUMNS:[&str;6] = ["epoch", "sv", "observable", "value", "lli", "snr"];
+
+pub const QUALITY_COLUMNS:[&str;8] = ["epoch", "sv", "signal", "lock_time_indicator", "extended_lock_time", "minimum_lock_time_ms", "half_cycle_ambiguity", "extended_satellite_info"];
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExportRow {
+    pub epoch:String,
+    pub sv:String,
+    pub observable:String,
+    pub value:f64,
+    /// RINEX LLI bits
+    pub lli:Option<u8>,
+    /// RINEX signal strength digit (1-9)
+    pub snr:Option<u8>
+}
+
+/// Flattens decoded data into rows ordered by epoch, sv and observable, one epoch at a time.
+pub fn rows(rtcm_data:&RtcmData) -> impl Iterator<Item = ExportRow> + '_ {
+
+    rtcm_data.iter().flat_map(|((epoch, _), (_, satellites))| {
+
+        let epoch = epoch.to_string();
+        let mut rows:Vec<ExportRow> = Vec::new();
+
+        for (sv, observations) in satellites.iter() {
+
+            let mut observables:Vec<_> = observations.iter().collect();
+            observables.sort_by_key(|(observable, _)| observable.to_string());
+
+            for (observable, observation) in observables {
+                rows.push(ExportRow {
+                    epoch: epoch.clone(),
+                    sv: sv.to_string(),
+                    observable: observable.to_string(),
+                    value: observation.obs,
+                    lli: observation.lli.map(|lli:LliFlags| lli.bits()),
+                    snr: observation.snr.map(|snr| snr as u8)
+                });
+            }
+        }
+
+        rows
+    })
+}
+
+fn optional(value:Option<u8>, null:&str) -> String {
+    value.map(|v| v.to_string()).unwrap_or(null.to_string())
+}
+
+// JSON has no NaN or infinity
+fn json_number(value:f64) -> String {
+    if value.is_finite() { value.to_string() } else { "null".to_string() }
+}
+
+pub fn write_csv<W:Write>(rtcm_data:&RtcmData, mut writer:W) -> io::Result<()> {
+
+    writeln!(writer, "{}", COLUMNS.join(","))?;
+
+    for row in rows(rtcm_data) {
+        writeln!(writer, "{},{},{},{},{},{}", row.epoch, row.sv, row.observable, row.value, optional(row.lli, ""), optional(row.snr, ""))?;
+    }
+
+    writer.flush()
+}
+
+pub fn write_ndjson<W:Write>(rtcm_data:&RtcmData, mut writer:W) -> io::Result<()> {
+
+    for row in rows(rtcm_data) {
+        writeln!(writer, "{{\"epoch\":\"{}\",\"sv\":\"{}\",\"observable\":\"{}\",\"value\":{},\"lli\":{},\"snr\":{}}}",
+            row.epoch, row.sv, row.observable, json_number(row.value), optional(row.lli, "null"), optional(row.snr, "null"))?;
+    }
+
+    writer.flush()
+}
+
+/// One row per epoch/sv/signal of the decoder's signal quality table, the header row only when `header` is set
+/// so sessions can be appended to the same file.
+pub fn write_quality_csv<W:Write>(quality:&QualityData, mut writer:W, header:bool) -> io::Result<()> {
+
+    if header {
+        writeln!(writer, "{}", QUALITY_COLUMNS.join(","))?;
+    }
+
+    for ((epoch, sv, code), q) in quality.iter() {
+        writeln!(writer, "{},{},{},{},{},{},{},{}", epoch, sv, code, q.lock_time_indicator, q.extended_lock_time, q.minimum_lock_time_ms,
+            q.half_cycle_ambiguity, optional(q.extended_satellite_info, ""))?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(feature = "parquet")]
+pub fn write_parquet<W:Write + Send>(rtcm_data:&RtcmData, writer:W) -> io::Result<()> {
+
+    use std::sync::Arc;
+
+    use arrow::{array::{ArrayRef, Float64Array, StringArray, UInt8Array}, datatypes::{DataType, Field, Schema}, record_batch::RecordBatch};
+    use parquet::arrow::ArrowWriter;
+
+    // rows are built one record batch at a time
+    const BATCH_ROWS:usize = 65536;
+
+    let to_io = |e:&dyn std::fmt::Display| io::Error::new(io::ErrorKind::Other, e.to_string());
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("epoch", DataType::Utf8, false),
+        Field::new("sv", DataType::Utf8, false),
+        Field::new("observable", DataType::Utf8, false),
+        Field::new("value", DataType::Float64, false),
+        Field::new("lli", DataType::UInt8, true),
+        Field::new("snr", DataType::UInt8, true)
+    ]));
+
+    let mut parquet_writer = ArrowWriter::try_new(writer, schema.clone(), None).map_err(|e| to_io(&e))?;
+
+    let mut rows = rows(rtcm_data).peekable();
+
+    while rows.peek().is_some() {
+        let chunk:Vec<ExportRow> = rows.by_ref().take(BATCH_ROWS).collect();
+        let columns:Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(chunk.iter().map(|r| r.epoch.as_str()))),
+            Arc::new(StringArray::from_iter_values(chunk.iter().map(|r| r.sv.as_str()))),
+            Arc::new(StringArray::from_iter_values(chunk.iter().map(|r| r.observable.as_str()))),
+            Arc::new(Float64Array::from_iter_values(chunk.iter().map(|r| r.value))),
+            Arc::new(UInt8Array::from_iter(chunk.iter().map(|r| r.lli))),
+            Arc::new(UInt8Array::from_iter(chunk.iter().map(|r| r.snr)))
+        ];
+
+        let batch = RecordBatch::try_new(schema.clone(), columns).map_err(|e| to_io(&e))?;
+        parquet_writer.write(&batch).map_err(|e| to_io(&e))?;
+    }
+
+    parquet_writer.close().map_err(|e| to_io(&e))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::{BTreeMap, HashMap};
+
+    use rinex::{observation::{EpochFlag, ObservationData}, prelude::{Constellation, Epoch, Observable, SV}};
+
+    use super::*;
+
+    fn rtcm_data() -> RtcmData {
+        let epoch = Epoch::from_gpst_seconds(1_400_000_000.0);
+        let sv = SV {constellation: Constellation::GPS, prn: 5};
+
+        let mut observations:HashMap<Observable, ObservationData> = HashMap::new();
+        observations.insert(Observable::PseudoRange("C1C".to_string()), ObservationData {obs: 21000000.5, lli: None, snr: None});
+        observations.insert(Observable::Phase("L1C".to_string()), ObservationData {obs: 110000000.25, lli: Some(LliFlags::LOCK_LOSS), snr: None});
+        observations.insert(Observable::Doppler("D1C".to_string()), ObservationData {obs: f64::NAN, lli: None, snr: None});
+
+        let mut satellites = BTreeMap::new();
+        satellites.insert(sv, observations);
+
+        let mut rtcm_data:RtcmData = BTreeMap::new();
+        rtcm_data.insert((epoch, EpochFlag::Ok), (None, satellites));
+        rtcm_data
+    }
+
+    #[test]
+    fn rows_are_sorted_by_observable() {
+        let rows:Vec<ExportRow> = rows(&rtcm_data()).collect();
+        let observables:Vec<&str> = rows.iter().map(|row| row.observable.as_str()).collect();
+        assert_eq!(observables, vec!["C1C", "D1C", "L1C"]);
+        assert_eq!(rows[2].lli, Some(LliFlags::LOCK_LOSS.bits()));
+    }
+
+    #[test]
+    fn csv_rows() {
+        let mut out:Vec<u8> = Vec::new();
+        write_csv(&rtcm_data(), &mut out).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let lines:Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "epoch,sv,observable,value,lli,snr");
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].ends_with(",G05,C1C,21000000.5,,"));
+        assert!(lines[3].ends_with(",G05,L1C,110000000.25,1,"));
+    }
+
+    #[test]
+    fn ndjson_writes_null_for_nan() {
+        let mut out:Vec<u8> = Vec::new();
+        write_ndjson(&rtcm_data(), &mut out).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let lines:Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains("\"observable\":\"D1C\",\"value\":null,"));
+        assert!(!text.contains("NaN"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn ndjson_lines_parse() {
+        let mut out:Vec<u8> = Vec::new();
+        write_ndjson(&rtcm_data(), &mut out).unwrap();
+
+        for line in String::from_utf8(out).unwrap().lines() {
+            let row:serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(row["sv"], "G05");
+        }
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_round_trip() {
+        use arrow::array::{Array, Float64Array, StringArray};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let path = std::env::temp_dir().join("rtcmlib_export.parquet");
+        write_parquet(&rtcm_data(), std::fs::File::create(&path).unwrap()).unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap()).unwrap().build().unwrap();
+        let batches:Vec<_> = reader.map(|batch| batch.unwrap()).collect();
+        assert_eq!(batches.iter().map(|batch| batch.num_rows()).sum::<usize>(), 3);
+
+        let observables = batches[0].column(2).as_any().downcast_ref::<StringArray>().unwrap();
+        let values = batches[0].column(3).as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(observables.value(0), "C1C");
+        assert_eq!(values.value(0), 21000000.5);
+        assert!(batches[0].column(4).is_null(0));
+    }
+}
@@ -0,0 +1,194 @@
+// constellation / satellite / signal / observable selection applied by RtcmDecoder before data is stored
+
+use std::str::FromStr;
+
+use hifitime::Duration;
+use rinex::prelude::{Constellation, Epoch, SV};
+
+/// Empty lists keep everything.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignalFilter {
+    /// constellations to keep
+    pub systems:Vec<Constellation>,
+    /// satellites to drop
+    pub exclude_sv:Vec<SV>,
+    /// signal codes to keep, e.g. 1C, 5Q
+    pub signals:Vec<String>,
+    /// observable types to keep: C (pseudorange), L (phase), D (doppler), S (signal strength)
+    pub observables:Vec<char>
+}
+
+impl SignalFilter {
+
+    pub fn keep_signal(&self, sv:&SV, code:&str) -> bool {
+        (self.systems.is_empty() || self.systems.contains(&sv.constellation))
+            && !self.exclude_sv.contains(sv)
+            && (self.signals.is_empty() || self.signals.iter().any(|s| s == code))
+    }
+
+    pub fn keep_observable(&self, observable_type:char) -> bool {
+        self.observables.is_empty() || self.observables.contains(&observable_type)
+    }
+}
+
+/// Epoch range and decimation. Epochs outside the window or off the interval grid aren't stored.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimeWindow {
+    /// first epoch kept (inclusive)
+    pub start:Option<Epoch>,
+    /// last epoch kept (inclusive)
+    pub end:Option<Epoch>,
+    /// keep epochs on this grid, aligned to GPS time, e.g. 30 s keeps :00 and :30
+    pub interval:Option<Duration>
+}
+
+impl TimeWindow {
+
+    pub fn contains(&self, epoch:Epoch) -> bool {
+        (self.start.is_none() || epoch >= self.start.unwrap()) && (self.end.is_none() || epoch <= self.end.unwrap())
+    }
+
+    pub fn on_interval(&self, epoch:Epoch) -> bool {
+        match self.interval {
+            Some(interval) => {
+                // compare in ms, MSM epoch times are integer milliseconds
+                let interval_ms = (interval.to_seconds() * 1000.0).round() as i64;
+                let epoch_ms = (epoch.to_gpst_seconds() * 1000.0).round() as i64;
+                interval_ms <= 0 || epoch_ms % interval_ms == 0
+            }
+            None => true
+        }
+    }
+}
+
+/// Constellation from a RINEX system letter or name, e.g. G, E, C, GPS.
+pub fn parse_constellation(system:&str) -> Result<Constellation, String> {
+    Constellation::from_str(system.trim()).map_err(|_| format!("unknown constellation: {}", system))
+}
+
+/// Satellite in RINEX notation, e.g. G05, E14.
+pub fn parse_sv(sv:&str) -> Result<SV, String> {
+    SV::from_str(sv.trim()).map_err(|_| format!("invalid satellite: {}", sv))
+}
+
+/// Epoch in any format hifitime reads, e.g. 2024-12-30T02:00:00 GPST.
+pub fn parse_epoch(epoch:&str) -> Result<Epoch, String> {
+    Epoch::from_str(epoch.trim()).map_err(|_| format!("invalid epoch: {}", epoch))
+}
+
+/// Decimation interval in seconds.
+pub fn parse_interval(interval:&str) -> Result<Duration, String> {
+    match interval.trim().parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 => Ok(Duration::from_seconds(seconds)),
+        _ => Err(format!("invalid interval (expected seconds > 0): {}", interval))
+    }
+}
+
+/// Two character signal code, e.g. 1C, 5Q.
+pub fn parse_signal(code:&str) -> Result<String, String> {
+    let code = code.trim().to_uppercase();
+    let mut chars = code.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(band), Some(_), None) if band.is_ascii_digit() => Ok(code),
+        _ => Err(format!("invalid signal code (expected band and attribute, e.g. 1C): {}", code))
+    }
+}
+
+/// Observable type letter: C, L, D or S.
+pub fn parse_observable(observable:&str) -> Result<char, String> {
+    match observable.trim().to_uppercase().as_str() {
+        "C" => Ok('C'),
+        "L" => Ok('L'),
+        "D" => Ok('D'),
+        "S" => Ok('S'),
+        _ => Err(format!("invalid observable type (expected C, L, D or S): {}", observable))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::{BTreeMap, HashMap};
+
+    use nyx_space::cosmic::SPEED_OF_LIGHT;
+    use rinex::{observation::{EpochFlag, LliFlags, ObservationData}, prelude::{Carrier, Observable}};
+
+    use crate::{encoder::{MsmType, RtcmEncoder}, RtcmData, RtcmDecoder};
+
+    use super::*;
+
+    const DEBUG_RTCM:&str = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm");
+
+    fn sv() -> SV {
+        SV {constellation: Constellation::GPS, prn: 5}
+    }
+
+    // debug.rtcm's 1019 ephemeris, then ten 1 s epochs of G05 L1C from a 10 s boundary in its week, lock lost in the third
+    fn stream() -> Vec<u8> {
+        let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+        let mut rtcm_decoder = RtcmDecoder::new(false);
+        rtcm_decoder.decode_bytes(&rtcm_buffer).unwrap();
+        let first_epoch = rtcm_decoder.get_first_epoch().unwrap();
+        let start = Epoch::from_gpst_seconds((first_epoch.to_gpst_seconds() / 10.0).floor() * 10.0);
+
+        let lambda = SPEED_OF_LIGHT / Carrier::from_observable(Constellation::GPS, &Observable::Phase("L1C".to_string())).unwrap().frequency();
+
+        let mut stream = rtcm_buffer[..67].to_vec();
+        let mut encoder = RtcmEncoder::new(0, MsmType::Msm7);
+        for i in 0..10 {
+            let range = 20_000_000.0 + i as f64 * 100.0;
+            let lli = if i == 2 { Some(LliFlags::LOCK_LOSS) } else { None };
+            let mut observations:HashMap<Observable, ObservationData> = HashMap::new();
+            observations.insert(Observable::PseudoRange("C1C".to_string()), ObservationData {obs: range, lli: None, snr: None});
+            observations.insert(Observable::Phase("L1C".to_string()), ObservationData {obs: range / lambda, lli, snr: None});
+            for frame in encoder.encode_epoch(start + Duration::from_seconds(i as f64), &BTreeMap::from([(sv(), observations)])) {
+                stream.extend(frame);
+            }
+        }
+        stream
+    }
+
+    fn lock_loss(rtcm_data:&RtcmData, epoch:Epoch) -> bool {
+        rtcm_data.get(&(epoch, EpochFlag::Ok)).unwrap().1.get(&sv()).unwrap().get(&Observable::Phase("L1C".to_string())).unwrap()
+            .lli.map(|lli| lli.intersects(LliFlags::LOCK_LOSS)).unwrap_or(false)
+    }
+
+    #[test]
+    fn lock_loss_in_decimated_gap_moves_to_next_kept_epoch() {
+        let stream = stream();
+
+        let mut full_decoder = RtcmDecoder::new(false);
+        full_decoder.decode_bytes(&stream).unwrap();
+        let full = full_decoder.get_rtcm_data();
+        let epochs:Vec<Epoch> = full.keys().map(|(epoch, _)| *epoch).collect();
+        assert_eq!(epochs.len(), 10);
+        assert!(lock_loss(&full, epochs[2]));
+        assert!(!lock_loss(&full, epochs[5]));
+
+        let mut decimated_decoder = RtcmDecoder::new(false);
+        decimated_decoder.set_time_window(TimeWindow {start: None, end: None, interval: Some(Duration::from_seconds(5.0))});
+        decimated_decoder.decode_bytes(&stream).unwrap();
+        let decimated = decimated_decoder.get_rtcm_data();
+
+        assert_eq!(decimated.keys().map(|(epoch, _)| *epoch).collect::<Vec<Epoch>>(), vec![epochs[0], epochs[5]]);
+        assert!(!lock_loss(&decimated, epochs[0]));
+        assert!(lock_loss(&decimated, epochs[5]));
+    }
+
+    #[test]
+    fn interval_grid_ignores_start() {
+        // a start 7 s past a 10 s boundary still keeps :10 and :15, not :07 and :12
+        let start = Epoch::from_gpst_seconds(1_400_000_007.0);
+        let window = TimeWindow {start: Some(start), end: None, interval: Some(Duration::from_seconds(5.0))};
+
+        let kept:Vec<i64> = (-2..12)
+            .map(|i| start + Duration::from_seconds(i as f64))
+            .filter(|epoch| window.contains(*epoch) && window.on_interval(*epoch))
+            .map(|epoch| epoch.to_gpst_seconds().round() as i64)
+            .collect();
+
+        assert_eq!(kept, vec![1_400_000_010, 1_400_000_015]);
+    }
+}
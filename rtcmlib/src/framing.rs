@@ -0,0 +1,465 @@
+// RTCM 3 transport layer framing
+// see RTCM 10403.3 section 4: preamble (8 bits), reserved (6 bits), message length (10 bits), payload, CRC-24Q (24 bits)
+
+use crate::error::RtcmError;
+
+pub const PREAMBLE:u8 = 0xD3;
+
+pub const HEADER_LEN:usize = 3;
+pub const CRC_LEN:usize = 3;
+pub const MAX_PAYLOAD_LEN:usize = 1023;
+
+const CRC24Q_POLY:u32 = 0x1864CFB;
+
+// longest run of skipped bytes held before it's reported, bounds memory on input that never frames
+const MAX_GARBAGE_LEN:usize = 65536;
+
+/// Qualcomm CRC-24Q as used by the RTCM 3 transport layer.
+pub fn crc24q(data:&[u8]) -> u32 {
+    let mut crc:u32 = 0;
+    for byte in data {
+        crc ^= (*byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= CRC24Q_POLY;
+            }
+        }
+    }
+    crc & 0xFFFFFF
+}
+
+/// Wraps a message payload (at most `MAX_PAYLOAD_LEN` bytes) in a frame: preamble, length, payload and CRC-24Q.
+pub fn encode_frame(payload:&[u8]) -> Vec<u8> {
+
+    let mut frame:Vec<u8> = Vec::with_capacity(HEADER_LEN + payload.len() + CRC_LEN);
+
+    frame.push(PREAMBLE);
+    frame.push(((payload.len() >> 8) & 0x03) as u8);
+    frame.push((payload.len() & 0xFF) as u8);
+    frame.extend_from_slice(payload);
+
+    let crc = crc24q(&frame);
+    frame.extend_from_slice(&[(crc >> 16) as u8, (crc >> 8) as u8, crc as u8]);
+
+    frame
+}
+
+// payload length from the 10 bit length field of a frame header
+fn payload_len(header:&[u8]) -> usize {
+    (((header[1] & 0x03) as usize) << 8) | header[2] as usize
+}
+
+/// Message number (first 12 bits of the payload) of a frame starting with the preamble.
+pub fn message_number(frame:&[u8]) -> Option<u16> {
+    if frame.len() < HEADER_LEN + 2 || payload_len(frame) < 2 {
+        return None;
+    }
+    Some(((frame[HEADER_LEN] as u16) << 4) | ((frame[HEADER_LEN + 1] as u16) >> 4))
+}
+
+// GPS time minus BeiDou time (ms)
+const BDT_OFFSET_MS:u64 = 14000;
+
+pub const WEEK_MS:u64 = 7 * 86400 * 1000;
+
+// GPS week of Galileo week 0 / BeiDou week 0
+const GST_WEEK_OFFSET:u64 = 1024;
+const BDT_WEEK_OFFSET:u64 = 1356;
+
+// `len` (<= 64) bits of `data` starting at bit `start`, MSB first
+fn bits(data:&[u8], start:usize, len:usize) -> u64 {
+    (start..start + len).fold(0u64, |value, i| (value << 1) | ((data[i / 8] >> (7 - i % 8)) & 1) as u64)
+}
+
+/// GPS time of week (ms) from the header of a GPS, SBAS, QZSS, Galileo or BeiDou MSM frame.
+/// GLONASS MSMs (day and time of day in Moscow time) and other messages give none.
+pub fn msm_time_of_week_ms(frame:&[u8]) -> Option<u64> {
+
+    let message_number = message_number(frame)?;
+
+    // message number, station id, then a 30 bit epoch time
+    if payload_len(frame) < 7 || frame.len() < HEADER_LEN + 7 {
+        return None;
+    }
+    let epoch_time = bits(&frame[HEADER_LEN..], 24, 30);
+
+    match message_number {
+        1071..=1077 | 1091..=1097 | 1101..=1107 | 1111..=1117 => Some(epoch_time),
+        1121..=1127 => Some((epoch_time + BDT_OFFSET_MS) % WEEK_MS),
+        _ => None
+    }
+}
+
+/// Full GPS week from the 10 bit DF076 week of a 1019, assuming the current (2019-2038) rollover period.
+pub fn gps_week_from_df076(week:u64) -> u64 {
+    week + 2 * 1024
+}
+
+/// GPS week from the week field of a GPS (1019), BeiDou (1042) or Galileo I/NAV (1046) ephemeris frame.
+pub fn ephemeris_gps_week(frame:&[u8]) -> Option<u64> {
+
+    let message_number = message_number(frame)?;
+
+    // message number, satellite id, then the week
+    if payload_len(frame) < 4 || frame.len() < HEADER_LEN + 4 {
+        return None;
+    }
+    let payload = &frame[HEADER_LEN..];
+
+    match message_number {
+        1019 => Some(gps_week_from_df076(bits(payload, 18, 10))),
+        1042 => Some(bits(payload, 18, 13) + BDT_WEEK_OFFSET),
+        1046 => Some(bits(payload, 18, 12) + GST_WEEK_OFFSET),
+        _ => None
+    }
+}
+
+/// MSM signal ids (1-32) set in the signal mask of an MSM frame header.
+pub fn msm_signal_ids(frame:&[u8]) -> Option<Vec<u8>> {
+
+    let message_number = message_number(frame)?;
+    if !(1071..=1137).contains(&message_number) {
+        return None;
+    }
+
+    // header fields up to the satellite mask take 73 bits, then 64 satellite and 32 signal mask bits
+    if payload_len(frame) < 22 || frame.len() < HEADER_LEN + 22 {
+        return None;
+    }
+    let mask = bits(&frame[HEADER_LEN..], 73 + 64, 32);
+
+    Some((0..32).filter(|i| (mask >> (31 - i)) & 1 == 1).map(|i| i as u8 + 1).collect())
+}
+
+/// Unwraps MSM times of week across week rollovers into a continuous ms count from the first week seen.
+#[derive(Clone, Debug, Default)]
+pub struct TimeOfWeek {
+    previous_ms:Option<u64>,
+    week_offset_ms:u64
+}
+
+impl TimeOfWeek {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A time of week more than half a week before the previous one is taken as the next week.
+    pub fn continuous_ms(&mut self, time_of_week_ms:u64) -> u64 {
+        if self.previous_ms.is_some() && time_of_week_ms + WEEK_MS / 2 < self.previous_ms.unwrap() {
+            self.week_offset_ms += WEEK_MS;
+        }
+        self.previous_ms = Some(time_of_week_ms);
+        time_of_week_ms + self.week_offset_ms
+    }
+
+    /// Weeks passed since the first time of week.
+    pub fn weeks(&self) -> u64 {
+        self.week_offset_ms / WEEK_MS
+    }
+}
+
+/// Reference station id (DF003) of observation, station and MSM messages; ephemeris and other messages carry none.
+pub fn station_id(frame:&[u8]) -> Option<u16> {
+
+    let message_number = message_number(frame)?;
+
+    if payload_len(frame) < 3 || frame.len() < HEADER_LEN + 3 {
+        return None;
+    }
+
+    match message_number {
+        1001..=1013 | 1029 | 1033 | 1071..=1137 | 1230 => Some(bits(&frame[HEADER_LEN..], 12, 12) as u16),
+        _ => None
+    }
+}
+
+/// Frame candidate found at a preamble. `data` spans preamble through CRC as given by the length field;
+/// when the CRC fails it's only what the header claimed, not necessarily a real frame.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RawFrame {
+    /// byte offset of the preamble from the start of the input
+    pub offset:u64,
+    pub data:Vec<u8>,
+    pub crc_ok:bool
+}
+
+impl RawFrame {
+    pub fn message_number(&self) -> Option<u16> {
+        message_number(&self.data)
+    }
+
+    pub fn station_id(&self) -> Option<u16> {
+        if self.crc_ok { station_id(&self.data) } else { None }
+    }
+
+    pub fn msm_time_of_week_ms(&self) -> Option<u64> {
+        if self.crc_ok { msm_time_of_week_ms(&self.data) } else { None }
+    }
+}
+
+/// Bytes skipped while looking for frames: noise, NMEA or proprietary data between frames,
+/// preambles that didn't frame a valid message and a truncated frame at the end of the input.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GarbageRange {
+    /// input offset of the first byte
+    pub offset:u64,
+    pub data:Vec<u8>
+}
+
+/// Accumulates bytes from a file or live stream and splits them into complete, CRC-checked RTCM 3 frames.
+/// Partial frames are kept until the remaining bytes arrive.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameBuffer {
+    buffer:Vec<u8>,
+    // input offset of buffer[0]
+    offset:u64,
+    // run of skipped bytes still growing
+    garbage:Option<GarbageRange>,
+    // completed runs not yet taken
+    discarded:Vec<GarbageRange>,
+    // a candidate failed CRC and no valid frame followed yet, further failures are part of the same resync
+    resyncing:bool
+}
+
+impl FrameBuffer {
+
+    pub fn new() -> Self {
+        Self {buffer:Vec::new(), offset:0, garbage:None, discarded:Vec::new(), resyncing:false}
+    }
+
+    pub fn extend(&mut self, data:&[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    fn consume(&mut self, len:usize) {
+        self.buffer.drain(..len);
+        self.offset += len as u64;
+    }
+
+    // consumes bytes that aren't part of a frame, adding them to the current garbage run
+    fn discard(&mut self, len:usize) {
+
+        if len == 0 {
+            return;
+        }
+
+        if self.garbage.is_none() {
+            self.garbage = Some(GarbageRange {offset:self.offset, data:Vec::new()});
+        }
+        let garbage = self.garbage.as_mut().unwrap();
+        garbage.data.extend_from_slice(&self.buffer[..len]);
+        let full = garbage.data.len() >= MAX_GARBAGE_LEN;
+
+        self.consume(len);
+
+        if full {
+            self.close_garbage();
+        }
+    }
+
+    fn close_garbage(&mut self) {
+        if let Some(garbage) = self.garbage.take() {
+            self.discarded.push(garbage);
+        }
+    }
+
+    /// Garbage runs completed so far, i.e. followed by a valid frame (or cut at 64 KiB, or at `finish`).
+    pub fn take_garbage(&mut self) -> Vec<GarbageRange> {
+        std::mem::take(&mut self.discarded)
+    }
+
+    /// End of input: whatever is left (a truncated frame, trailing noise) becomes garbage.
+    pub fn finish(&mut self) {
+        let len = self.buffer.len();
+        self.discard(len);
+        self.close_garbage();
+    }
+
+    /// Returns the next complete frame (preamble through CRC), a CRC error for a preamble that didn't
+    /// frame a valid message (once per resync, not for every candidate until the next valid frame),
+    /// or None if more data is needed.
+    pub fn next_frame(&mut self) -> Option<Result<Vec<u8>, RtcmError>> {
+
+        let frame = self.next_raw_frame()?;
+
+        if frame.crc_ok {
+            Some(Ok(frame.data))
+        }
+        else {
+            Some(Err(RtcmError::CrcMismatch { message_number: frame.message_number() }))
+        }
+    }
+
+    /// Like `next_frame` but keeps the input offset and the first failed candidate's bytes, for inspection tools.
+    pub fn next_raw_frame(&mut self) -> Option<RawFrame> {
+
+        loop {
+            // skip anything ahead of the next preamble
+            match self.buffer.iter().position(|b| *b == PREAMBLE) {
+                Some(start) => self.discard(start),
+                None => {
+                    let len = self.buffer.len();
+                    self.discard(len);
+                    return None;
+                }
+            }
+
+            if self.buffer.len() < HEADER_LEN {
+                return None;
+            }
+
+            // the 6 reserved bits are always 0, otherwise it's a 0xD3 inside other data
+            if self.buffer[1] & 0xFC != 0 {
+                self.discard(1);
+                continue;
+            }
+
+            let len = payload_len(&self.buffer);
+            let frame_len = HEADER_LEN + len + CRC_LEN;
+
+            if self.buffer.len() < frame_len {
+                return None;
+            }
+
+            let crc = crc24q(&self.buffer[..HEADER_LEN + len]);
+            let frame_crc = ((self.buffer[HEADER_LEN + len] as u32) << 16)
+                            | ((self.buffer[HEADER_LEN + len + 1] as u32) << 8)
+                            | self.buffer[HEADER_LEN + len + 2] as u32;
+
+            let offset = self.offset;
+            let data = self.buffer[..frame_len].to_vec();
+
+            if crc == frame_crc {
+                self.close_garbage();
+                self.consume(frame_len);
+                self.resyncing = false;
+                return Some(RawFrame {offset, data, crc_ok:true});
+            }
+
+            // not a valid frame -- resync on the next preamble
+            self.discard(1);
+
+            if self.resyncing {
+                continue;
+            }
+            self.resyncing = true;
+
+            return Some(RawFrame {offset, data, crc_ok:false});
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const DEBUG_RTCM:&str = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm");
+
+    // frame offsets in debug.rtcm: 1019, 1046, 1077, 1077, 1097, 1097
+    const FRAME_OFFSETS:[u64;6] = [0, 67, 136, 322, 493, 629];
+
+    #[test]
+    fn crc24q_check_value() {
+        assert_eq!(crc24q(b"123456789"), 0xCDE703);
+        assert_eq!(crc24q(&[]), 0);
+
+        let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+        let frame = &rtcm_buffer[..67];
+        assert_eq!(encode_frame(&frame[HEADER_LEN..64]), frame);
+    }
+
+    #[test]
+    fn frames_split_across_extend() {
+        let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+
+        let mut frame_buffer = FrameBuffer::new();
+        let mut frames:Vec<RawFrame> = Vec::new();
+        for byte in rtcm_buffer.iter() {
+            frame_buffer.extend(&[*byte]);
+            while let Some(frame) = frame_buffer.next_raw_frame() {
+                frames.push(frame);
+            }
+        }
+
+        assert!(frames.iter().all(|frame| frame.crc_ok));
+        assert_eq!(frames.iter().map(|frame| frame.offset).collect::<Vec<u64>>(), FRAME_OFFSETS.to_vec());
+        assert_eq!(frames.iter().map(|frame| frame.message_number().unwrap()).collect::<Vec<u16>>(), vec![1019, 1046, 1077, 1077, 1097, 1097]);
+        frame_buffer.finish();
+        assert!(frame_buffer.take_garbage().is_empty());
+    }
+
+    #[test]
+    fn resync_after_corrupt_frames() {
+        // both ephemeris frames corrupted: one CRC failure for the resync, then the first 1077
+        let mut rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+        rtcm_buffer[30] ^= 0x01;
+        rtcm_buffer[100] ^= 0x01;
+
+        let mut frame_buffer = FrameBuffer::new();
+        frame_buffer.extend(&rtcm_buffer);
+
+        let failed = frame_buffer.next_raw_frame().unwrap();
+        assert!(!failed.crc_ok);
+        assert_eq!((failed.offset, failed.data.len(), failed.message_number()), (0, 67, Some(1019)));
+
+        let frame = frame_buffer.next_raw_frame().unwrap();
+        assert!(frame.crc_ok);
+        assert_eq!((frame.offset, frame.message_number()), (136, Some(1077)));
+
+        let garbage = frame_buffer.take_garbage();
+        assert_eq!(garbage.len(), 1);
+        assert_eq!((garbage[0].offset, garbage[0].data.as_slice()), (0, &rtcm_buffer[..136]));
+
+        let mut remaining = 0;
+        while let Some(frame) = frame_buffer.next_raw_frame() {
+            assert!(frame.crc_ok);
+            remaining += 1;
+        }
+        assert_eq!(remaining, 3);
+
+        // through next_frame the failure is a CRC error with the claimed message number
+        let mut frame_buffer = FrameBuffer::new();
+        frame_buffer.extend(&rtcm_buffer);
+        assert!(matches!(frame_buffer.next_frame(), Some(Err(RtcmError::CrcMismatch {message_number: Some(1019)}))));
+        assert!(matches!(frame_buffer.next_frame(), Some(Ok(_))));
+    }
+
+    #[test]
+    fn garbage_runs_are_capped() {
+        // noise read in 4 KiB blocks
+        let mut frame_buffer = FrameBuffer::new();
+        for chunk in vec![0u8; MAX_GARBAGE_LEN + 100].chunks(4096) {
+            frame_buffer.extend(chunk);
+            assert!(frame_buffer.next_raw_frame().is_none());
+        }
+
+        let garbage = frame_buffer.take_garbage();
+        assert_eq!(garbage.len(), 1);
+        assert_eq!((garbage[0].offset, garbage[0].data.len()), (0, MAX_GARBAGE_LEN));
+
+        frame_buffer.finish();
+        let garbage = frame_buffer.take_garbage();
+        assert_eq!(garbage.len(), 1);
+        assert_eq!((garbage[0].offset, garbage[0].data.len()), (MAX_GARBAGE_LEN as u64, 100));
+    }
+
+    #[test]
+    fn time_of_week_rollover() {
+        let mut time_of_week = TimeOfWeek::new();
+        assert_eq!(time_of_week.continuous_ms(WEEK_MS - 1000), WEEK_MS - 1000);
+        assert_eq!(time_of_week.weeks(), 0);
+
+        // Sunday 00:00:00.5 of the next week
+        assert_eq!(time_of_week.continuous_ms(500), WEEK_MS + 500);
+        assert_eq!(time_of_week.weeks(), 1);
+
+        // a slightly earlier time in the same week is not another rollover
+        assert_eq!(time_of_week.continuous_ms(400), WEEK_MS + 400);
+        assert_eq!(time_of_week.weeks(), 1);
+    }
+}
@@ -1,12 +1,40 @@
 #![allow(warnings)] 
 
-use std::{  borrow::{Borrow, BorrowMut}, collections::{BTreeMap, HashMap, HashSet}, fmt, fs::File, io::Read, path::Path, task::Context };
+use std::{  borrow::{Borrow, BorrowMut}, collections::{BTreeMap, HashMap, HashSet}, fmt, fs::File, io::{self, Read}, path::Path, task::Context, time::Instant };
 use hifitime::{Duration, Unit};
-use log::info;
+use log::{debug, info, warn};
 use rinex::{observation::{ Crinex, EpochFlag, HeaderFields, LliFlags, ObservationData}, prelude::{Carrier, Constellation, Epoch, Header, Observable, SV}, version::Version, Rinex};
 
 use rtcm_rs::{msg::{Msg1074T, Msg1077T, Msg1094T, Msg1097Data, Msg1097T, Msg1127Data, Msg1127T, Msm46Sat, Msm57Sat}, Message, MsgFrameIter};
 use nyx_space::cosmic::SPEED_OF_LIGHT;
+
+pub mod clock;
+pub mod compression;
+pub mod encoder;
+pub mod error;
+pub mod export;
+pub mod filter;
+pub mod framing;
+pub mod nmea;
+pub mod phase;
+pub mod quality;
+pub mod serial;
+pub mod slip;
+pub mod snr;
+#[cfg(feature = "serde")]
+pub mod serialization;
+pub mod station;
+pub mod stats;
+
+pub use error::{ErrorPolicy, RtcmError};
+pub use filter::{SignalFilter, TimeWindow};
+pub use snr::SnrMapping;
+pub use quality::{QualityData, SignalQuality};
+pub use station::StationInfo;
+pub use stats::{DecoderStats, DiscardedRange};
+use framing::FrameBuffer;
+use serial::SerialConfig;
+
 // epoch/sv/observation map for data extracted from rtcm log 
 pub type RtcmData = BTreeMap<(Epoch, EpochFlag), (Option<f64>, BTreeMap<SV, HashMap<Observable, ObservationData>>)>;
 
@@ -22,6 +50,28 @@ const RANGE_MS:f64 = SPEED_OF_LIGHT * 0.001;     // range in 1ms
 
 const DEFAULT_LLI:u16 = 0;
 
+// spacing beyond this multiple of a signal's interval means epochs were missed
+pub(crate) const GAP_FACTOR:f64 = 1.5;
+
+// spacing (ms) more than GAP_FACTOR times the signal's interval, unknown until one is learned
+pub(crate) fn is_gap(dt_ms:u64, interval_ms:Option<u64>) -> bool {
+    match interval_ms {
+        Some(interval_ms) => dt_ms as f64 > interval_ms as f64 * GAP_FACTOR,
+        None => false
+    }
+}
+
+// keeps the shortest non-zero spacing (ms) seen for each signal as its interval
+pub(crate) fn learn_interval(intervals:&mut HashMap<(SV, String), u64>, key:&(SV, String), dt_ms:u64) {
+    if dt_ms > 0 {
+        let learned = intervals.entry(key.clone()).or_insert(dt_ms);
+        *learned = (*learned).min(dt_ms);
+    }
+}
+
+// read size for file and stream input
+const READ_BUFFER_LEN:usize = 4096;
+
 struct MsmData {
 
     constellation:Constellation, 
@@ -36,26 +86,49 @@ struct MsmData {
     fine_phase_range_rate:Option<f64>, 
     cnr:Option<f64>,
     loss_of_lock_indicator:u16,
-    half_cycle_ambiguity:u8
+    // loss_of_lock_indicator is DF407 (MSM5/7) rather than DF402 (MSM4)
+    extended_lock_time:bool,
+    half_cycle_ambiguity:u8,
+    extended_satellite_info:Option<u8>
 }
 
+// lock history per signal, keyed by (sv, signal code)
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LockStatus {
     use_rtklib_method:bool,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::pairs"))]
     previous_lli:HashMap<(SV, String), u16>,
-    previous_epoch:HashMap<(SV, String), Epoch>
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::pairs"))]
+    previous_epoch:HashMap<(SV, String), Epoch>,
+    // lock losses seen on epochs that weren't stored (decimation), reported at the next stored epoch
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::pairs"))]
+    deferred_lli:HashMap<(SV, String), LliFlags>,
+    // configured observation interval (ms), otherwise learned per signal as its shortest spacing
+    expected_interval_ms:Option<u64>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::pairs"))]
+    learned_interval_ms:HashMap<(SV, String), u64>
 }
 
 impl LockStatus {
     pub fn new(use_rtklib_method:bool) -> LockStatus {
-        LockStatus { use_rtklib_method:use_rtklib_method, previous_lli: HashMap::new(), previous_epoch:HashMap::new()}
+        LockStatus { use_rtklib_method:use_rtklib_method, previous_lli: HashMap::new(), previous_epoch:HashMap::new(), deferred_lli:HashMap::new(), expected_interval_ms:None, learned_interval_ms:HashMap::new()}
     }
     
+    pub fn set_use_rtklib_method(&mut self, use_rtklib_method:bool) {
+        self.use_rtklib_method = use_rtklib_method;
+    }
+
+    /// Observation interval used to recognise data outages, instead of learning it from each signal.
+    pub fn set_expected_interval(&mut self, interval:Option<Duration>) {
+        self.expected_interval_ms = interval.map(|i| i.to_unit(Unit::Millisecond).round() as u64);
+    }
+
     /// Calculates the minimum lock time (t) based on the indicator value (i).
     /// # Arguments
     /// * `i` - The indicator value from DF407.
     /// # Returns
     /// * The minimum lock time in milliseconds.
-    fn calculate_minimum_lock_time(i: u16) -> u64 {
+    pub(crate) fn calculate_minimum_lock_time(i: u16) -> u64 {
         let i = i as u64;
         match i {
             0..=63 => i,
@@ -123,7 +196,8 @@ impl LockStatus {
         }
     }
 
-    pub fn update_lock_status(&mut self, sv:&SV, code:&String, current_epoch:&Epoch, current_lli:u16, half_cycle_ambiguity:u8) -> Option<LliFlags> {
+    /// `extended_lock_time`: `current_lli` is a DF407 (MSM5/7) indicator, otherwise DF402 (MSM4).
+    pub fn update_lock_status(&mut self, sv:&SV, code:&String, current_epoch:&Epoch, current_lli:u16, extended_lock_time:bool, half_cycle_ambiguity:u8) -> Option<LliFlags> {
        
         let mut lli = LliFlags::OK_OR_UNKNOWN;
 
@@ -148,40 +222,31 @@ impl LockStatus {
         }
         else {
 
-            /// Determines if there is a loss of lock based on DF407 values and the calculated minimum lock times.
-            /// According to RTCM 10403.4 section 3.5.12.3.2 Lock Time Indicator
-
-            let previous_epoch = self.previous_epoch.get(lock_key);
+            let previous_epoch = self.previous_epoch.get(lock_key).copied();
 
             let mut dt:u64 = 0;
 
             if previous_epoch.is_some() {
-                dt = (*current_epoch - *previous_epoch.unwrap()).to_unit(Unit::Millisecond) as u64;
+                dt = (*current_epoch - previous_epoch.unwrap()).to_unit(Unit::Millisecond) as u64;
             }
 
-            let p = LockStatus::calculate_minimum_lock_time(previous_lli);
-            let n = LockStatus::calculate_minimum_lock_time(current_lli);
-            let a: u32 = LockStatus::get_lli_coefficient(previous_lli);
-            let b = LockStatus::get_lli_coefficient(current_lli);
+            let lock_loss = if extended_lock_time {
+                LockStatus::df407_lock_loss(previous_lli, current_lli, dt)
+            }
+            else {
+                LockStatus::df402_lock_loss(previous_lli, current_lli, dt)
+            };
 
-            if p > n {
-                lli |= LliFlags::LOCK_LOSS;
-            } else if p == n && dt >= a as u64 {
-                lli |= LliFlags::LOCK_LOSS;
-            } else if p == n && dt < a as u64 {
-                lli |= LliFlags::OK_OR_UNKNOWN;
-            } else if p < n && b > p as u32 && dt >= (n + b as u64 - p) {
-                lli |= LliFlags::LOCK_LOSS;
-            } else if p < n && b > p as u32 && n < dt && dt < (n + b as u64 - p) {
+            if lock_loss {
                 lli |= LliFlags::LOCK_LOSS;
-            } else if p < n && b > p as u32 && dt <= n {
-                lli |= LliFlags::OK_OR_UNKNOWN;
-            } else if p < n && b <= p as u32 && dt > n {
+            }
+            // signal missing for one or more epochs: the lock time has to account for the whole outage
+            else if previous_epoch.is_some() && self.is_gap(lock_key, dt) && LockStatus::gap_exceeds_lock_time(previous_lli, current_lli, extended_lock_time, dt) {
                 lli |= LliFlags::LOCK_LOSS;
-            } else if p < n && b <= p as u32 && dt <= n {
-                lli |= LliFlags::OK_OR_UNKNOWN;
-            } else {
-                lli |= LliFlags::OK_OR_UNKNOWN;
+            }
+
+            if previous_epoch.is_some() {
+                learn_interval(&mut self.learned_interval_ms, lock_key, dt);
             }
         }
         
@@ -192,6 +257,98 @@ impl LockStatus {
         return Some(lli);
 
     }
+
+    /// Determines if there is a loss of lock based on DF407 values and the calculated minimum lock times.
+    /// According to RTCM 10403.4 section 3.5.12.3.2 Lock Time Indicator
+    /// # Arguments
+    /// * `previous_indicator` - DF407 of the previous observation of the signal.
+    /// * `current_indicator` - DF407 of this observation.
+    /// * `dt` - time between the two observations in milliseconds.
+    pub fn df407_lock_loss(previous_indicator:u16, current_indicator:u16, dt:u64) -> bool {
+
+        let p = LockStatus::calculate_minimum_lock_time(previous_indicator);
+        let n = LockStatus::calculate_minimum_lock_time(current_indicator);
+        let a = LockStatus::get_lli_coefficient(previous_indicator) as u64;
+        let b = LockStatus::get_lli_coefficient(current_indicator) as u64;
+
+        if p > n {
+            true
+        } else if p == n && dt >= a {
+            true
+        } else if p == n && dt < a {
+            false
+        } else if p < n && b > p && dt >= (n + b - p) {
+            true
+        } else if p < n && b > p && n < dt && dt < (n + b - p) {
+            true
+        } else if p < n && b > p && dt <= n {
+            false
+        } else if p < n && b <= p && dt > n {
+            true
+        } else {
+            // p < n && b <= p && dt <= n
+            false
+        }
+    }
+
+    /// Determines if there is a loss of lock between two DF402 (MSM4) indicators, which only give
+    /// the lock time to within a power of two: it can't have gone down, and it has to have grown by `dt`.
+    /// # Arguments
+    /// * `previous_indicator` - DF402 of the previous observation of the signal.
+    /// * `current_indicator` - DF402 of this observation.
+    /// * `dt` - time between the two observations in milliseconds.
+    pub fn df402_lock_loss(previous_indicator:u16, current_indicator:u16, dt:u64) -> bool {
+
+        let p = quality::df402_minimum_lock_time(previous_indicator);
+        let n = quality::df402_minimum_lock_time(current_indicator);
+
+        if p > n {
+            true
+        } else if p == n {
+            dt >= quality::df402_lock_time_range(previous_indicator)
+        } else {
+            dt >= n.saturating_add(quality::df402_lock_time_range(current_indicator)) - p
+        }
+    }
+
+    /// Lock held through an outage of `dt` ms means the lock time grew by at least `dt`;
+    /// the most it can have grown is n + b - p (current upper bound minus previous lower bound).
+    pub fn gap_exceeds_lock_time(previous_indicator:u16, current_indicator:u16, extended_lock_time:bool, dt:u64) -> bool {
+
+        let (p, n, b) = if extended_lock_time {
+            (LockStatus::calculate_minimum_lock_time(previous_indicator),
+                LockStatus::calculate_minimum_lock_time(current_indicator),
+                LockStatus::get_lli_coefficient(current_indicator) as u64)
+        }
+        else {
+            (quality::df402_minimum_lock_time(previous_indicator),
+                quality::df402_minimum_lock_time(current_indicator),
+                quality::df402_lock_time_range(current_indicator))
+        };
+
+        dt >= n.saturating_add(b).saturating_sub(p)
+    }
+
+    // more than the expected spacing since the signal's last observation, i.e. at least one epoch missing
+    fn is_gap(&self, lock_key:&(SV, String), dt:u64) -> bool {
+        is_gap(dt, self.expected_interval_ms.or(self.learned_interval_ms.get(lock_key).copied()))
+    }
+
+    /// Keeps a lock loss from an epoch that isn't stored so it can be reported on the next stored one.
+    pub fn defer_lli(&mut self, sv:&SV, code:&String, lli:LliFlags) {
+        if lli.intersects(LliFlags::LOCK_LOSS) {
+            *self.deferred_lli.entry((*sv, code.clone())).or_insert(LliFlags::OK_OR_UNKNOWN) |= LliFlags::LOCK_LOSS;
+        }
+    }
+
+    pub fn take_deferred_lli(&mut self, sv:&SV, code:&String) -> LliFlags {
+        self.deferred_lli.remove(&(*sv, code.clone())).unwrap_or(LliFlags::OK_OR_UNKNOWN)
+    }
+
+    // epoch of the last observation processed for this signal
+    pub fn last_epoch(&self, sv:&SV, code:&String) -> Option<Epoch> {
+        self.previous_epoch.get(&(*sv, code.clone())).copied()
+    }
     
 }
 
@@ -238,15 +395,124 @@ pub fn rtcm_bds_time2epoch(tow_ms:f64, week:u64) -> Epoch {
     return t;
 }
 
+// start of the fixed length session containing `epoch`, aligned to GPS time boundaries (e.g. 00:00, 00:15 ... for 15 minute sessions)
+pub fn session_start(epoch:Epoch, period:Duration) -> Epoch {
+
+    let period_sec = period.to_seconds();
+    let gpst_sec = epoch.to_gpst_seconds();
+
+    Epoch::from_gpst_seconds((gpst_sec / period_sec).floor() * period_sec)
+}
+
+// splits decoded data into fixed length sessions keyed by session start
+pub fn split_sessions(rtcm_data:&RtcmData, period:Duration) -> BTreeMap<Epoch, RtcmData> {
+
+    let mut sessions:BTreeMap<Epoch, RtcmData> = BTreeMap::new();
+
+    for (key, epoch_data) in rtcm_data.iter() {
+        let start = session_start(key.0, period);
+        sessions.entry(start).or_insert(BTreeMap::new()).insert(*key, epoch_data.clone());
+    }
+
+    sessions
+}
+
+// observables present in the data by constellation, ordered by signal code then C, L, D, S -- the header obs type table
+pub fn extract_observables(rtcm_data:&RtcmData) -> HashMap<Constellation, Vec<Observable>> {
+
+    let mut observables:HashMap<Constellation, Vec<Observable>> = HashMap::new();
+
+    for epoch in rtcm_data.values() {
+        for (sv, observations) in epoch.1.iter() {
+            let constellation_observables = observables.entry(sv.constellation).or_insert(Vec::new());
+            for observable in observations.keys() {
+                if !constellation_observables.contains(observable) {
+                    constellation_observables.push(observable.clone());
+                }
+            }
+        }
+    }
+
+    let type_order = |observable:&Observable| match observable {
+        Observable::PseudoRange(_) => 0,
+        Observable::Phase(_) => 1,
+        Observable::Doppler(_) => 2,
+        _ => 3
+    };
+
+    for constellation_observables in observables.values_mut() {
+        constellation_observables.sort_by_key(|o| (o.code().unwrap_or_default(), type_order(o)));
+    }
+
+    observables
+}
+
+pub fn extract_observed_signals(rtcm_data:&RtcmData) -> HashSet<(Constellation, String)> {
+
+    let mut observed_signals = HashSet::new();
+
+    for epoch in rtcm_data.values() {
+        for sv in epoch.1.keys() {
+            let constellation = sv.constellation;
+            let observations = epoch.1.get(&sv).unwrap();
+            for observable in observations.keys() {
+                let code = observable.code().unwrap();
+                observed_signals.insert((constellation, code));
+            }   
+        }
+    }
+
+    observed_signals
+}
+
 
 
 
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RtcmDecoder {
     first_epoch:Option<Epoch>,
     last_epoch:Option<Epoch>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::rtcm_data"))]
     rtcm_data:RtcmData,
-    lock_status:LockStatus
+    lock_status:LockStatus,
+    gps_week:Option<u64>,
+    galileo_week:Option<u64>,
+    bds_week:Option<u64>,
+    frame_buffer:FrameBuffer,
+    error_policy:ErrorPolicy,
+    stats:DecoderStats,
+    station:StationInfo,
+    // epochs before this were taken out and written, see take_data_before
+    flushed_until:Option<Epoch>,
+    filter:SignalFilter,
+    time_window:TimeWindow,
+    snr_mapping:SnrMapping,
+    phase_alignment:bool,
+    // raw lock time / half-cycle / extended info per stored signal, when enabled
+    collect_quality:bool,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::pairs"))]
+    signal_quality:QualityData,
+    // keep NMEA sentences found between frames
+    extract_nmea:bool,
+    nmea_sentences:Vec<String>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    log_context:LogContext
+}
+
+// input file / message / epoch being decoded, attached as key-values to log records
+#[derive(Default)]
+struct LogContext {
+    file:String,
+    // 0 when unknown
+    message_number:u16,
+    epoch:Option<Epoch>
+}
+
+impl LogContext {
+    fn epoch_str(&self) -> String {
+        self.epoch.map(|e| e.to_string()).unwrap_or_default()
+    }
 }
 
 
@@ -255,13 +521,22 @@ impl RtcmDecoder {
     pub fn new(use_rtklib_method:bool) -> Self {
         let rtcm_data = BTreeMap::new();
         let lock_status = LockStatus::new(use_rtklib_method);
-        Self {first_epoch:None, last_epoch:None, rtcm_data, lock_status}
+        Self {first_epoch:None, last_epoch:None, rtcm_data, lock_status, gps_week:None, galileo_week:None, bds_week:None, frame_buffer:FrameBuffer::new(), error_policy:ErrorPolicy::default(), stats:DecoderStats::new(), station:StationInfo::default(), flushed_until:None, filter:SignalFilter::default(), time_window:TimeWindow::default(), snr_mapping:SnrMapping::default(), phase_alignment:false, collect_quality:false, signal_quality:BTreeMap::new(), extract_nmea:false, nmea_sentences:Vec::new(), log_context:LogContext::default()}
     }
 
+    /// Drops decoded data, statistics and the flushed session marker; lock history, week numbers and settings are kept.
     pub fn clear(&mut self) {
         self.first_epoch = None;
         self.last_epoch = None;
         self.rtcm_data = BTreeMap::new();
+        self.signal_quality = BTreeMap::new();
+        self.stats = DecoderStats::new();
+        self.flushed_until = None;
+    }
+
+    /// LLI from RTKLIB's simplified lock time comparison instead of the DF402/DF407 rules (diagnostics).
+    pub fn set_use_rtklib_method(&mut self, use_rtklib_method:bool) {
+        self.lock_status.set_use_rtklib_method(use_rtklib_method);
     }
 
     pub fn get_first_epoch(&self) -> Option<Epoch> {
@@ -276,11 +551,113 @@ impl RtcmDecoder {
         self.rtcm_data.clone()
     }
 
-    fn process_signals(&mut self, signal:MsmData, msm_epoch:Epoch)  {
+    pub fn set_error_policy(&mut self, error_policy:ErrorPolicy) {
+        self.error_policy = error_policy;
+    }
+
+    /// Signals and observables outside the filter are dropped before they're stored.
+    pub fn set_filter(&mut self, filter:SignalFilter) {
+        self.filter = filter;
+    }
+
+    /// Observation interval for outage detection in the lock status (learned per signal when not set).
+    pub fn set_expected_interval(&mut self, interval:Option<Duration>) {
+        self.lock_status.set_expected_interval(interval);
+    }
+
+    /// Epochs outside the window or off its interval grid are dropped, lock is still tracked through them.
+    pub fn set_time_window(&mut self, time_window:TimeWindow) {
+        self.time_window = time_window;
+    }
+
+    /// CNR to RINEX signal strength indicator table.
+    pub fn set_snr_mapping(&mut self, snr_mapping:SnrMapping) {
+        self.snr_mapping = snr_mapping;
+    }
+
+    /// Shifts phase to the RINEX 3 reference signal of each band (see `phase::phase_shift`),
+    /// the output should then carry `phase::phase_shift_records` in its header.
+    pub fn set_phase_alignment(&mut self, phase_alignment:bool) {
+        self.phase_alignment = phase_alignment;
+    }
+
+    /// Keeps raw lock time indicators, minimum lock time, half-cycle flag and MSM7 extended satellite info
+    /// for every stored signal, see `get_signal_quality`.
+    pub fn set_collect_quality(&mut self, collect_quality:bool) {
+        self.collect_quality = collect_quality;
+    }
+
+    pub fn get_signal_quality(&self) -> &QualityData {
+        &self.signal_quality
+    }
+
+    /// Keeps NMEA sentences interleaved with the RTCM frames, see `take_nmea_sentences`.
+    pub fn set_extract_nmea(&mut self, extract_nmea:bool) {
+        self.extract_nmea = extract_nmea;
+    }
+
+    /// NMEA sentences found since the last call, in input order.
+    pub fn take_nmea_sentences(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.nmea_sentences)
+    }
+
+    pub fn get_stats(&self) -> &DecoderStats {
+        &self.stats
+    }
+
+    pub fn get_station_info(&self) -> &StationInfo {
+        &self.station
+    }
+
+    fn process_signals(&mut self, signal:MsmData, msm_epoch:Epoch) -> Result<(), RtcmError> {
                             
         // modeled on RKTLIB msm7 decoder 
         // see: https://github.com/rtklibexplorer/RTKLIB/blob/demo5/src/rtcm3.c#L1987
 
+        let code_str = format!("{}{}", signal.band, signal.attribute);
+        
+        let sv_key = SV {constellation:signal.constellation, prn: signal.satellite_id};
+
+        if !self.filter.keep_signal(&sv_key, &code_str) {
+            self.stats.filtered_signals += 1;
+            return Ok(());
+        }
+
+        // signal already decoded at this (or a later) epoch, e.g. the overlapping tail of a rotated log
+        // skip so the stored data and lock history stay continuous
+        let last_signal_epoch = self.lock_status.last_epoch(&sv_key, &code_str);
+        if last_signal_epoch.is_some() && last_signal_epoch.unwrap() >= msm_epoch {
+            self.stats.duplicate_signals += 1;
+            return Ok(());
+        }
+
+        // session already written out, storing it would start a new file that overwrites the completed one
+        if self.flushed_until.is_some() && msm_epoch < self.flushed_until.unwrap() {
+            self.stats.late_signals += 1;
+            return Ok(());
+        }
+
+        // not stored, but lock is tracked so a loss inside a decimation gap shows up on the next stored epoch
+        if !self.time_window.contains(msm_epoch) || !self.time_window.on_interval(msm_epoch) {
+            let lli = self.lock_status.update_lock_status(&sv_key, &code_str, &msm_epoch, signal.loss_of_lock_indicator, signal.extended_lock_time, signal.half_cycle_ambiguity);
+            if self.time_window.contains(msm_epoch) && lli.is_some() {
+                self.lock_status.defer_lli(&sv_key, &code_str, lli.unwrap());
+            }
+            self.stats.skipped_epoch_signals += 1;
+            return Ok(());
+        }
+
+        // build example pr observable to use rinex carrier frequency tables
+        let obs_key= Observable::PseudoRange(format!("C{}", code_str));
+
+        let frequency:f64 = match Carrier::from_observable(signal.constellation, &obs_key) {
+            Ok(carrier) => carrier.frequency(),
+            Err(_) => return Err(RtcmError::UnknownSignal {constellation: signal.constellation, code: code_str})
+        };
+        let wavelength:f64 = frequency / SPEED_OF_LIGHT;
+
+        self.stats.record_signal(&sv_key, &code_str, msm_epoch, last_signal_epoch);
+
         if self.first_epoch.is_none() || self.first_epoch.unwrap().gt(&msm_epoch) {
             self.first_epoch = Some(msm_epoch);
         }
@@ -297,15 +674,6 @@ impl RtcmDecoder {
         let epoch_data = self.rtcm_data.get_mut(&(msm_epoch, EpochFlag::Ok)).unwrap().1.borrow_mut();
         
 
-        let code_str = format!("{}{}", signal.band, signal.attribute);
-        // build example pr observable to use rinex carrier frequency tables
-        let obs_key= Observable::PseudoRange(format!("C{}", code_str));
-        
-        let sv_key = SV {constellation:signal.constellation, prn: signal.satellite_id};
-
-        let frequency:f64 = Carrier::from_observable(signal.constellation, &obs_key).unwrap().frequency();
-        let wavelength:f64 = frequency / SPEED_OF_LIGHT;
-
         if !epoch_data.contains_key(&sv_key) {
             epoch_data.insert(sv_key, HashMap::new());
         }   
@@ -317,7 +685,16 @@ impl RtcmDecoder {
             range = Some(((signal.rough_range.unwrap() as f64) * RANGE_MS) + (signal.rough_range_mod1ms  * RANGE_MS));
         }
 
-        let mut lli:Option<LliFlags> = self.lock_status.update_lock_status(&sv_key, &code_str, &msm_epoch, signal.loss_of_lock_indicator, signal.half_cycle_ambiguity);
+        let mut lli:Option<LliFlags> = self.lock_status.update_lock_status(&sv_key, &code_str, &msm_epoch, signal.loss_of_lock_indicator, signal.extended_lock_time, signal.half_cycle_ambiguity);
+        let deferred_lli = self.lock_status.take_deferred_lli(&sv_key, &code_str);
+
+        if self.collect_quality {
+            let quality = SignalQuality::new(signal.loss_of_lock_indicator, signal.extended_lock_time, signal.half_cycle_ambiguity, signal.extended_satellite_info);
+            self.signal_quality.insert((msm_epoch, sv_key, code_str.clone()), quality);
+        }
+        if lli.is_some() {
+            lli = Some(lli.unwrap() | deferred_lli);
+        }
 
     
         let mut rough_phase_range_rate:Option<f64> = None;
@@ -346,37 +723,44 @@ impl RtcmDecoder {
         }
         
         
-        if range.is_some() && fine_pseudo_range.is_some() {
+        // signal strength indicator applies to the code, phase and doppler of the signal, S already is the strength
+        let snr = signal.cnr.and_then(|cnr| snr::snr(cnr, self.snr_mapping));
+
+        if range.is_some() && fine_pseudo_range.is_some() && self.filter.keep_observable('C') {
             let pseudo_range_obs = (range.unwrap() + fine_pseudo_range.unwrap());
             let code = Observable::PseudoRange(format!("C{}", code_str));
             observation_data.insert(code, 
-                                    ObservationData {obs:pseudo_range_obs, lli: None, snr: None});
+                                    ObservationData {obs:pseudo_range_obs, lli: None, snr: snr});
         }
     
-        if range.is_some() && fine_phase_range.is_some() {
-            let phase_range_obs =(range.unwrap() + fine_phase_range.unwrap()) * wavelength; 
+        if range.is_some() && fine_phase_range.is_some() && self.filter.keep_observable('L') {
+            let mut phase_range_obs =(range.unwrap() + fine_phase_range.unwrap()) * wavelength; 
+            if self.phase_alignment {
+                phase_range_obs += phase::phase_shift(signal.constellation, &code_str);
+            }
             let code = Observable::Phase(format!("L{}", code_str));
             observation_data.insert(code, 
-                                    ObservationData {obs:phase_range_obs, lli: lli, snr: None});
+                                    ObservationData {obs:phase_range_obs, lli: lli, snr: snr});
         }
         
-        if rough_phase_range_rate.is_some() && fine_phase_range_rate.is_some() {
+        if rough_phase_range_rate.is_some() && fine_phase_range_rate.is_some() && self.filter.keep_observable('D') {
             let phase_range_rate_obs:f64 = (-(rough_phase_range_rate.unwrap() + fine_phase_range_rate.unwrap())) * wavelength;
             let code = Observable::Doppler(format!("D{}", code_str));
             observation_data.insert(code, 
-                                    ObservationData {obs:phase_range_rate_obs, lli: None, snr: None});
+                                    ObservationData {obs:phase_range_rate_obs, lli: None, snr: snr});
         }
         
-        if signal.cnr.is_some() {
+        if signal.cnr.is_some() && self.filter.keep_observable('S') {
             let cnr_obs = (signal.cnr.unwrap() as f64);
             let code = Observable::SSI(format!("S{}", code_str));
             observation_data.insert(code, 
                                     ObservationData {obs:cnr_obs, lli: None, snr: None});
         }
 
+        Ok(())
     }
 
-    pub fn process_msm1074(&mut self, msg:Msg1074T, msm_epoch:Epoch) {
+    pub fn process_msm1074(&mut self, msg:Msg1074T, msm_epoch:Epoch) -> Result<(), RtcmError> {
                               
         let mut satellites:HashMap<u8,&Msm46Sat>  = HashMap::new();
             
@@ -386,6 +770,14 @@ impl RtcmDecoder {
 
         let mut i = 0;
         for signal in msg.data_segment.signal_data.iter() {
+
+            let satellite = match satellites.get(&signal.satellite_id) {
+                Some(satellite) => satellite,
+                None => {
+                    self.handle_error(RtcmError::MissingSatellite {constellation: Constellation::GPS, satellite_id: signal.satellite_id})?;
+                    continue;
+                }
+            };
         
             let cnr_u8:Option<u8> = signal.gnss_signal_cnr_dbhz;
             let mut cnr_f64:Option<f64> = None;
@@ -399,24 +791,29 @@ impl RtcmDecoder {
                 satellite_id: signal.satellite_id, 
                 band: signal.signal_id.band(),
                 attribute: signal.signal_id.attribute(),
-                rough_range: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_range_integer_ms,
-                rough_range_mod1ms: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_range_mod1ms_ms as f64,
+                rough_range: satellite.gnss_satellite_rough_range_integer_ms,
+                rough_range_mod1ms: satellite.gnss_satellite_rough_range_mod1ms_ms as f64,
                 rough_phase_range_rate: None, 
                 loss_of_lock_indicator: signal.gnss_phaserange_lock_time_ind as u16,
+                extended_lock_time: false,
                 half_cycle_ambiguity: signal.half_cycle_ambiguity_ind,
+                extended_satellite_info: None,
                 fine_pseudo_range: signal.gnss_signal_fine_pseudorange_ms,
                 fine_phase_range: signal.gnss_signal_fine_phaserange_ms,
                 fine_phase_range_rate: None, 
                 cnr: cnr_f64
             };
 
-            self.process_signals(signal, msm_epoch);
+            if let Err(e) = self.process_signals(signal, msm_epoch) {
+                self.handle_error(e)?;
+            }
     
         }
-            
+
+        Ok(())
     }
 
-    pub fn process_msm1077(&mut self, msg:Msg1077T, msm_epoch:Epoch) {
+    pub fn process_msm1077(&mut self, msg:Msg1077T, msm_epoch:Epoch) -> Result<(), RtcmError> {
                                   
         let mut satellites:HashMap<u8,&Msm57Sat>  = HashMap::new();
             
@@ -425,30 +822,43 @@ impl RtcmDecoder {
         }
 
         for signal in msg.data_segment.signal_data.iter() {
+
+            let satellite = match satellites.get(&signal.satellite_id) {
+                Some(satellite) => satellite,
+                None => {
+                    self.handle_error(RtcmError::MissingSatellite {constellation: Constellation::GPS, satellite_id: signal.satellite_id})?;
+                    continue;
+                }
+            };
             
             let signal:MsmData  = MsmData {
                 constellation: Constellation::GPS, 
                 satellite_id: signal.satellite_id, 
                 band: signal.signal_id.band(),
                 attribute: signal.signal_id.attribute(),
-                rough_range: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_range_integer_ms,
-                rough_range_mod1ms: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_range_mod1ms_ms as f64,
-                rough_phase_range_rate: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_phaserange_rates_m_s,
+                rough_range: satellite.gnss_satellite_rough_range_integer_ms,
+                rough_range_mod1ms: satellite.gnss_satellite_rough_range_mod1ms_ms as f64,
+                rough_phase_range_rate: satellite.gnss_satellite_rough_phaserange_rates_m_s,
                 loss_of_lock_indicator: signal.gnss_phaserange_lock_time_ext_ind,
+                extended_lock_time: true,
                 half_cycle_ambiguity: signal.half_cycle_ambiguity_ind,
+                extended_satellite_info: Some(satellite.extended_satellite_info),
                 fine_pseudo_range: signal.gnss_signal_fine_pseudorange_ext_ms,
                 fine_phase_range: signal.gnss_signal_fine_phaserange_ext_ms,
                 fine_phase_range_rate: signal.gnss_signal_fine_phaserange_rate_m_s,
                 cnr: signal.gnss_signal_cnr_ext_dbhz,
             };
 
-            self.process_signals(signal, msm_epoch);
+            if let Err(e) = self.process_signals(signal, msm_epoch) {
+                self.handle_error(e)?;
+            }
 
         }
-            
+
+        Ok(())
     }
 
-    pub fn process_msm1094(&mut self, msg:Msg1094T,msm_epoch:Epoch) {
+    pub fn process_msm1094(&mut self, msg:Msg1094T,msm_epoch:Epoch) -> Result<(), RtcmError> {
                                   
         let mut satellites:HashMap<u8,&Msm46Sat>  = HashMap::new();
             
@@ -457,6 +867,14 @@ impl RtcmDecoder {
         }
 
         for signal in msg.data_segment.signal_data.iter() {
+
+            let satellite = match satellites.get(&signal.satellite_id) {
+                Some(satellite) => satellite,
+                None => {
+                    self.handle_error(RtcmError::MissingSatellite {constellation: Constellation::Galileo, satellite_id: signal.satellite_id})?;
+                    continue;
+                }
+            };
         
             let cnr_u8:Option<u8> = signal.gnss_signal_cnr_dbhz;
             let mut cnr_f64:Option<f64> = None;
@@ -466,28 +884,33 @@ impl RtcmDecoder {
             } 
 
             let signal:MsmData  = MsmData {
-                constellation: Constellation::GPS, 
+                constellation: Constellation::Galileo, 
                 satellite_id: signal.satellite_id, 
                 band: signal.signal_id.band(),
                 attribute: signal.signal_id.attribute(),
-                rough_range: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_range_integer_ms,
-                rough_range_mod1ms: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_range_mod1ms_ms as f64,
+                rough_range: satellite.gnss_satellite_rough_range_integer_ms,
+                rough_range_mod1ms: satellite.gnss_satellite_rough_range_mod1ms_ms as f64,
                 rough_phase_range_rate: None, 
                 loss_of_lock_indicator: signal.gnss_phaserange_lock_time_ind as u16,
+                extended_lock_time: false,
                 half_cycle_ambiguity: signal.half_cycle_ambiguity_ind,
+                extended_satellite_info: None,
                 fine_pseudo_range: signal.gnss_signal_fine_pseudorange_ms,
                 fine_phase_range: signal.gnss_signal_fine_phaserange_ms,
                 fine_phase_range_rate: None, 
                 cnr: cnr_f64
             };
 
-            self.process_signals(signal, msm_epoch);
+            if let Err(e) = self.process_signals(signal, msm_epoch) {
+                self.handle_error(e)?;
+            }
 
         }
-            
+
+        Ok(())
     }
 
-    pub fn process_msm1097(&mut self, msg:Msg1097T, msm_epoch:Epoch) {
+    pub fn process_msm1097(&mut self, msg:Msg1097T, msm_epoch:Epoch) -> Result<(), RtcmError> {
         
         let mut observations: BTreeMap<SV, HashMap<Observable, ObservationData>> = BTreeMap::new();
                                     
@@ -499,16 +922,26 @@ impl RtcmDecoder {
 
         for signal in msg.data_segment.signal_data.iter() {
 
+            let satellite = match satellites.get(&signal.satellite_id) {
+                Some(satellite) => satellite,
+                None => {
+                    self.handle_error(RtcmError::MissingSatellite {constellation: Constellation::Galileo, satellite_id: signal.satellite_id})?;
+                    continue;
+                }
+            };
+
             let signal:MsmData  = MsmData {
                 constellation: Constellation::Galileo, 
                 satellite_id: signal.satellite_id, 
                 band: signal.signal_id.band(),
                 attribute: signal.signal_id.attribute(),
-                rough_range: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_range_integer_ms,
-                rough_range_mod1ms: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_range_mod1ms_ms as f64,
-                rough_phase_range_rate: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_phaserange_rates_m_s,
+                rough_range: satellite.gnss_satellite_rough_range_integer_ms,
+                rough_range_mod1ms: satellite.gnss_satellite_rough_range_mod1ms_ms as f64,
+                rough_phase_range_rate: satellite.gnss_satellite_rough_phaserange_rates_m_s,
                 loss_of_lock_indicator: signal.gnss_phaserange_lock_time_ext_ind,
+                extended_lock_time: true,
                 half_cycle_ambiguity: signal.half_cycle_ambiguity_ind,
+                extended_satellite_info: Some(satellite.extended_satellite_info),
                 fine_pseudo_range: signal.gnss_signal_fine_pseudorange_ext_ms,
                 fine_phase_range: signal.gnss_signal_fine_phaserange_ext_ms,
                 fine_phase_range_rate: signal.gnss_signal_fine_phaserange_rate_m_s,
@@ -516,13 +949,16 @@ impl RtcmDecoder {
                 
             };
 
-            self.process_signals(signal, msm_epoch);
+            if let Err(e) = self.process_signals(signal, msm_epoch) {
+                self.handle_error(e)?;
+            }
             
         }
-            
+
+        Ok(())
     }
 
-    pub fn process_msm1127(&mut self, msg:Msg1127T, msm_epoch:Epoch) {
+    pub fn process_msm1127(&mut self, msg:Msg1127T, msm_epoch:Epoch) -> Result<(), RtcmError> {
         
         let mut observations: BTreeMap<SV, HashMap<Observable, ObservationData>> = BTreeMap::new();
                                     
@@ -534,16 +970,26 @@ impl RtcmDecoder {
 
         for signal in msg.data_segment.signal_data.iter() {
 
+            let satellite = match satellites.get(&signal.satellite_id) {
+                Some(satellite) => satellite,
+                None => {
+                    self.handle_error(RtcmError::MissingSatellite {constellation: Constellation::BeiDou, satellite_id: signal.satellite_id})?;
+                    continue;
+                }
+            };
+
             let signal:MsmData  = MsmData {
                 constellation: Constellation::BeiDou, 
                 satellite_id: signal.satellite_id, 
                 band: signal.signal_id.band(),
                 attribute: signal.signal_id.attribute(),
-                rough_range: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_range_integer_ms,
-                rough_range_mod1ms: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_range_mod1ms_ms as f64,
-                rough_phase_range_rate: satellites.get(&signal.satellite_id).unwrap().gnss_satellite_rough_phaserange_rates_m_s,
+                rough_range: satellite.gnss_satellite_rough_range_integer_ms,
+                rough_range_mod1ms: satellite.gnss_satellite_rough_range_mod1ms_ms as f64,
+                rough_phase_range_rate: satellite.gnss_satellite_rough_phaserange_rates_m_s,
                 loss_of_lock_indicator: signal.gnss_phaserange_lock_time_ext_ind,
+                extended_lock_time: true,
                 half_cycle_ambiguity: signal.half_cycle_ambiguity_ind,
+                extended_satellite_info: Some(satellite.extended_satellite_info),
                 fine_pseudo_range: signal.gnss_signal_fine_pseudorange_ext_ms,
                 fine_phase_range: signal.gnss_signal_fine_phaserange_ext_ms,
                 fine_phase_range_rate: signal.gnss_signal_fine_phaserange_rate_m_s,
@@ -551,144 +997,599 @@ impl RtcmDecoder {
                 
             };
 
-            self.process_signals(signal, msm_epoch);
+            if let Err(e) = self.process_signals(signal, msm_epoch) {
+                self.handle_error(e)?;
+            }
             
         }
-            
+
+        Ok(())
     }
 
     // convenience function for rinex library to build header table of observed signal codes by constellation (e.g. GPS: C1C, L5Q ... )
     pub fn extract_observed_signals(&self) -> HashSet<(Constellation, String)> {
+        extract_observed_signals(&self.rtcm_data)
+    }
+
+    /// Removes and returns all epochs before `epoch`, e.g. a completed session of a live stream.
+    pub fn take_data_before(&mut self, epoch:Epoch) -> RtcmData {
+
+        let remaining = self.rtcm_data.split_off(&(epoch, EpochFlag::Ok));
+        let taken = std::mem::replace(&mut self.rtcm_data, remaining);
+
+        self.first_epoch = self.rtcm_data.keys().next().map(|k| k.0);
+        self.last_epoch = self.rtcm_data.keys().next_back().map(|k| k.0);
+
+        if self.flushed_until.is_none() || self.flushed_until.unwrap() < epoch {
+            self.flushed_until = Some(epoch);
+        }
+
+        taken
+    }
+
+    /// Removes and returns the signal quality rows before `epoch`, the counterpart of `take_data_before`.
+    pub fn take_signal_quality_before(&mut self, epoch:Epoch) -> QualityData {
+        let (taken, remaining):(QualityData, QualityData) = std::mem::take(&mut self.signal_quality).into_iter().partition(|((e, _, _), _)| *e < epoch);
+        self.signal_quality = remaining;
+        taken
+    }
+
+    /// Removes and returns all signal quality rows, e.g. once they're written out so a checkpoint doesn't repeat them.
+    pub fn take_signal_quality(&mut self) -> QualityData {
+        std::mem::take(&mut self.signal_quality)
+    }
+
+    /// Writes the decoder state (week numbers, lock history, buffered partial frame, epochs not yet taken out
+    /// and the split position) to `path`, so a restarted conversion continues without flagging lock losses.
+    #[cfg(feature = "serde")]
+    pub fn save_state(&self, path:&Path) -> Result<(), RtcmError> {
+
+        let state = serde_json::to_vec(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // write then rename so a crash mid-write leaves the previous checkpoint intact
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, state)?;
+        std::fs::rename(&tmp_path, path)?;
+
+        debug!(file = path.to_str().unwrap_or_default(); "saved decoder checkpoint");
+
+        Ok(())
+    }
+
+    /// Restores a decoder saved with `save_state`.
+    #[cfg(feature = "serde")]
+    pub fn restore_state(path:&Path) -> Result<RtcmDecoder, RtcmError> {
+
+        let state = std::fs::read(path)?;
+        let rtcm_decoder:RtcmDecoder = serde_json::from_slice(&state).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        info!(file = path.to_str().unwrap_or_default(); "resuming from decoder checkpoint");
+
+        Ok(rtcm_decoder)
+    }
+
+    pub fn load_file(&mut self, file_path:&Path) -> Result<(), RtcmError> {
+
+        self.log_context.file = file_path.to_str().unwrap().to_string();
+
+        info!(file = self.log_context.file.as_str(); "converting rtcm file");
+
+        let (compression, rtcm_file) = compression::open_file(file_path)?;
+
+        if compression != compression::Compression::None {
+            debug!(file = self.log_context.file.as_str(); "decompressing {:?} input", compression);
+        }
+
+        let result = self.load_reader(rtcm_file);
+
+        // drop a truncated final frame rather than splicing it onto the next file
+        self.frame_buffer.finish();
+        self.record_garbage();
+        self.frame_buffer = FrameBuffer::new();
+
+        result
+    }
+
+    /// Decodes a serial device (or pty) stream until the port closes or the optional duration has elapsed.
+    pub fn load_serial(&mut self, device_path:&str, config:&SerialConfig, duration:Option<std::time::Duration>) -> Result<(), RtcmError> {
+        self.load_serial_with(device_path, config, duration, |_| {})
+    }
 
-        let mut observed_signals = HashSet::new();
+    /// `load_serial`, calling `on_read` after each read has been decoded, e.g. to write out completed sessions
+    /// or checkpoint while the stream is still open.
+    pub fn load_serial_with<F:FnMut(&mut RtcmDecoder)>(&mut self, device_path:&str, config:&SerialConfig, duration:Option<std::time::Duration>, mut on_read:F) -> Result<(), RtcmError> {
 
-        for epoch in self.rtcm_data.values() {
-            for sv in epoch.1.keys() {
-                let constellation = sv.constellation;
-                let observations = epoch.1.get(&sv).unwrap();
-                for observable in observations.keys() {
-                    let code = observable.code().unwrap();
-                    observed_signals.insert((constellation, code));
-                }   
+        self.log_context.file = device_path.to_string();
+
+        info!(file = device_path; "reading rtcm stream ({} baud)", config.baud_rate);
+
+        let mut port = serial::open(device_path, config)?;
+
+        let start = Instant::now();
+        let mut buffer = [0u8; READ_BUFFER_LEN];
+
+        loop {
+            if duration.is_some() && start.elapsed() >= duration.unwrap() {
+                break;
+            }
+
+            match port.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.decode_bytes(&buffer[..n])?;
+                    on_read(self);
+                }
+                Err(e) if serial::is_transient(&e) => continue,
+                // pty or usb device went away, treat as end of stream
+                Err(e) => {
+                    info!(file = device_path; "rtcm stream closed: {}", e);
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decodes everything readable from `reader` (file, pipe, socket ...).
+    pub fn load_reader<R:Read>(&mut self, mut reader:R) -> Result<(), RtcmError> {
+
+        let mut buffer = [0u8; READ_BUFFER_LEN];
+
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => self.decode_bytes(&buffer[..n])?,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(RtcmError::Io(e))
             }
         }
 
-        observed_signals
+        Ok(())
     }
 
-    pub fn load_file(&mut self, file_path:&Path) {
+    /// Feeds raw stream bytes to the decoder. Incomplete frames are held until the next call.
+    pub fn decode_bytes(&mut self, data:&[u8]) -> Result<(), RtcmError> {
+
+        self.frame_buffer.extend(data);
+
+        while let Some(frame) = self.frame_buffer.next_frame() {
+            self.record_garbage();
+            match frame {
+                Ok(frame) => self.process_frame(&frame)?,
+                Err(e) => self.handle_error(e)?
+            }
+        }
 
-        info!("converting rtcm file: {}", file_path.to_str().unwrap());
+        self.record_garbage();
 
-        let mut rtcm_file = File::open(file_path).expect(format!("Unable to open file: {}", file_path.to_str().unwrap()).as_str());
+        Ok(())
+    }
 
-        let mut rtcm_buffer = Vec::<u8>::new();
+    // counts and logs the byte ranges the framing layer skipped, keeping NMEA sentences found in them
+    fn record_garbage(&mut self) {
 
-        if let Ok(_) = rtcm_file.read_to_end(&mut rtcm_buffer) {
+        for garbage in self.frame_buffer.take_garbage() {
 
-            let mut iterator = MsgFrameIter::new(rtcm_buffer.as_slice());
+            let sentences = nmea::sentences(&garbage.data);
 
-            let mut gps_week:Option<u64>  = None;
-            let mut galileo_week:Option<u64>  = None;
-            let mut bds_week:Option<u64>  = None;
+            debug!(file = self.log_context.file.as_str(); "skipped {} bytes at offset {} ({} NMEA sentences)", garbage.data.len(), garbage.offset, sentences.len());
 
-       
+            self.stats.record_discarded_range(DiscardedRange {file: self.log_context.file.clone(), offset: garbage.offset, len: garbage.data.len() as u64, nmea_sentences: sentences.len() as u64});
 
-            for message_frame in &mut iterator {
-                if message_frame.message_number().is_some() {
-                
-                    let msg_data = message_frame.get_message();
-                    match msg_data {
-
-                        // gps ephemeris 
-                        Message::Msg1019(msg1019) => {
-                            // TODO handle GPS week rollover correctly
-                            gps_week = Some(msg1019.gps_week_number as u64 + 1024 + 1024);   
-                            println!("gps week: {}", gps_week.unwrap());
+            if self.extract_nmea {
+                self.nmea_sentences.extend(sentences);
+            }
+        }
+    }
+
+    // applies the error policy: skipped errors are counted and logged, fail-fast errors are returned
+    fn handle_error(&mut self, error:RtcmError) -> Result<(), RtcmError> {
+
+        if self.error_policy == ErrorPolicy::FailFast {
+            return Err(error);
+        }
+
+        match &error {
+            RtcmError::CrcMismatch{message_number} => {
+                // the failed frame, not the last message decoded
+                self.log_context.message_number = message_number.unwrap_or(0);
+                self.log_context.epoch = None;
+                self.stats.crc_failures += 1;
+            }
+            RtcmError::CorruptMessage{..} => self.stats.corrupt_messages += 1,
+            RtcmError::UnknownSignal{constellation, code} => *self.stats.unmapped_signals.entry((*constellation, code.clone())).or_insert(0) += 1,
+            RtcmError::MissingSatellite{..} => self.stats.missing_satellites += 1,
+            RtcmError::Io(_) => return Err(error)
+        }
+
+        warn!(file = self.log_context.file.as_str(), message_number = self.log_context.message_number, epoch = self.log_context.epoch_str().as_str(); "skipping: {}", error);
+
+        Ok(())
+    }
+
+    fn process_frame(&mut self, frame:&[u8]) -> Result<(), RtcmError> {
+
+        let mut iterator = MsgFrameIter::new(frame);
+
+        for message_frame in &mut iterator {
+            if message_frame.message_number().is_some() {
+
+                self.stats.record_message(message_frame.message_number().unwrap());
+                self.log_context.message_number = message_frame.message_number().unwrap();
+            
+                let msg_data = message_frame.get_message();
+                self.station.update(&msg_data);
+
+                match msg_data {
+
+                    // gps ephemeris 
+                    Message::Msg1019(msg1019) => {
+                        // TODO handle GPS week rollover correctly
+                        self.gps_week = Some(framing::gps_week_from_df076(msg1019.gps_week_number as u64));
+                        debug!(file = self.log_context.file.as_str(), message_number = 1019; "gps week: {}", self.gps_week.unwrap());
+                    }
+
+                    // galileo i/nav ephemeris (need to check f/nav 1042 as well?)
+                    Message::Msg1042(msg1042) => {
+                        self.bds_week = Some(msg1042.bds_week_number as u64);  
+                        debug!(file = self.log_context.file.as_str(), message_number = 1042; "beidou week: {}", self.bds_week.unwrap());
+                    }
+                    
+                    // galileo i/nav ephemeris (need to check f/nav 1042 as well?)
+                    Message::Msg1046(msg1046) => {
+                        self.galileo_week = Some(msg1046.gal_week_number as u64);  
+                        debug!(file = self.log_context.file.as_str(), message_number = 1046; "galileo week: {}", self.galileo_week.unwrap());
+                    }
+                    
+                    // gps msm7 
+                    Message::Msg1074(msg1074) => {
+                    
+                        // wait for ephemeris gpst week before processing MSM7
+                        if self.gps_week.is_some() {
+
+                            let time = msg1074.gps_epoch_time_ms as f64;
+                            let msm_epoch = rtcm_gps_time2epoch(time, self.gps_week.unwrap());
+                            self.log_context.epoch = Some(msm_epoch);
+                            
+                            self.process_msm1074(msg1074, msm_epoch)?;
+                        }
+                        else {
+                            self.stats.msm_waiting_for_week += 1;
+                        }  
+                    }      
+
+                    // gps msm7 
+                    Message::Msg1077(msg1077) => {
+                    
+                        // wait for ephemeris gpst week before processing MSM7
+                        if self.gps_week.is_some() {
+
+                            let time = msg1077.gps_epoch_time_ms as f64;
+                            let msm_epoch = rtcm_gps_time2epoch(time, self.gps_week.unwrap());
+                            self.log_context.epoch = Some(msm_epoch);
+
+                            self.process_msm1077(msg1077, msm_epoch)?;
                         }
+                        else {
+                            self.stats.msm_waiting_for_week += 1;
+                        }
+                    }      
+
+                    // galileo msm7 
+                    Message::Msg1094(msg1094) => {
+                    
+                        // wait for ephemeris gpst week before processing MSM7
+                        if self.galileo_week.is_some() {
+                            let time = msg1094.gal_epoch_time_ms as f64;
+                            let msm_epoch = rtcm_galileo_time2epoch(time, self.galileo_week.unwrap());
+                            self.log_context.epoch = Some(msm_epoch);
+
+                            self.process_msm1094(msg1094, msm_epoch)?;
+                        }
+                        else {
+                            self.stats.msm_waiting_for_week += 1;
+                        }
+                    }         
+
+                    // galileo msm7 
+                    Message::Msg1097(msg1097) => {
+                    
+                        // wait for ephemeris gpst week before processing MSM7
+                        if self.galileo_week.is_some() {
+                            let time = msg1097.gal_epoch_time_ms as f64;
+                            let msm_epoch = rtcm_galileo_time2epoch(time, self.galileo_week.unwrap());
+                            self.log_context.epoch = Some(msm_epoch);
+
+                            self.process_msm1097(msg1097, msm_epoch)?;
+                        }
+                        else {
+                            self.stats.msm_waiting_for_week += 1;
+                        }            
+                    } 
+
+                    // bds msm7 
+                    Message::Msg1127(msg1127) => {
+                    
+                        // wait for ephemeris gpst week before processing MSM7
+                        if self.bds_week.is_some() {
+                            let time = msg1127.bds_epoch_time_ms as f64;
+                            let msm_epoch = rtcm_bds_time2epoch(time, self.bds_week.unwrap());
+                            self.log_context.epoch = Some(msm_epoch);
+                            self.process_msm1127(msg1127, msm_epoch)?;
 
-                        // galileo i/nav ephemeris (need to check f/nav 1042 as well?)
-                        Message::Msg1042(msg1042) => {
-                            bds_week = Some(msg1042.bds_week_number as u64);  
-                            println!("beidou week: {}", bds_week.unwrap());
                         }
-                        
-                        // galileo i/nav ephemeris (need to check f/nav 1042 as well?)
-                        Message::Msg1046(msg1046) => {
-                            galileo_week = Some(msg1046.gal_week_number as u64);  
-                            println!("galileo week: {}", galileo_week.unwrap());
+                        else {
+                            self.stats.msm_waiting_for_week += 1;
                         }
+                    }              
+
+                    Message::Corrupt => {
+                        self.handle_error(RtcmError::CorruptMessage {message_number: message_frame.message_number()})?;
+                    }
+
+                    _ => {
                         
-                        // gps msm7 
-                        Message::Msg1074(msg1074) => {
-                        
-                            // wait for ephemeris gpst week before processing MSM7
-                            if gps_week.is_some() {
-
-                                let time = msg1074.gps_epoch_time_ms as f64;
-                                let msm_epoch = rtcm_gps_time2epoch(time, gps_week.unwrap());
-                                
-                                self.process_msm1074(msg1074, msm_epoch);
-                            }  
-                        }      
-
-                        // gps msm7 
-                        Message::Msg1077(msg1077) => {
-                        
-                            // wait for ephemeris gpst week before processing MSM7
-                            if gps_week.is_some() {
+                    }
+                }
+            }   
+        }
 
-                                let time = msg1077.gps_epoch_time_ms as f64;
-                                let msm_epoch = rtcm_gps_time2epoch(time, gps_week.unwrap());
+        Ok(())
+    }
+}
 
-                                self.process_msm1077(msg1077, msm_epoch);
-                            }
-                        }      
 
-                        // galileo msm7 
-                        Message::Msg1094(msg1094) => {
-                        
-                            // wait for ephemeris gpst week before processing MSM7
-                            if galileo_week.is_some() {
-                                let time = msg1094.gal_epoch_time_ms as f64;
-                                let msm_epoch = rtcm_galileo_time2epoch(time, galileo_week.unwrap());
+#[cfg(test)]
+mod tests {
 
-                                self.process_msm1094(msg1094, msm_epoch);
-                            }
-                        }         
+    use super::*;
 
-                        // galileo msm7 
-                        Message::Msg1097(msg1097) => {
-                        
-                            // wait for ephemeris gpst week before processing MSM7
-                            if galileo_week.is_some() {
-                                let time = msg1097.gal_epoch_time_ms as f64;
-                                let msm_epoch = rtcm_galileo_time2epoch(time, galileo_week.unwrap());
+    fn gps_sv() -> SV {
+        SV {constellation: Constellation::GPS, prn: 1}
+    }
 
-                                self.process_msm1097(msg1097, msm_epoch);
-                            }            
-                        } 
+    #[test]
+    fn minimum_lock_time_table() {
+        assert_eq!(LockStatus::calculate_minimum_lock_time(0), 0);
+        assert_eq!(LockStatus::calculate_minimum_lock_time(63), 63);
+        assert_eq!(LockStatus::calculate_minimum_lock_time(64), 64);
+        assert_eq!(LockStatus::calculate_minimum_lock_time(96), 128);
+        assert_eq!(LockStatus::calculate_minimum_lock_time(480), 524288);
+        assert_eq!(LockStatus::calculate_minimum_lock_time(704), 67108864);
+        assert_eq!(LockStatus::calculate_minimum_lock_time(705), 0);
+        assert_eq!(LockStatus::get_lli_coefficient(64), 2);
+        assert_eq!(LockStatus::get_lli_coefficient(705), 0);
+    }
 
-                        // bds msm7 
-                        Message::Msg1127(msg1127) => {
-                        
-                            // wait for ephemeris gpst week before processing MSM7
-                            if bds_week.is_some() {
-                                let time = msg1127.bds_epoch_time_ms as f64;
-                                let msm_epoch = rtcm_bds_time2epoch(time, bds_week.unwrap());
-                                self.process_msm1127(msg1127, msm_epoch);
+    #[test]
+    fn df407_previous_longer_than_current() {
+        // p = 136, n = 50
+        assert!(LockStatus::df407_lock_loss(100, 50, 1000));
+    }
 
-                            }
-                        }              
+    #[test]
+    fn df407_unchanged_lock_time() {
+        // p = n = 10, a = 1
+        assert!(LockStatus::df407_lock_loss(10, 10, 1));
+        assert!(!LockStatus::df407_lock_loss(10, 10, 0));
+    }
 
-                        _ => {
-                            
-                        }
-                    }
-                }   
+    #[test]
+    fn df407_coefficient_above_previous() {
+        // p = 0, n = 10, b = 1: loss once dt >= n + b - p = 11
+        assert!(LockStatus::df407_lock_loss(0, 10, 11));
+        assert!(!LockStatus::df407_lock_loss(0, 10, 10));
+        // p = 0, n = 64, b = 2: n < dt < n + b - p
+        assert!(LockStatus::df407_lock_loss(0, 64, 65));
+    }
+
+    #[test]
+    fn df407_coefficient_within_previous() {
+        // p = 5, n = 10, b = 1
+        assert!(LockStatus::df407_lock_loss(5, 10, 11));
+        assert!(!LockStatus::df407_lock_loss(5, 10, 10));
+    }
+
+    #[test]
+    fn gap_not_covered_by_lock_time() {
+        // p = 524.288 s, n = 540.672 s, b = 16.384 s: lock time grew by at most 32.768 s
+        assert!(!LockStatus::gap_exceeds_lock_time(480, 481, true, 32767));
+        assert!(LockStatus::gap_exceeds_lock_time(480, 481, true, 32768));
+        // the DF407 table alone passes a 3 minute outage
+        assert!(!LockStatus::df407_lock_loss(480, 481, 180000));
+    }
+
+    #[test]
+    fn outage_flags_lock_loss() {
+
+        let code = "1C".to_string();
+        let t0 = Epoch::from_gpst_seconds(1e9);
+
+        let mut learned = LockStatus::new(false);
+        learned.update_lock_status(&gps_sv(), &code, &t0, 479, true, 0);
+        let lli = learned.update_lock_status(&gps_sv(), &code, &(t0 + Duration::from_seconds(1.0)), 480, true, 0).unwrap();
+        assert!(!lli.intersects(LliFlags::LOCK_LOSS));
+
+        // signal gone for 180 s, back with a lock time that only grew by ~16 s
+        let lli = learned.update_lock_status(&gps_sv(), &code, &(t0 + Duration::from_seconds(181.0)), 481, true, 0).unwrap();
+        assert!(lli.intersects(LliFlags::LOCK_LOSS));
+
+        // same spacing is the normal interval when configured that way, no outage
+        let mut configured = LockStatus::new(false);
+        configured.set_expected_interval(Some(Duration::from_seconds(300.0)));
+        configured.update_lock_status(&gps_sv(), &code, &t0, 479, true, 0);
+        configured.update_lock_status(&gps_sv(), &code, &(t0 + Duration::from_seconds(1.0)), 480, true, 0);
+        let lli = configured.update_lock_status(&gps_sv(), &code, &(t0 + Duration::from_seconds(181.0)), 481, true, 0).unwrap();
+        assert!(!lli.intersects(LliFlags::LOCK_LOSS));
+    }
+
+    #[test]
+    fn steady_msm4_lock() {
+
+        let code = "1C".to_string();
+        let t0 = Epoch::from_gpst_seconds(1e9);
+
+        // DF402 indicator of a lock time (ms): 2^(i + 4) <= t < 2^(i + 5)
+        let df402 = |lock_time:u64| (63 - lock_time.leading_zeros() as u16).saturating_sub(4);
+
+        // 1 Hz MSM4 signal locked for 20 s, 40 epochs through indicators 10 and 11
+        let mut lock_status = LockStatus::new(false);
+        for k in 0..40u64 {
+            let lli = lock_status.update_lock_status(&gps_sv(), &code, &(t0 + Duration::from_seconds(k as f64)), df402(20000 + k * 1000), false, 0).unwrap();
+            assert!(!lli.intersects(LliFlags::LOCK_LOSS));
+        }
+
+        // the DF407 table reads an unchanged 10 as a lock that didn't grow
+        assert!(LockStatus::df407_lock_loss(10, 10, 1000));
+        assert!(!LockStatus::df402_lock_loss(10, 10, 1000));
+
+        // reacquired: indicator drops back
+        let lli = lock_status.update_lock_status(&gps_sv(), &code, &(t0 + Duration::from_seconds(40.0)), df402(500), false, 0).unwrap();
+        assert!(lli.intersects(LliFlags::LOCK_LOSS));
+    }
+
+    #[test]
+    fn df402_lock_time_bounds() {
+        // indicator 10: 16.384 s to 32.768 s
+        assert_eq!(quality::df402_minimum_lock_time(10), 16384);
+        assert!(!LockStatus::df402_lock_loss(10, 10, 16383));
+        assert!(LockStatus::df402_lock_loss(10, 10, 16384));
+        // 10 -> 11 (32.768 s to 65.536 s): grew by at most 65.536 - 16.384 s
+        assert!(!LockStatus::df402_lock_loss(10, 11, 49151));
+        assert!(LockStatus::df402_lock_loss(10, 11, 49152));
+        // the last indicator has no upper bound
+        assert!(!LockStatus::df402_lock_loss(15, 15, 3600000));
+        assert!(LockStatus::gap_exceeds_lock_time(10, 11, false, 49152));
+        assert!(!LockStatus::gap_exceeds_lock_time(10, 11, false, 49151));
+    }
+
+    // debug.rtcm's Galileo observations re-encoded as MSM4 (1094) behind its 1046 ephemeris
+    fn galileo_msm4_stream() -> (RtcmData, Vec<u8>) {
+
+        let rtcm_buffer = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm")).unwrap();
+
+        let mut rtcm_decoder = RtcmDecoder::new(false);
+        rtcm_decoder.set_filter(SignalFilter {systems: vec![Constellation::Galileo], ..SignalFilter::default()});
+        rtcm_decoder.decode_bytes(&rtcm_buffer).unwrap();
+        let galileo = rtcm_decoder.get_rtcm_data();
+
+        let mut stream = rtcm_buffer[67..136].to_vec();
+        let mut encoder = encoder::RtcmEncoder::new(0, encoder::MsmType::Msm4);
+        for ((epoch, _), (_, satellites)) in galileo.iter() {
+            for frame in encoder.encode_epoch(*epoch, satellites) {
+                assert_eq!(framing::message_number(&frame), Some(1094));
+                stream.extend(frame);
             }
         }
+
+        (galileo, stream)
     }
-}
 
+    #[test]
+    fn msm1094_is_galileo() {
+
+        let (galileo, stream) = galileo_msm4_stream();
+
+        let mut rtcm_decoder = RtcmDecoder::new(false);
+        rtcm_decoder.decode_bytes(&stream).unwrap();
+        let rtcm_data = rtcm_decoder.get_rtcm_data();
+
+        assert_eq!(rtcm_decoder.get_stats().message_counts.get(&1094), Some(&(galileo.len() as u64)));
+        assert_eq!(rtcm_data.len(), galileo.len());
+
+        // same satellites and signals as the MSM7 source, less the doppler MSM4 doesn't carry
+        for (key, (_, satellites)) in galileo.iter() {
+            let decoded = &rtcm_data.get(key).unwrap().1;
+            assert_eq!(decoded.keys().collect::<Vec<_>>(), satellites.keys().collect::<Vec<_>>());
+            for (sv, observations) in satellites.iter() {
+                let mut expected:Vec<String> = observations.keys().filter(|o| !matches!(o, Observable::Doppler(_))).map(|o| o.to_string()).collect();
+                let mut codes:Vec<String> = decoded.get(sv).unwrap().keys().map(|o| o.to_string()).collect();
+                expected.sort();
+                codes.sort();
+                assert_eq!(codes, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn system_filter_applies_to_galileo_msm4() {
+
+        let (_, stream) = galileo_msm4_stream();
+
+        let mut galileo_decoder = RtcmDecoder::new(false);
+        galileo_decoder.set_filter(SignalFilter {systems: vec![Constellation::Galileo], ..SignalFilter::default()});
+        galileo_decoder.decode_bytes(&stream).unwrap();
+        assert!(galileo_decoder.get_rtcm_data().values().all(|(_, satellites)| !satellites.is_empty()));
+        assert_eq!(galileo_decoder.get_stats().filtered_signals, 0);
+
+        let mut gps_decoder = RtcmDecoder::new(false);
+        gps_decoder.set_filter(SignalFilter {systems: vec![Constellation::GPS], ..SignalFilter::default()});
+        gps_decoder.decode_bytes(&stream).unwrap();
+        assert!(gps_decoder.get_rtcm_data().values().all(|(_, satellites)| satellites.is_empty()));
+        assert!(gps_decoder.get_stats().filtered_signals > 0);
+    }
+
+    #[test]
+    fn clear_keeps_lock_history_and_weeks() {
+
+        let rtcm_buffer = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm")).unwrap();
 
+        let mut rtcm_decoder = RtcmDecoder::new(false);
+        rtcm_decoder.decode_bytes(&rtcm_buffer).unwrap();
+        rtcm_decoder.clear();
+
+        assert!(rtcm_decoder.get_rtcm_data().is_empty());
+        assert_eq!(rtcm_decoder.get_stats().frames, 0);
+        assert!(rtcm_decoder.get_stats().signal_epochs.is_empty());
+
+        // MSMs alone decode (weeks kept) and are recognised as already seen (lock history kept)
+        rtcm_decoder.decode_bytes(&rtcm_buffer[136..]).unwrap();
+        assert_eq!(rtcm_decoder.get_stats().msm_waiting_for_week, 0);
+        assert!(rtcm_decoder.get_stats().duplicate_signals > 0);
+    }
+
+    // hash map fields serialize in iteration order, compare as sorted lists
+    #[cfg(feature = "serde")]
+    fn canonical(value:serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Array(values) => {
+                let mut values:Vec<serde_json::Value> = values.into_iter().map(canonical).collect();
+                values.sort_by_key(|v| v.to_string());
+                serde_json::Value::Array(values)
+            }
+            serde_json::Value::Object(fields) => serde_json::Value::Object(fields.into_iter().map(|(k, v)| (k, canonical(v))).collect()),
+            value => value
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn state_round_trip() {
+
+        let rtcm_buffer = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm")).unwrap();
+
+        // stop mid frame so the partial frame is part of the state
+        let mut rtcm_decoder = RtcmDecoder::new(false);
+        rtcm_decoder.set_collect_quality(true);
+        rtcm_decoder.decode_bytes(&rtcm_buffer[..rtcm_buffer.len() - 50]).unwrap();
+
+        let path = std::env::temp_dir().join("rtcmlib_state_round_trip.json");
+        rtcm_decoder.save_state(&path).unwrap();
+        let mut restored = RtcmDecoder::restore_state(&path).unwrap();
+
+        assert_eq!(canonical(serde_json::to_value(&restored).unwrap()), canonical(serde_json::to_value(&rtcm_decoder).unwrap()));
+        assert_eq!(restored.get_rtcm_data(), rtcm_decoder.get_rtcm_data());
+        assert_eq!(restored.get_first_epoch(), rtcm_decoder.get_first_epoch());
+        assert_eq!(restored.get_last_epoch(), rtcm_decoder.get_last_epoch());
+        assert_eq!(restored.get_station_info(), rtcm_decoder.get_station_info());
+        assert_eq!(restored.get_signal_quality(), rtcm_decoder.get_signal_quality());
+        assert_eq!(restored.get_stats().message_counts, rtcm_decoder.get_stats().message_counts);
+
+        // both finish the partial frame the same way
+        rtcm_decoder.decode_bytes(&rtcm_buffer[rtcm_buffer.len() - 50..]).unwrap();
+        restored.decode_bytes(&rtcm_buffer[rtcm_buffer.len() - 50..]).unwrap();
+        assert_eq!(restored.get_rtcm_data(), rtcm_decoder.get_rtcm_data());
+        assert_eq!(restored.get_stats().frames, rtcm_decoder.get_stats().frames);
+    }
+}
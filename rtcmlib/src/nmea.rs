@@ -0,0 +1,59 @@
+// NMEA 0183 sentences interleaved with RTCM on radio links, recovered from the bytes the framing layer discards
+
+// longest valid sentence is 82 characters including $ and CR LF, allow some slack for proprietary ones
+const MAX_SENTENCE_LEN:usize = 128;
+
+fn checksum(body:&[u8]) -> u8 {
+    body.iter().fold(0u8, |checksum, b| checksum ^ b)
+}
+
+// `$...*hh` with printable characters and a matching checksum
+fn valid(sentence:&[u8]) -> bool {
+
+    if sentence.len() < 4 || sentence.len() > MAX_SENTENCE_LEN {
+        return false;
+    }
+
+    let star = sentence.len() - 3;
+    if sentence[star] != b'*' || !sentence[1..].iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        return false;
+    }
+
+    let expected = std::str::from_utf8(&sentence[star + 1..]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    expected == Some(checksum(&sentence[1..star]))
+}
+
+/// Checksum-valid `$` (or `!` encapsulated) sentences in `data`, without line endings, in input order.
+pub fn sentences(data:&[u8]) -> Vec<String> {
+
+    let mut sentences:Vec<String> = Vec::new();
+    let mut start:Option<usize> = None;
+
+    for (i, b) in data.iter().enumerate() {
+        match b {
+            b'$' | b'!' => start = Some(i),
+            b'\r' | b'\n' => {
+                if let Some(s) = start {
+                    if valid(&data[s..i]) {
+                        sentences.push(String::from_utf8_lossy(&data[s..i]).to_string());
+                    }
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    sentences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_checksum_valid_sentences() {
+        let data = b"\xd3\x00$GPGGA,092750.000,5321.6802,N,00630.3372,W,1,8,1.03,61.7,M,55.2,M,,*76\r\n$GPGGA,1*00\r\n$GPRMC,trunc";
+        assert_eq!(sentences(data), vec!["$GPGGA,092750.000,5321.6802,N,00630.3372,W,1,8,1.03,61.7,M,55.2,M,,*76".to_string()]);
+    }
+}
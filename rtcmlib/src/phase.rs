@@ -0,0 +1,177 @@
+// quarter cycle phase alignment to the RINEX 3 reference signal of each frequency band
+// see RINEX 3.04 section 5.16 / table A23
+
+use std::collections::BTreeMap;
+
+use rinex::prelude::{Constellation, Observable};
+
+use crate::RtcmData;
+
+/// Correction (cycles) that aligns phase of `code` (e.g. 2L) to the band's reference signal, 0 for reference signals.
+pub fn phase_shift(constellation:Constellation, code:&str) -> f64 {
+    // combined I+Q / data+pilot tracking (X) of L5, E5a, E5b, E5, B1, B2 and B3 is in phase with the reference
+    match (constellation, code) {
+        (Constellation::GPS, "1S" | "1L" | "1X" | "1P" | "1W" | "1Y" | "1M") => 0.25,
+        (Constellation::GPS, "2C" | "2S" | "2L" | "2X") => -0.25,
+        (Constellation::GPS, "5Q") => -0.25,
+        (Constellation::Galileo, "1C") => 0.5,
+        (Constellation::Galileo, "5Q" | "7Q" | "8Q") => -0.25,
+        (Constellation::Galileo, "6C") => -0.5,
+        (Constellation::BeiDou, "2Q" | "7Q" | "6Q") => -0.25,
+        (Constellation::BeiDou, "1P" | "5P" | "7P") => 0.25,
+        _ => 0.0
+    }
+}
+
+fn system_letter(constellation:Constellation) -> char {
+    match constellation {
+        Constellation::GPS => 'G',
+        Constellation::Galileo => 'E',
+        Constellation::BeiDou => 'C',
+        Constellation::Glonass => 'R',
+        Constellation::QZSS => 'J',
+        Constellation::IRNSS => 'I',
+        _ => 'S'
+    }
+}
+
+/// `SYS / PHASE SHIFT` header records for phase aligned data: one per shifted signal,
+/// or a bare system record when none of that system's signals needed a shift.
+pub fn phase_shift_records(rtcm_data:&RtcmData) -> Vec<String> {
+
+    let mut shifts:BTreeMap<char, BTreeMap<String, f64>> = BTreeMap::new();
+
+    for epoch in rtcm_data.values() {
+        for (sv, observations) in epoch.1.iter() {
+            let system_shifts = shifts.entry(system_letter(sv.constellation)).or_insert(BTreeMap::new());
+            for observable in observations.keys() {
+                if let Observable::Phase(code) = observable {
+                    let shift = phase_shift(sv.constellation, code.trim_start_matches('L'));
+                    if shift != 0.0 {
+                        system_shifts.insert(code.clone(), shift);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut records:Vec<String> = Vec::new();
+
+    for (system, system_shifts) in shifts.iter() {
+        if system_shifts.is_empty() {
+            records.push(format!("{:<60}SYS / PHASE SHIFT", system));
+        }
+        for (code, shift) in system_shifts.iter() {
+            records.push(format!("{:<60}SYS / PHASE SHIFT", format!("{} {:<3} {:8.5}", system, code, shift)));
+        }
+    }
+
+    records
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use rinex::{observation::{EpochFlag, ObservationData}, prelude::{Epoch, SV}};
+
+    use super::*;
+
+    #[test]
+    fn shifts_match_table_a23() {
+        // RINEX 3.04 table A23, every GPS, Galileo and BeiDou row
+        let table:&[(Constellation, &str, f64)] = &[
+            (Constellation::GPS, "1C", 0.0),
+            (Constellation::GPS, "1S", 0.25),
+            (Constellation::GPS, "1L", 0.25),
+            (Constellation::GPS, "1X", 0.25),
+            (Constellation::GPS, "1P", 0.25),
+            (Constellation::GPS, "1W", 0.25),
+            (Constellation::GPS, "1Y", 0.25),
+            (Constellation::GPS, "1M", 0.25),
+            (Constellation::GPS, "2C", -0.25),
+            (Constellation::GPS, "2D", 0.0),
+            (Constellation::GPS, "2S", -0.25),
+            (Constellation::GPS, "2L", -0.25),
+            (Constellation::GPS, "2X", -0.25),
+            (Constellation::GPS, "2P", 0.0),
+            (Constellation::GPS, "2W", 0.0),
+            (Constellation::GPS, "2Y", 0.0),
+            (Constellation::GPS, "2M", 0.0),
+            (Constellation::GPS, "5I", 0.0),
+            (Constellation::GPS, "5Q", -0.25),
+            (Constellation::GPS, "5X", 0.0),
+            (Constellation::Galileo, "1A", 0.0),
+            (Constellation::Galileo, "1B", 0.0),
+            (Constellation::Galileo, "1C", 0.5),
+            (Constellation::Galileo, "1X", 0.0),
+            (Constellation::Galileo, "1Z", 0.0),
+            (Constellation::Galileo, "5I", 0.0),
+            (Constellation::Galileo, "5Q", -0.25),
+            (Constellation::Galileo, "5X", 0.0),
+            (Constellation::Galileo, "7I", 0.0),
+            (Constellation::Galileo, "7Q", -0.25),
+            (Constellation::Galileo, "7X", 0.0),
+            (Constellation::Galileo, "8I", 0.0),
+            (Constellation::Galileo, "8Q", -0.25),
+            (Constellation::Galileo, "8X", 0.0),
+            (Constellation::Galileo, "6A", 0.0),
+            (Constellation::Galileo, "6B", 0.0),
+            (Constellation::Galileo, "6C", -0.5),
+            (Constellation::Galileo, "6X", 0.0),
+            (Constellation::Galileo, "6Z", 0.0),
+            (Constellation::BeiDou, "2I", 0.0),
+            (Constellation::BeiDou, "2Q", -0.25),
+            (Constellation::BeiDou, "2X", 0.0),
+            (Constellation::BeiDou, "1D", 0.0),
+            (Constellation::BeiDou, "1P", 0.25),
+            (Constellation::BeiDou, "1X", 0.0),
+            (Constellation::BeiDou, "5D", 0.0),
+            (Constellation::BeiDou, "5P", 0.25),
+            (Constellation::BeiDou, "5X", 0.0),
+            (Constellation::BeiDou, "7I", 0.0),
+            (Constellation::BeiDou, "7Q", -0.25),
+            (Constellation::BeiDou, "7X", 0.0),
+            (Constellation::BeiDou, "7D", 0.0),
+            (Constellation::BeiDou, "7P", 0.25),
+            (Constellation::BeiDou, "7Z", 0.0),
+            (Constellation::BeiDou, "6I", 0.0),
+            (Constellation::BeiDou, "6Q", -0.25),
+            (Constellation::BeiDou, "6X", 0.0),
+        ];
+
+        for (constellation, code, shift) in table {
+            assert_eq!(phase_shift(*constellation, code), *shift, "{:?} {}", constellation, code);
+        }
+
+        // systems without a table here aren't shifted
+        assert_eq!(phase_shift(Constellation::Glonass, "1C"), 0.0);
+    }
+
+    #[test]
+    fn records_per_shifted_signal() {
+        let observation = ObservationData {obs: 1.0, lli: None, snr: None};
+
+        let mut gps:HashMap<Observable, ObservationData> = HashMap::new();
+        gps.insert(Observable::Phase("L1C".to_string()), observation.clone());
+        gps.insert(Observable::Phase("L5Q".to_string()), observation.clone());
+        gps.insert(Observable::PseudoRange("C5Q".to_string()), observation.clone());
+
+        let mut beidou:HashMap<Observable, ObservationData> = HashMap::new();
+        beidou.insert(Observable::Phase("L2I".to_string()), observation.clone());
+
+        let mut satellites = BTreeMap::new();
+        satellites.insert(SV {constellation: Constellation::GPS, prn: 1}, gps);
+        satellites.insert(SV {constellation: Constellation::BeiDou, prn: 7}, beidou);
+
+        let mut rtcm_data:RtcmData = BTreeMap::new();
+        rtcm_data.insert((Epoch::from_gpst_seconds(1_400_000_000.0), EpochFlag::Ok), (None, satellites));
+
+        let records = phase_shift_records(&rtcm_data);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], format!("{:<60}SYS / PHASE SHIFT", "C"));
+        assert_eq!(records[1], format!("{:<60}SYS / PHASE SHIFT", "G L5Q -0.25000"));
+        assert!(records.iter().all(|record| record.len() == 77));
+    }
+}
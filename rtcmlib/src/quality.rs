@@ -0,0 +1,63 @@
+// raw MSM signal quality fields kept alongside decoded observations, for QC beyond the collapsed LliFlags
+
+use std::collections::BTreeMap;
+
+use rinex::prelude::{Epoch, SV};
+
+use crate::LockStatus;
+
+/// Quality fields by epoch, satellite and signal code (e.g. 1C).
+pub type QualityData = BTreeMap<(Epoch, SV, String), SignalQuality>;
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignalQuality {
+    /// raw lock time indicator: DF402 (MSM4, 4 bit) or DF407 (MSM7, 10 bit)
+    pub lock_time_indicator:u16,
+    /// true when `lock_time_indicator` is the extended DF407 indicator
+    pub extended_lock_time:bool,
+    /// minimum lock time (ms) for the indicator
+    pub minimum_lock_time_ms:u64,
+    /// DF420 half-cycle ambiguity
+    pub half_cycle_ambiguity:bool,
+    /// MSM5/MSM7 extended satellite info (DF419 for GLONASS, reserved otherwise)
+    pub extended_satellite_info:Option<u8>
+}
+
+/// Minimum lock time (ms) for a DF402 indicator: 0 below 32 ms, then doubling from 32 ms to 524288 ms.
+pub fn df402_minimum_lock_time(indicator:u16) -> u64 {
+    match indicator {
+        0 => 0,
+        1..=15 => 1 << (indicator + 4),
+        _ => 0
+    }
+}
+
+/// How much longer (ms) than its minimum the lock time of a DF402 indicator can be, unbounded for the last one.
+pub fn df402_lock_time_range(indicator:u16) -> u64 {
+    match indicator {
+        0 => 32,
+        1..=14 => 1 << (indicator + 4),
+        _ => u64::MAX
+    }
+}
+
+impl SignalQuality {
+    pub fn new(lock_time_indicator:u16, extended_lock_time:bool, half_cycle_ambiguity:u8, extended_satellite_info:Option<u8>) -> Self {
+
+        let minimum_lock_time_ms = if extended_lock_time {
+            LockStatus::calculate_minimum_lock_time(lock_time_indicator)
+        }
+        else {
+            df402_minimum_lock_time(lock_time_indicator)
+        };
+
+        Self {
+            lock_time_indicator,
+            extended_lock_time,
+            minimum_lock_time_ms,
+            half_cycle_ambiguity: half_cycle_ambiguity > 0,
+            extended_satellite_info
+        }
+    }
+}
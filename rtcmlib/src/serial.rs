@@ -0,0 +1,57 @@
+// serial (or pseudo-terminal) device input for live RTCM streams from a receiver
+
+use std::{io, time::Duration};
+
+pub use serialport::{FlowControl, Parity, SerialPort};
+
+// default read timeout, keeps the read loop responsive when the receiver goes quiet
+const DEFAULT_TIMEOUT_MS:u64 = 1000;
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SerialConfig {
+    pub baud_rate:u32,
+    pub parity:Parity,
+    pub flow_control:FlowControl,
+    pub timeout:Duration
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {baud_rate:115200, parity:Parity::None, flow_control:FlowControl::None, timeout:Duration::from_millis(DEFAULT_TIMEOUT_MS)}
+    }
+}
+
+pub fn open(path:&str, config:&SerialConfig) -> io::Result<Box<dyn SerialPort>> {
+
+    let port = serialport::new(path, config.baud_rate)
+        .parity(config.parity)
+        .flow_control(config.flow_control)
+        .timeout(config.timeout)
+        .open()?;
+
+    Ok(port)
+}
+
+pub fn parse_parity(parity:&str) -> Option<Parity> {
+    match parity.to_lowercase().as_str() {
+        "none" | "n" => Some(Parity::None),
+        "odd" | "o" => Some(Parity::Odd),
+        "even" | "e" => Some(Parity::Even),
+        _ => None
+    }
+}
+
+pub fn parse_flow_control(flow_control:&str) -> Option<FlowControl> {
+    match flow_control.to_lowercase().as_str() {
+        "none" => Some(FlowControl::None),
+        "software" | "xonxoff" => Some(FlowControl::Software),
+        "hardware" | "rtscts" => Some(FlowControl::Hardware),
+        _ => None
+    }
+}
+
+// errors that indicate the read should be retried rather than ending the stream
+pub fn is_transient(error:&io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock)
+}
@@ -0,0 +1,157 @@
+// serde helpers for maps json can't key directly (tuple, SV or Observable keys)
+// use with #[serde(with = "...")] on fields, or rtcm_data::serialize / deserialize for RtcmData snapshots
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Serializes a map as a list of `[key, value]` pairs.
+pub mod pairs {
+
+    use super::*;
+
+    pub fn serialize<'a, K, V, M, S>(map:&'a M, serializer:S) -> Result<S::Ok, S::Error>
+    where
+        &'a M:IntoIterator<Item = (&'a K, &'a V)>,
+        K:Serialize + 'a,
+        V:Serialize + 'a,
+        S:Serializer
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, K, V, M, D>(deserializer:D) -> Result<M, D::Error>
+    where
+        K:Deserialize<'de>,
+        V:Deserialize<'de>,
+        M:FromIterator<(K, V)>,
+        D:Deserializer<'de>
+    {
+        let pairs:Vec<(K, V)> = Vec::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+/// `RtcmData` as a list of epochs, each with its satellites and observables sorted so snapshots are stable.
+pub mod rtcm_data {
+
+    use std::collections::{BTreeMap, HashMap};
+
+    use rinex::{observation::{EpochFlag, ObservationData}, prelude::{Epoch, Observable, SV}};
+
+    use super::*;
+    use crate::RtcmData;
+
+    #[derive(Serialize, Deserialize)]
+    struct EpochRecord {
+        epoch:Epoch,
+        flag:EpochFlag,
+        clock_offset:Option<f64>,
+        satellites:Vec<SatelliteRecord>
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct SatelliteRecord {
+        sv:SV,
+        observations:Vec<(Observable, ObservationData)>
+    }
+
+    pub fn serialize<S:Serializer>(rtcm_data:&RtcmData, serializer:S) -> Result<S::Ok, S::Error> {
+
+        let records = rtcm_data.iter().map(|((epoch, flag), (clock_offset, satellites))| {
+            EpochRecord {
+                epoch: *epoch,
+                flag: *flag,
+                clock_offset: *clock_offset,
+                satellites: satellites.iter().map(|(sv, observations)| {
+                    let mut observations:Vec<(Observable, ObservationData)> = observations.iter().map(|(o, d)| (o.clone(), d.clone())).collect();
+                    observations.sort_by_key(|(observable, _)| observable.to_string());
+                    SatelliteRecord {sv: *sv, observations}
+                }).collect()
+            }
+        });
+
+        serializer.collect_seq(records)
+    }
+
+    pub fn deserialize<'de, D:Deserializer<'de>>(deserializer:D) -> Result<RtcmData, D::Error> {
+
+        let records:Vec<EpochRecord> = Vec::deserialize(deserializer)?;
+
+        let mut rtcm_data:RtcmData = BTreeMap::new();
+
+        for record in records {
+            let satellites:BTreeMap<SV, HashMap<Observable, ObservationData>> = record.satellites.into_iter()
+                .map(|satellite| (satellite.sv, satellite.observations.into_iter().collect()))
+                .collect();
+            rtcm_data.insert((record.epoch, record.flag), (record.clock_offset, satellites));
+        }
+
+        Ok(rtcm_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::{BTreeMap, HashMap};
+
+    use rinex::{observation::{EpochFlag, LliFlags, ObservationData}, prelude::{Constellation, Epoch, Observable, SV}};
+
+    use super::*;
+    use crate::RtcmData;
+
+    #[derive(Serialize, Deserialize)]
+    struct Snapshot {
+        #[serde(with = "rtcm_data")]
+        rtcm_data:RtcmData
+    }
+
+    const OBSERVABLES:[&str; 6] = ["C1C", "C2W", "D1C", "L1C", "L2W", "S1C"];
+
+    fn observable(name:&str) -> Observable {
+        match &name[..1] {
+            "C" => Observable::PseudoRange(name.to_string()),
+            "D" => Observable::Doppler(name.to_string()),
+            "L" => Observable::Phase(name.to_string()),
+            _ => Observable::SSI(name.to_string())
+        }
+    }
+
+    // the same data, observables inserted in the given order
+    fn rtcm_data(names:&[&str]) -> RtcmData {
+        let mut rtcm_data:RtcmData = BTreeMap::new();
+        for second in 0..2 {
+            let mut satellites = BTreeMap::new();
+            for prn in [3, 12] {
+                let mut observations:HashMap<Observable, ObservationData> = HashMap::new();
+                for name in names {
+                    let index = OBSERVABLES.iter().position(|o| o == name).unwrap();
+                    let lli = if *name == "L1C" && second == 1 { Some(LliFlags::LOCK_LOSS) } else { None };
+                    observations.insert(observable(name), ObservationData {obs: 1000.125 * (index + 1) as f64 + prn as f64, lli, snr: None});
+                }
+                satellites.insert(SV {constellation: Constellation::GPS, prn}, observations);
+            }
+            let epoch = Epoch::from_gpst_seconds(1_400_000_000.0 + second as f64);
+            rtcm_data.insert((epoch, EpochFlag::Ok), (Some(0.001), satellites));
+        }
+        rtcm_data
+    }
+
+    #[test]
+    fn rtcm_data_json_round_trip() {
+        let snapshot = Snapshot {rtcm_data: rtcm_data(&OBSERVABLES)};
+        let json = serde_json::to_string(&snapshot).unwrap();
+
+        let restored:Snapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.rtcm_data, snapshot.rtcm_data);
+
+        // insertion order doesn't leak into the snapshot
+        let mut reversed = OBSERVABLES;
+        reversed.reverse();
+        assert_eq!(serde_json::to_string(&Snapshot {rtcm_data: rtcm_data(&reversed)}).unwrap(), json);
+        assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+
+        // observables of the first satellite follow their sorted names
+        let positions:Vec<usize> = OBSERVABLES.iter().map(|name| json.find(&format!("\"{}\"", name)).unwrap()).collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,324 @@
+// data driven cycle slip detection on decoded data, for slips the receiver's lock time indicator misses
+// dual frequency: geometry-free and Melbourne-Wubbena combinations, any signal with doppler: doppler predicted phase
+
+use std::collections::{BTreeMap, HashMap};
+
+use nyx_space::cosmic::SPEED_OF_LIGHT;
+use rinex::{observation::{LliFlags, ObservationData}, prelude::{Carrier, Epoch, Observable, SV}};
+
+use crate::RtcmData;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SlipSource {
+    GeometryFree,
+    MelbourneWubbena,
+    Doppler
+}
+
+/// Slip found in the data on a signal the receiver reported as continuous.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CycleSlip {
+    pub epoch:Epoch,
+    pub sv:SV,
+    /// signal code, e.g. 1C
+    pub code:String,
+    pub source:SlipSource
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlipDetector {
+    /// epoch to epoch change of L1 - L2 (m)
+    pub gf_threshold_m:f64,
+    /// departure of the widelane ambiguity from its running mean (widelane cycles)
+    pub mw_threshold_cycles:f64,
+    /// difference between phase change and doppler prediction (cycles)
+    pub doppler_threshold_cycles:f64,
+    /// epochs further apart than this aren't compared (s)
+    pub max_gap_s:f64
+}
+
+impl Default for SlipDetector {
+    fn default() -> Self {
+        Self {gf_threshold_m:0.05, mw_threshold_cycles:4.0, doppler_threshold_cycles:5.0, max_gap_s:60.0}
+    }
+}
+
+// per signal values carried to the next epoch
+struct Signal {
+    code:String,
+    frequency:f64,
+    // cycles
+    phase:f64,
+    // m
+    pseudo_range:Option<f64>,
+    // Hz
+    doppler:Option<f64>,
+    lock_loss:bool
+}
+
+fn signals(sv:&SV, observations:&HashMap<Observable, ObservationData>) -> Vec<Signal> {
+
+    let mut signals:Vec<Signal> = Vec::new();
+
+    for (observable, observation) in observations.iter() {
+        if let Observable::Phase(_) = observable {
+            let code = observable.code().unwrap();
+            let frequency = match Carrier::from_observable(sv.constellation, observable) {
+                Ok(carrier) => carrier.frequency(),
+                Err(_) => continue
+            };
+            signals.push(Signal {
+                frequency,
+                phase: observation.obs,
+                pseudo_range: observations.get(&Observable::PseudoRange(format!("C{}", code))).map(|o| o.obs),
+                doppler: observations.get(&Observable::Doppler(format!("D{}", code))).map(|o| o.obs),
+                lock_loss: observation.lli.map(|lli| lli.intersects(LliFlags::LOCK_LOSS)).unwrap_or(false),
+                code
+            });
+        }
+    }
+
+    signals.sort_by(|a, b| a.code.cmp(&b.code));
+    signals
+}
+
+// first signal of the two lowest bands, e.g. L1C + L2W, for the dual frequency combinations
+fn dual_frequency_pair(signals:&Vec<Signal>) -> Option<(&Signal, &Signal)> {
+    let first = signals.first()?;
+    let second = signals.iter().find(|s| s.frequency != first.frequency)?;
+    Some((first, second))
+}
+
+fn geometry_free(a:&Signal, b:&Signal) -> f64 {
+    a.phase * SPEED_OF_LIGHT / a.frequency - b.phase * SPEED_OF_LIGHT / b.frequency
+}
+
+// widelane ambiguity (cycles), needs both pseudoranges
+fn melbourne_wubbena(a:&Signal, b:&Signal) -> Option<f64> {
+    let (p1, p2) = (a.pseudo_range?, b.pseudo_range?);
+    let (f1, f2) = (a.frequency, b.frequency);
+    let narrowlane_code = (f1 * p1 + f2 * p2) / (f1 + f2);
+    // widelane phase (m) over the widelane wavelength c / (f1 - f2)
+    Some((a.phase - b.phase) - narrowlane_code * (f1 - f2) / SPEED_OF_LIGHT)
+}
+
+impl SlipDetector {
+
+    /// Sets `LliFlags::LOCK_LOSS` on phase observations where a slip is detected and the receiver didn't flag one,
+    /// and returns those detections.
+    pub fn detect(&self, rtcm_data:&mut RtcmData) -> Vec<CycleSlip> {
+
+        let mut slips:Vec<CycleSlip> = Vec::new();
+
+        // previous epoch's signals and combinations by satellite
+        let mut previous:HashMap<SV, (Epoch, Vec<Signal>)> = HashMap::new();
+        let mut previous_gf:HashMap<SV, (String, String, f64)> = HashMap::new();
+        // running mean and count of the widelane ambiguity per satellite and pair
+        let mut mw_mean:HashMap<(SV, String, String), (f64, u32)> = HashMap::new();
+
+        for ((epoch, _), (_, satellites)) in rtcm_data.iter_mut() {
+
+            for (sv, observations) in satellites.iter_mut() {
+
+                let current = signals(sv, observations);
+                let mut detected:BTreeMap<String, SlipSource> = BTreeMap::new();
+
+                let continuous = match previous.get(sv) {
+                    Some((previous_epoch, _)) => (*epoch - *previous_epoch).to_seconds() <= self.max_gap_s,
+                    None => false
+                };
+
+                // doppler predicted phase, any signal
+                if continuous {
+                    let (previous_epoch, previous_signals) = previous.get(sv).unwrap();
+                    let dt = (*epoch - *previous_epoch).to_seconds();
+                    for signal in current.iter() {
+                        let previous_signal = match previous_signals.iter().find(|s| s.code == signal.code) {
+                            Some(previous_signal) => previous_signal,
+                            None => continue
+                        };
+                        if let (Some(d), Some(previous_d)) = (signal.doppler, previous_signal.doppler) {
+                            // doppler is the negative phase rate
+                            let predicted = -(d + previous_d) / 2.0 * dt;
+                            if (signal.phase - previous_signal.phase - predicted).abs() > self.doppler_threshold_cycles {
+                                detected.insert(signal.code.clone(), SlipSource::Doppler);
+                            }
+                        }
+                    }
+                }
+
+                if let Some((a, b)) = dual_frequency_pair(&current) {
+
+                    let gf = geometry_free(a, b);
+                    let previous_pair_gf = previous_gf.get(sv).filter(|(c1, c2, _)| *c1 == a.code && *c2 == b.code);
+                    if continuous && previous_pair_gf.is_some() && (gf - previous_pair_gf.unwrap().2).abs() > self.gf_threshold_m {
+                        detected.insert(a.code.clone(), SlipSource::GeometryFree);
+                        detected.insert(b.code.clone(), SlipSource::GeometryFree);
+                    }
+                    previous_gf.insert(*sv, (a.code.clone(), b.code.clone(), gf));
+
+                    if let Some(mw) = melbourne_wubbena(a, b) {
+                        let key = (*sv, a.code.clone(), b.code.clone());
+                        let restart = !continuous || a.lock_loss || b.lock_loss || detected.contains_key(&a.code) || detected.contains_key(&b.code);
+                        match mw_mean.get(&key).copied() {
+                            Some((mean, count)) if !restart && count > 0 && (mw - mean).abs() > self.mw_threshold_cycles => {
+                                detected.entry(a.code.clone()).or_insert(SlipSource::MelbourneWubbena);
+                                detected.entry(b.code.clone()).or_insert(SlipSource::MelbourneWubbena);
+                                mw_mean.insert(key, (mw, 1));
+                            }
+                            Some((mean, count)) if !restart => {
+                                let count = count + 1;
+                                mw_mean.insert(key, (mean + (mw - mean) / count as f64, count));
+                            }
+                            _ => {
+                                mw_mean.insert(key, (mw, 1));
+                            }
+                        }
+                    }
+                }
+
+                for (code, source) in detected {
+                    let phase = observations.get_mut(&Observable::Phase(format!("L{}", code))).unwrap();
+                    let lli = phase.lli.unwrap_or(LliFlags::OK_OR_UNKNOWN);
+                    if !lli.intersects(LliFlags::LOCK_LOSS) {
+                        phase.lli = Some(lli | LliFlags::LOCK_LOSS);
+                        slips.push(CycleSlip {epoch: *epoch, sv: *sv, code, source});
+                    }
+                }
+
+                previous.insert(*sv, (*epoch, current));
+            }
+        }
+
+        slips
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::{BTreeMap, HashMap};
+
+    use rinex::{observation::EpochFlag, prelude::{Constellation, Duration}};
+
+    use super::*;
+
+    const RANGE_M:f64 = 20_000_000.0;
+
+    fn frequency(code:&str) -> f64 {
+        Carrier::from_observable(Constellation::GPS, &Observable::Phase(format!("L{}", code))).unwrap().frequency()
+    }
+
+    // phase and pseudorange of each (code, slip in cycles) at `range` m, optionally with doppler
+    fn observations(range:f64, signals:&[(&str, f64)], doppler:Option<f64>) -> HashMap<Observable, ObservationData> {
+        let mut observations:HashMap<Observable, ObservationData> = HashMap::new();
+        for (code, slip) in signals {
+            observations.insert(Observable::Phase(format!("L{}", code)), ObservationData {obs: range * frequency(code) / SPEED_OF_LIGHT + slip, lli: None, snr: None});
+            observations.insert(Observable::PseudoRange(format!("C{}", code)), ObservationData {obs: range, lli: None, snr: None});
+            if let Some(doppler) = doppler {
+                observations.insert(Observable::Doppler(format!("D{}", code)), ObservationData {obs: doppler, lli: None, snr: None});
+            }
+        }
+        observations
+    }
+
+    fn sv() -> SV {
+        SV {constellation: Constellation::GPS, prn: 5}
+    }
+
+    fn epoch(seconds:f64) -> Epoch {
+        Epoch::from_gpst_seconds(1_400_000_000.0) + Duration::from_seconds(seconds)
+    }
+
+    // one satellite, observations by seconds from the first epoch
+    fn rtcm_data(epochs:Vec<(f64, HashMap<Observable, ObservationData>)>) -> RtcmData {
+        let mut rtcm_data:RtcmData = BTreeMap::new();
+        for (seconds, observations) in epochs {
+            let mut satellites = BTreeMap::new();
+            satellites.insert(sv(), observations);
+            rtcm_data.insert((epoch(seconds), EpochFlag::Ok), (None, satellites));
+        }
+        rtcm_data
+    }
+
+    fn lli(rtcm_data:&RtcmData, seconds:f64, code:&str) -> Option<LliFlags> {
+        rtcm_data.get(&(epoch(seconds), EpochFlag::Ok)).unwrap().1.get(&sv()).unwrap().get(&Observable::Phase(format!("L{}", code))).unwrap().lli
+    }
+
+    fn slip(seconds:f64, code:&str, source:SlipSource) -> CycleSlip {
+        CycleSlip {epoch: epoch(seconds), sv: sv(), code: code.to_string(), source}
+    }
+
+    #[test]
+    fn geometry_free_jump() {
+        // one L1 cycle from the third epoch on
+        let mut rtcm_data = rtcm_data((0..4).map(|i| {
+            let l1_slip = if i >= 2 { 1.0 } else { 0.0 };
+            (i as f64, observations(RANGE_M, &[("1C", l1_slip), ("2W", 0.0)], None))
+        }).collect());
+
+        let slips = SlipDetector::default().detect(&mut rtcm_data);
+
+        assert_eq!(slips, vec![slip(2.0, "1C", SlipSource::GeometryFree), slip(2.0, "2W", SlipSource::GeometryFree)]);
+        assert_eq!(lli(&rtcm_data, 2.0, "1C"), Some(LliFlags::LOCK_LOSS));
+        assert_eq!(lli(&rtcm_data, 2.0, "2W"), Some(LliFlags::LOCK_LOSS));
+        assert_eq!(lli(&rtcm_data, 3.0, "1C"), None);
+    }
+
+    #[test]
+    fn melbourne_wubbena_jump() {
+        // 77 L1 and 60 L2 cycles are the same length, the geometry-free combination doesn't move
+        let mut rtcm_data = rtcm_data((0..5).map(|i| {
+            let (l1_slip, l2_slip) = if i >= 3 { (77.0, 60.0) } else { (0.0, 0.0) };
+            (i as f64, observations(RANGE_M, &[("1C", l1_slip), ("2W", l2_slip)], None))
+        }).collect());
+
+        let slips = SlipDetector::default().detect(&mut rtcm_data);
+
+        assert_eq!(slips, vec![slip(3.0, "1C", SlipSource::MelbourneWubbena), slip(3.0, "2W", SlipSource::MelbourneWubbena)]);
+        assert_eq!(lli(&rtcm_data, 3.0, "1C"), Some(LliFlags::LOCK_LOSS));
+        assert_eq!(lli(&rtcm_data, 4.0, "1C"), None);
+    }
+
+    #[test]
+    fn single_frequency_doppler_mismatch() {
+        // approaching at 1000 cycles per second, 10 cycles slip on the third epoch
+        let doppler = 1000.0;
+        let mut rtcm_data = rtcm_data((0..4).map(|i| {
+            let range = RANGE_M - doppler * i as f64 * SPEED_OF_LIGHT / frequency("1C");
+            let l1_slip = if i >= 2 { 10.0 } else { 0.0 };
+            (i as f64, observations(range, &[("1C", l1_slip)], Some(doppler)))
+        }).collect());
+
+        let slips = SlipDetector::default().detect(&mut rtcm_data);
+
+        assert_eq!(slips, vec![slip(2.0, "1C", SlipSource::Doppler)]);
+        assert_eq!(lli(&rtcm_data, 1.0, "1C"), None);
+        assert_eq!(lli(&rtcm_data, 2.0, "1C"), Some(LliFlags::LOCK_LOSS));
+        assert_eq!(lli(&rtcm_data, 3.0, "1C"), None);
+    }
+
+    #[test]
+    fn gaps_and_reported_lock_loss_are_not_flagged() {
+        // a slip across a gap longer than max_gap_s, then another one the receiver already flagged
+        let mut reported = observations(RANGE_M, &[("1C", 2.0), ("2W", 0.0)], None);
+        for observation in reported.values_mut() {
+            observation.lli = Some(LliFlags::LOCK_LOSS);
+        }
+        let mut rtcm_data = rtcm_data(vec![
+            (0.0, observations(RANGE_M, &[("1C", 0.0), ("2W", 0.0)], None)),
+            (1.0, observations(RANGE_M, &[("1C", 0.0), ("2W", 0.0)], None)),
+            (100.0, observations(RANGE_M, &[("1C", 1.0), ("2W", 0.0)], None)),
+            (101.0, reported)
+        ]);
+
+        let slips = SlipDetector::default().detect(&mut rtcm_data);
+
+        assert!(slips.is_empty());
+        assert_eq!(lli(&rtcm_data, 100.0, "1C"), None);
+        assert_eq!(lli(&rtcm_data, 101.0, "1C"), Some(LliFlags::LOCK_LOSS));
+    }
+}
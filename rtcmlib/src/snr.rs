@@ -0,0 +1,101 @@
+// RINEX signal strength indicator (1-9) from MSM CNR (dB-Hz)
+
+use rinex::observation::SNR;
+
+/// Which CNR to SSI table the decoder uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SnrMapping {
+    /// RINEX 3 table (1: < 12 dB-Hz, 2: 12-17 ... 9: >= 54), CNR rounded to whole dB-Hz
+    Rinex,
+    /// RTKLIB style: floor(CNR / 6) clamped to 1-9
+    Rtklib
+}
+
+impl Default for SnrMapping {
+    fn default() -> Self {
+        SnrMapping::Rinex
+    }
+}
+
+impl SnrMapping {
+    pub fn from_str(mapping:&str) -> Option<SnrMapping> {
+        match mapping {
+            "rinex" => Some(SnrMapping::Rinex),
+            "rtklib" => Some(SnrMapping::Rtklib),
+            _ => None
+        }
+    }
+}
+
+/// SSI digit for a CNR value, none when the receiver didn't report one (CNR 0).
+pub fn ssi_digit(cnr:f64, mapping:SnrMapping) -> Option<u8> {
+
+    if !(cnr > 0.0) {
+        return None;
+    }
+
+    // both tables are 6 dB-Hz bands, they only differ in how fractional CNR falls into a band:
+    // MSM4 CNR is whole dB-Hz and maps the same either way, MSM7 CNR (1/16 dB-Hz) near a band edge doesn't
+    let band = match mapping {
+        SnrMapping::Rinex => cnr.round() / 6.0,
+        SnrMapping::Rtklib => cnr / 6.0
+    };
+
+    Some((band.floor() as i64).clamp(1, 9) as u8)
+}
+
+pub fn snr(cnr:f64, mapping:SnrMapping) -> Option<SNR> {
+    match ssi_digit(cnr, mapping)? {
+        1 => Some(SNR::DbHz12),
+        2 => Some(SNR::DbHz12_17),
+        3 => Some(SNR::DbHz18_23),
+        4 => Some(SNR::DbHz24_29),
+        5 => Some(SNR::DbHz30_35),
+        6 => Some(SNR::DbHz36_41),
+        7 => Some(SNR::DbHz42_47),
+        8 => Some(SNR::DbHz48_53),
+        _ => Some(SNR::DbHz54)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rinex_band_edges() {
+        assert_eq!(ssi_digit(0.0, SnrMapping::Rinex), None);
+        assert_eq!(ssi_digit(1.0, SnrMapping::Rinex), Some(1));
+        assert_eq!(ssi_digit(11.0, SnrMapping::Rinex), Some(1));
+        assert_eq!(ssi_digit(11.5, SnrMapping::Rinex), Some(2));
+        assert_eq!(ssi_digit(12.0, SnrMapping::Rinex), Some(2));
+        assert_eq!(ssi_digit(17.4375, SnrMapping::Rinex), Some(2));
+        assert_eq!(ssi_digit(17.5, SnrMapping::Rinex), Some(3));
+        assert_eq!(ssi_digit(47.0, SnrMapping::Rinex), Some(7));
+        assert_eq!(ssi_digit(48.0, SnrMapping::Rinex), Some(8));
+        assert_eq!(ssi_digit(53.0, SnrMapping::Rinex), Some(8));
+        assert_eq!(ssi_digit(54.0, SnrMapping::Rinex), Some(9));
+        assert_eq!(ssi_digit(63.0, SnrMapping::Rinex), Some(9));
+    }
+
+    #[test]
+    fn rtklib_band_edges() {
+        assert_eq!(ssi_digit(0.0, SnrMapping::Rtklib), None);
+        assert_eq!(ssi_digit(1.0, SnrMapping::Rtklib), Some(1));
+        assert_eq!(ssi_digit(11.9375, SnrMapping::Rtklib), Some(1));
+        assert_eq!(ssi_digit(12.0, SnrMapping::Rtklib), Some(2));
+        assert_eq!(ssi_digit(17.9375, SnrMapping::Rtklib), Some(2));
+        assert_eq!(ssi_digit(18.0, SnrMapping::Rtklib), Some(3));
+        assert_eq!(ssi_digit(53.9375, SnrMapping::Rtklib), Some(8));
+        assert_eq!(ssi_digit(54.0, SnrMapping::Rtklib), Some(9));
+        assert_eq!(ssi_digit(63.0, SnrMapping::Rtklib), Some(9));
+    }
+
+    #[test]
+    fn whole_db_hz_maps_the_same() {
+        for cnr in 1..64 {
+            assert_eq!(ssi_digit(cnr as f64, SnrMapping::Rinex), ssi_digit(cnr as f64, SnrMapping::Rtklib));
+        }
+    }
+}
@@ -0,0 +1,117 @@
+// reference station metadata from station messages (1005/1006/1007/1008/1033) and MSM headers
+
+use std::collections::BTreeSet;
+
+use rtcm_rs::Message;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StationInfo {
+    /// reference station ids seen in station and MSM messages
+    pub station_ids:BTreeSet<u16>,
+    /// antenna reference point ECEF (m) from 1005/1006
+    pub reference_position:Option<(f64, f64, f64)>,
+    /// antenna height above marker (m) from 1006
+    pub antenna_height:Option<f64>,
+    pub antenna_descriptor:Option<String>,
+    pub antenna_setup_id:Option<u8>,
+    pub antenna_serial_number:Option<String>,
+    pub receiver_type:Option<String>,
+    pub receiver_firmware_version:Option<String>,
+    pub receiver_serial_number:Option<String>
+}
+
+impl StationInfo {
+
+    /// Takes station ids and descriptors from station and MSM4/MSM7 messages, other messages are ignored.
+    pub fn update(&mut self, message:&Message) {
+        match message {
+
+            // stationary rtk reference station arp
+            Message::Msg1005(msg1005) => {
+                self.station_ids.insert(msg1005.reference_station_id);
+                self.reference_position = Some((msg1005.antenna_ref_point_ecef_x_m, msg1005.antenna_ref_point_ecef_y_m, msg1005.antenna_ref_point_ecef_z_m));
+            }
+
+            // stationary rtk reference station arp with antenna height
+            Message::Msg1006(msg1006) => {
+                self.station_ids.insert(msg1006.reference_station_id);
+                self.reference_position = Some((msg1006.antenna_ref_point_ecef_x_m, msg1006.antenna_ref_point_ecef_y_m, msg1006.antenna_ref_point_ecef_z_m));
+                self.antenna_height = Some(msg1006.antenna_height_m);
+            }
+
+            // antenna descriptor
+            Message::Msg1007(msg1007) => {
+                self.station_ids.insert(msg1007.reference_station_id);
+                self.antenna_descriptor = descriptor(msg1007.antenna_descriptor.to_string());
+                self.antenna_setup_id = Some(msg1007.antenna_setup_id);
+            }
+
+            // antenna descriptor & serial number
+            Message::Msg1008(msg1008) => {
+                self.station_ids.insert(msg1008.reference_station_id);
+                self.antenna_descriptor = descriptor(msg1008.antenna_descriptor.to_string());
+                self.antenna_setup_id = Some(msg1008.antenna_setup_id);
+                self.antenna_serial_number = descriptor(msg1008.antenna_serial_number.to_string());
+            }
+
+            // receiver and antenna descriptors
+            Message::Msg1033(msg1033) => {
+                self.station_ids.insert(msg1033.reference_station_id);
+                self.antenna_descriptor = descriptor(msg1033.antenna_descriptor.to_string());
+                self.antenna_setup_id = Some(msg1033.antenna_setup_id);
+                self.antenna_serial_number = descriptor(msg1033.antenna_serial_number.to_string());
+                self.receiver_type = descriptor(msg1033.receiver_type_descriptor.to_string());
+                self.receiver_firmware_version = descriptor(msg1033.receiver_firmware_version.to_string());
+                self.receiver_serial_number = descriptor(msg1033.receiver_serial_number.to_string());
+            }
+
+            Message::Msg1074(msg) => { self.station_ids.insert(msg.reference_station_id); }
+            Message::Msg1077(msg) => { self.station_ids.insert(msg.reference_station_id); }
+            Message::Msg1084(msg) => { self.station_ids.insert(msg.reference_station_id); }
+            Message::Msg1087(msg) => { self.station_ids.insert(msg.reference_station_id); }
+            Message::Msg1094(msg) => { self.station_ids.insert(msg.reference_station_id); }
+            Message::Msg1097(msg) => { self.station_ids.insert(msg.reference_station_id); }
+            Message::Msg1104(msg) => { self.station_ids.insert(msg.reference_station_id); }
+            Message::Msg1107(msg) => { self.station_ids.insert(msg.reference_station_id); }
+            Message::Msg1114(msg) => { self.station_ids.insert(msg.reference_station_id); }
+            Message::Msg1117(msg) => { self.station_ids.insert(msg.reference_station_id); }
+            Message::Msg1124(msg) => { self.station_ids.insert(msg.reference_station_id); }
+            Message::Msg1127(msg) => { self.station_ids.insert(msg.reference_station_id); }
+
+            _ => {}
+        }
+    }
+}
+
+// empty descriptor strings mean "not provided"
+fn descriptor(value:String) -> Option<String> {
+    let value = value.trim().to_string();
+    if value.is_empty() { None } else { Some(value) }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn station_info_json_round_trip() {
+        let station_info = StationInfo {
+            station_ids: BTreeSet::from([0, 2003]),
+            reference_position: Some((4027893.8174, 307045.6216, 4919475.1546)),
+            antenna_height: Some(0.0754),
+            antenna_descriptor: Some("TRM59800.00".to_string()),
+            antenna_setup_id: Some(0),
+            antenna_serial_number: Some("5000118316".to_string()),
+            receiver_type: Some("SEPT POLARX5".to_string()),
+            receiver_firmware_version: Some("5.5.0".to_string()),
+            receiver_serial_number: None
+        };
+
+        let json = serde_json::to_string(&station_info).unwrap();
+        assert_eq!(serde_json::from_str::<StationInfo>(&json).unwrap(), station_info);
+
+        assert_eq!(serde_json::from_str::<StationInfo>(&serde_json::to_string(&StationInfo::default()).unwrap()).unwrap(), StationInfo::default());
+    }
+}
@@ -0,0 +1,158 @@
+// decoding statistics collected by RtcmDecoder for diagnostics and conversion reports
+
+use std::collections::{BTreeMap, HashMap};
+
+use rinex::prelude::{Constellation, Epoch, SV};
+
+use crate::{is_gap, learn_interval};
+
+/// Discarded ranges kept in `DecoderStats`, later ones are only counted (and logged at debug level).
+pub const MAX_DISCARDED_RANGES:usize = 100;
+
+/// Interval in which a signal was not observed.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignalGap {
+    pub sv:SV,
+    pub code:String,
+    /// last epoch before the gap
+    pub start:Epoch,
+    /// first epoch after the gap
+    pub end:Epoch
+}
+
+/// Run of input bytes the framing layer skipped.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiscardedRange {
+    pub file:String,
+    /// byte offset in the (decompressed) input
+    pub offset:u64,
+    pub len:u64,
+    /// checksum-valid NMEA sentences found in it
+    pub nmea_sentences:u64
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecoderStats {
+    /// frames that passed CRC
+    pub frames:u64,
+    /// resyncs after a preamble that didn't frame a valid message
+    pub crc_failures:u64,
+    pub corrupt_messages:u64,
+    /// frame count by message number
+    pub message_counts:BTreeMap<u16, u64>,
+    /// MSM messages dropped because the week number (from ephemeris) wasn't known yet
+    pub msm_waiting_for_week:u64,
+    /// signals skipped as already decoded for that epoch (overlapping input)
+    pub duplicate_signals:u64,
+    /// MSM signal cells without satellite data
+    pub missing_satellites:u64,
+    /// signals for epochs before output that was already written out (live split sessions)
+    pub late_signals:u64,
+    /// signals dropped by the decoder's SignalFilter
+    pub filtered_signals:u64,
+    /// signals outside the decoder's TimeWindow or between decimated epochs
+    pub skipped_epoch_signals:u64,
+    /// bytes outside valid frames (noise, NMEA, proprietary binary, failed or truncated frames)
+    pub garbage_bytes:u64,
+    /// all skipped byte ranges, `discarded_ranges` only has the first MAX_DISCARDED_RANGES
+    pub discarded_range_count:u64,
+    pub discarded_ranges:Vec<DiscardedRange>,
+    /// signal ids without a RINEX mapping, by constellation and code
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::pairs"))]
+    pub unmapped_signals:HashMap<(Constellation, String), u64>,
+    /// epochs observed per satellite and signal code
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::pairs"))]
+    pub signal_epochs:BTreeMap<SV, BTreeMap<String, u64>>,
+    pub gaps:Vec<SignalGap>,
+    // shortest spacing seen per signal, used to detect gaps
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::pairs"))]
+    min_interval_ms:HashMap<(SV, String), u64>
+}
+
+impl DecoderStats {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record_message(&mut self, message_number:u16) {
+        self.frames += 1;
+        *self.message_counts.entry(message_number).or_insert(0) += 1;
+    }
+
+    pub(crate) fn record_discarded_range(&mut self, range:DiscardedRange) {
+        self.garbage_bytes += range.len;
+        self.discarded_range_count += 1;
+        if self.discarded_ranges.len() < MAX_DISCARDED_RANGES {
+            self.discarded_ranges.push(range);
+        }
+    }
+
+    pub(crate) fn record_signal(&mut self, sv:&SV, code:&String, epoch:Epoch, previous_epoch:Option<Epoch>) {
+
+        *self.signal_epochs.entry(*sv).or_insert(BTreeMap::new()).entry(code.clone()).or_insert(0) += 1;
+
+        if previous_epoch.is_none() {
+            return;
+        }
+
+        let key = (*sv, code.clone());
+        let dt_ms = ((epoch - previous_epoch.unwrap()).to_seconds() * 1000.0).round() as u64;
+
+        // a signal missing for longer than GAP_FACTOR times its shortest observed spacing counts as a gap
+        if is_gap(dt_ms, self.min_interval_ms.get(&key).copied()) {
+            self.gaps.push(SignalGap {sv:*sv, code:code.clone(), start:previous_epoch.unwrap(), end:epoch});
+        }
+
+        learn_interval(&mut self.min_interval_ms, &key, dt_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use rinex::prelude::Duration;
+
+    use super::*;
+
+    fn epoch(seconds:f64) -> Epoch {
+        Epoch::from_gpst_seconds(1_400_000_000.0) + Duration::from_seconds(seconds)
+    }
+
+    #[test]
+    fn gaps_against_shortest_interval() {
+        let sv = SV {constellation: Constellation::GPS, prn: 7};
+        let code = "1C".to_string();
+        let mut stats = DecoderStats::new();
+
+        // 1 s spacing with 3 s and 5 s outages, the first 2 s spacing only sets the interval to learn from
+        let seconds = [0.0, 2.0, 3.0, 4.0, 7.0, 8.0, 13.0, 14.0];
+        let mut previous_epoch = None;
+        for s in seconds {
+            stats.record_signal(&sv, &code, epoch(s), previous_epoch);
+            previous_epoch = Some(epoch(s));
+        }
+
+        assert_eq!(stats.signal_epochs[&sv]["1C"], seconds.len() as u64);
+        assert_eq!(stats.gaps, vec![
+            SignalGap {sv, code: code.clone(), start: epoch(4.0), end: epoch(7.0)},
+            SignalGap {sv, code: code.clone(), start: epoch(8.0), end: epoch(13.0)}
+        ]);
+    }
+
+    #[test]
+    fn discarded_ranges_beyond_max_are_only_counted() {
+        let mut stats = DecoderStats::new();
+        for i in 0..MAX_DISCARDED_RANGES + 5 {
+            stats.record_discarded_range(DiscardedRange {file: "input".to_string(), offset: i as u64 * 100, len: 10, nmea_sentences: 0});
+        }
+
+        assert_eq!(stats.discarded_range_count, MAX_DISCARDED_RANGES as u64 + 5);
+        assert_eq!(stats.discarded_ranges.len(), MAX_DISCARDED_RANGES);
+        assert_eq!(stats.discarded_ranges.last().unwrap().offset, (MAX_DISCARDED_RANGES as u64 - 1) * 100);
+        assert_eq!(stats.garbage_bytes, (MAX_DISCARDED_RANGES as u64 + 5) * 10);
+    }
+}
@@ -0,0 +1,761 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+#[cfg(unix)]
+use std::{thread, time::Duration};
+use float_cmp::approx_eq;
+use nyx_space::cosmic::SPEED_OF_LIGHT;
+use rinex::observation::{LliFlags, ObservationData};
+use rinex::prelude::EpochFlag;
+use rtcm_rs::{Message, MsgFrameIter};
+use rtcmlib::{ErrorPolicy, RtcmDecoder, RtcmError, SignalFilter, StationInfo, TimeWindow};
+#[cfg(unix)]
+use rtcmlib::serial::SerialConfig;
+use rtcmlib::compression::{self, Compression};
+use rtcmlib::encoder::{MsmType, RtcmEncoder};
+use rtcmlib::framing::FrameBuffer;
+#[cfg(unix)]
+use serialport::{SerialPort, TTYPort};
+use rtcmlib::prelude::{SV,Constellation, Observable};
+use rinex::prelude::Carrier;
+
+const DEBUG_RTCM:&str = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm");
+const DEBUG_RINEX:&str = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/data/debug.rtcm.rnx");
+
+#[cfg(unix)]
+#[test]
+fn serial_pty_matches_file() {
+    let file_path = DEBUG_RTCM;
+
+    let mut file_decoder = RtcmDecoder::new(false);
+    file_decoder.load_file(std::path::Path::new(file_path)).unwrap();
+
+    let stats = file_decoder.get_stats();
+    assert!(stats.frames > 0);
+    assert_eq!(stats.crc_failures, 0);
+    assert!(stats.message_counts.contains_key(&1077));
+
+    // replay the log through a pty pair, the slave end stands in for the receiver's serial device
+    let (mut master, slave) = TTYPort::pair().expect("unable to create pty pair");
+    let device_path = slave.name().unwrap();
+
+    let rtcm_buffer = std::fs::read(file_path).unwrap();
+    let writer = thread::spawn(move || {
+        for chunk in rtcm_buffer.chunks(512) {
+            master.write_all(chunk).unwrap();
+        }
+        thread::sleep(Duration::from_millis(500));
+    });
+
+    let mut serial_decoder = RtcmDecoder::new(false);
+    serial_decoder.load_serial(&device_path, &SerialConfig::default(), Some(Duration::from_secs(5))).unwrap();
+    writer.join().unwrap();
+
+    assert_eq!(serial_decoder.get_first_epoch(), file_decoder.get_first_epoch());
+    assert_eq!(serial_decoder.get_last_epoch(), file_decoder.get_last_epoch());
+    assert_eq!(serial_decoder.get_rtcm_data(), file_decoder.get_rtcm_data());
+}
+
+#[test]
+fn gzip_input_matches_raw() {
+    let file_path = DEBUG_RTCM;
+
+    let mut raw_decoder = RtcmDecoder::new(false);
+    raw_decoder.load_file(std::path::Path::new(file_path)).unwrap();
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&std::fs::read(file_path).unwrap()).unwrap();
+    let gzip_buffer = encoder.finish().unwrap();
+
+    let (detected, reader) = compression::decompress(std::io::Cursor::new(gzip_buffer)).unwrap();
+    assert_eq!(detected, Compression::Gzip);
+
+    let mut gzip_decoder = RtcmDecoder::new(false);
+    gzip_decoder.load_reader(reader).unwrap();
+
+    assert_eq!(gzip_decoder.get_rtcm_data(), raw_decoder.get_rtcm_data());
+}
+
+#[test]
+fn overlapping_files_merge() {
+    let file_path = DEBUG_RTCM;
+
+    let mut single_decoder = RtcmDecoder::new(false);
+    single_decoder.load_file(std::path::Path::new(file_path)).unwrap();
+
+    // two rotated logs sharing the middle third of the session
+    let rtcm_buffer = std::fs::read(file_path).unwrap();
+    let third = rtcm_buffer.len() / 3;
+
+    let output_dir = std::env::temp_dir();
+    let first_path = output_dir.join("overlap_a.rtcm");
+    let second_path = output_dir.join("overlap_b.rtcm");
+    std::fs::write(&first_path, &rtcm_buffer[..2 * third]).unwrap();
+    std::fs::write(&second_path, &rtcm_buffer[third..]).unwrap();
+
+    let mut merge_decoder = RtcmDecoder::new(false);
+    merge_decoder.load_file(&first_path).unwrap();
+    merge_decoder.load_file(&second_path).unwrap();
+
+    assert!(merge_decoder.get_stats().duplicate_signals > 0);
+    assert_eq!(merge_decoder.get_first_epoch(), single_decoder.get_first_epoch());
+    assert_eq!(merge_decoder.get_last_epoch(), single_decoder.get_last_epoch());
+    assert_eq!(merge_decoder.get_rtcm_data(), single_decoder.get_rtcm_data());
+}
+
+#[test]
+fn error_policy_on_truncated_log() {
+    // chop bytes out of the first 1077 frame (offset 136) so the stream has a crc failure
+    let mut rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+    rtcm_buffer.drain(150..160);
+
+    // the broken 1077 is skipped, the frames after it still decode
+    let mut skip_decoder = RtcmDecoder::new(false);
+    skip_decoder.decode_bytes(&rtcm_buffer).unwrap();
+    // one resync, however many 0xD3 candidates the broken frame holds
+    assert_eq!(skip_decoder.get_stats().crc_failures, 1);
+    assert_eq!(skip_decoder.get_stats().message_counts.get(&1077), Some(&1));
+    assert_eq!(skip_decoder.get_stats().message_counts.get(&1097), Some(&2));
+    assert!(!skip_decoder.get_rtcm_data().is_empty());
+
+    // fail fast stops at the broken 1077, before any observations were stored
+    let mut fail_decoder = RtcmDecoder::new(false);
+    fail_decoder.set_error_policy(ErrorPolicy::FailFast);
+    assert!(matches!(fail_decoder.decode_bytes(&rtcm_buffer), Err(RtcmError::CrcMismatch{message_number: Some(1077)})));
+    assert!(fail_decoder.get_rtcm_data().is_empty());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn checkpoint_resume_matches_continuous() {
+    let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+
+    let mut continuous_decoder = RtcmDecoder::new(false);
+    continuous_decoder.decode_bytes(&rtcm_buffer).unwrap();
+
+    // restart mid frame: the partial frame, weeks and lock history come back from the checkpoint
+    let checkpoint_path = std::env::temp_dir().join("rtcm2rnx_checkpoint.json");
+    let middle = rtcm_buffer.len() / 2 + 7;
+
+    let mut first_decoder = RtcmDecoder::new(false);
+    first_decoder.decode_bytes(&rtcm_buffer[..middle]).unwrap();
+    first_decoder.save_state(&checkpoint_path).unwrap();
+
+    let mut resumed_decoder = RtcmDecoder::restore_state(&checkpoint_path).unwrap();
+    resumed_decoder.decode_bytes(&rtcm_buffer[middle..]).unwrap();
+
+    assert_eq!(resumed_decoder.get_first_epoch(), continuous_decoder.get_first_epoch());
+    assert_eq!(resumed_decoder.get_last_epoch(), continuous_decoder.get_last_epoch());
+    assert_eq!(resumed_decoder.get_rtcm_data(), continuous_decoder.get_rtcm_data());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn checkpoint_resume_writes_quality_once() {
+    let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+
+    let mut continuous_decoder = RtcmDecoder::new(false);
+    continuous_decoder.set_collect_quality(true);
+    continuous_decoder.decode_bytes(&rtcm_buffer).unwrap();
+
+    // serial conversion stopped before the last Galileo MSM: rows written, then checkpointed
+    let checkpoint_path = std::env::temp_dir().join("rtcm2rnx_quality_checkpoint.json");
+    let mut first_decoder = RtcmDecoder::new(false);
+    first_decoder.set_collect_quality(true);
+    first_decoder.decode_bytes(&rtcm_buffer[..629]).unwrap();
+    let written = first_decoder.take_signal_quality();
+    first_decoder.save_state(&checkpoint_path).unwrap();
+
+    let mut resumed_decoder = RtcmDecoder::restore_state(&checkpoint_path).unwrap();
+    resumed_decoder.set_collect_quality(true);
+    resumed_decoder.decode_bytes(&rtcm_buffer[629..]).unwrap();
+    let resumed = resumed_decoder.take_signal_quality();
+
+    assert!(!written.is_empty());
+    assert!(!resumed.is_empty());
+    assert!(resumed.keys().all(|key| !written.contains_key(key)));
+    assert_eq!(written.len() + resumed.len(), continuous_decoder.get_signal_quality().len());
+    // the open session itself is still in the checkpoint
+    assert_eq!(resumed_decoder.get_rtcm_data(), continuous_decoder.get_rtcm_data());
+}
+
+#[test]
+fn signal_filter_limits_stored_data() {
+    let mut rtcm_decoder = RtcmDecoder::new(false);
+    rtcm_decoder.set_filter(SignalFilter {
+        systems: vec![Constellation::GPS],
+        exclude_sv: vec![SV {constellation: Constellation::GPS, prn: 5}],
+        signals: vec!["1C".to_string()],
+        observables: vec!['C', 'L']
+    });
+    rtcm_decoder.load_file(std::path::Path::new(DEBUG_RTCM)).unwrap();
+
+    let rtcm_data = rtcm_decoder.get_rtcm_data();
+    assert!(!rtcm_data.is_empty());
+    assert!(rtcm_decoder.get_stats().filtered_signals > 0);
+
+    for (_, (_, satellites)) in rtcm_data.iter() {
+        for (sv, observations) in satellites.iter() {
+            assert_eq!(sv.constellation, Constellation::GPS);
+            assert_ne!(sv.prn, 5);
+            for observable in observations.keys() {
+                assert!(matches!(observable, Observable::PseudoRange(_) | Observable::Phase(_)));
+                assert_eq!(observable.code().unwrap(), "1C");
+            }
+        }
+    }
+
+    let observables = rtcmlib::extract_observables(&rtcm_data);
+    assert_eq!(observables.get(&Constellation::GPS).unwrap(), &vec![Observable::PseudoRange("C1C".to_string()), Observable::Phase("L1C".to_string())]);
+}
+
+#[test]
+fn header_obs_types_match_reference() {
+    // debug.rtcm.rnx is the reference conversion of debug.rtcm: only the observables that were stored are listed
+    let mut rtcm_decoder = RtcmDecoder::new(false);
+    rtcm_decoder.load_file(std::path::Path::new(DEBUG_RTCM)).unwrap();
+    let observables = rtcmlib::extract_observables(&rtcm_decoder.get_rtcm_data());
+
+    let reference = std::fs::read_to_string(DEBUG_RINEX).unwrap();
+    let mut reference_observables:HashMap<Constellation, Vec<String>> = HashMap::new();
+    for line in reference.lines().filter(|line| line.ends_with("SYS / # / OBS TYPES")) {
+        let fields:Vec<&str> = line[..60].split_whitespace().collect();
+        let constellation:Constellation = fields[0].parse().unwrap();
+        reference_observables.insert(constellation, fields[2..].iter().map(|o| o.to_string()).collect());
+    }
+
+    assert_eq!(observables.len(), reference_observables.len());
+    for (constellation, codes) in observables.iter() {
+        let codes:Vec<String> = codes.iter().map(|o| o.to_string()).collect();
+        assert_eq!(&codes, reference_observables.get(constellation).unwrap());
+    }
+}
+
+#[test]
+fn decimation_keeps_interval_epochs() {
+    let file_path = std::path::Path::new(DEBUG_RTCM);
+
+    let mut full_decoder = RtcmDecoder::new(false);
+    full_decoder.load_file(file_path).unwrap();
+
+    let mut decimated_decoder = RtcmDecoder::new(false);
+    decimated_decoder.set_time_window(TimeWindow {start: None, end: None, interval: Some(rinex::prelude::Duration::from_seconds(5.0))});
+    decimated_decoder.load_file(file_path).unwrap();
+
+    let decimated_data = decimated_decoder.get_rtcm_data();
+    assert!(!decimated_data.is_empty());
+    assert!(decimated_data.len() < full_decoder.get_rtcm_data().len());
+
+    for ((epoch, _), _) in decimated_data.iter() {
+        assert_eq!((epoch.to_gpst_seconds() * 1000.0).round() as i64 % 5000, 0);
+    }
+}
+
+#[test]
+fn clock_jump_detect_and_repair() {
+    let mut rtcm_decoder = RtcmDecoder::new(false);
+    rtcm_decoder.load_file(std::path::Path::new(DEBUG_RTCM)).unwrap();
+
+    let original = rtcm_decoder.get_rtcm_data();
+    assert!(rtcmlib::clock::detect_clock_jumps(&original).is_empty());
+
+    // +1 ms receiver clock step in every pseudorange from the middle epoch on
+    let jump_epoch = original.keys().nth(original.len() / 2).unwrap().0;
+    let mut jumped = original.clone();
+    for ((epoch, _), (_, satellites)) in jumped.iter_mut() {
+        if *epoch >= jump_epoch {
+            for observations in satellites.values_mut() {
+                for (observable, observation) in observations.iter_mut() {
+                    if matches!(observable, Observable::PseudoRange(_)) {
+                        observation.obs += 299792.458;
+                    }
+                }
+            }
+        }
+    }
+
+    let jumps = rtcmlib::clock::detect_clock_jumps(&jumped);
+    assert_eq!(jumps.len(), 1);
+    assert_eq!(jumps[0].epoch, jump_epoch);
+    assert_eq!(jumps[0].milliseconds, 1);
+    assert!(!jumps[0].phase_jumped);
+
+    rtcmlib::clock::repair_clock_jumps(&mut jumped, &jumps);
+    for (key, (_, satellites)) in jumped.iter() {
+        for (sv, observations) in satellites.iter() {
+            for (observable, observation) in observations.iter() {
+                let expected = original.get(key).unwrap().1.get(sv).unwrap().get(observable).unwrap().obs;
+                assert!(approx_eq!(f64, observation.obs, expected, epsilon = 1e-6));
+            }
+        }
+    }
+}
+
+#[test]
+fn clock_jump_repair_carries_across_files() {
+    let mut rtcm_decoder = RtcmDecoder::new(false);
+    rtcm_decoder.load_file(std::path::Path::new(DEBUG_RTCM)).unwrap();
+    let original = rtcm_decoder.get_rtcm_data();
+
+    // first file: the first epoch, second file: the last epoch after a +1 ms step, third file: that epoch again 1 s later
+    let (first_key, first_data) = original.first_key_value().unwrap();
+    let (last_key, last_data) = original.last_key_value().unwrap();
+    let mut jumped_data = last_data.clone();
+    for observations in jumped_data.1.values_mut() {
+        for (observable, observation) in observations.iter_mut() {
+            if matches!(observable, Observable::PseudoRange(_)) {
+                observation.obs += 299792.458;
+            }
+        }
+    }
+    let mut files:Vec<rtcmlib::RtcmData> = vec![
+        BTreeMap::from([(*first_key, first_data.clone())]),
+        BTreeMap::from([(*last_key, jumped_data.clone())]),
+        BTreeMap::from([((last_key.0 + rinex::prelude::Duration::from_seconds(1.0), last_key.1), jumped_data)])
+    ];
+
+    let mut tracker = rtcmlib::clock::ClockJumpTracker::new();
+    let mut jumps:Vec<Vec<rtcmlib::clock::ClockJump>> = Vec::new();
+    for file in files.iter_mut() {
+        let file_jumps = tracker.detect(file);
+        tracker.repair(file, &file_jumps);
+        jumps.push(file_jumps);
+    }
+
+    assert!(jumps[0].is_empty());
+    assert_eq!(jumps[1].len(), 1);
+    assert_eq!(jumps[1][0].epoch, last_key.0);
+    assert_eq!(jumps[1][0].milliseconds, 1);
+    assert!(jumps[2].is_empty());
+
+    // the repair offset still applies in the third file
+    for file in files[1..].iter() {
+        for (_, (_, satellites)) in file.iter() {
+            for (sv, observations) in satellites.iter() {
+                for (observable, observation) in observations.iter() {
+                    let expected = last_data.1.get(sv).unwrap().get(observable).unwrap().obs;
+                    assert!(approx_eq!(f64, observation.obs, expected, epsilon = 1e-6));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn slip_detector_flags_injected_slip() {
+    let mut rtcm_decoder = RtcmDecoder::new(false);
+    rtcm_decoder.load_file(std::path::Path::new(DEBUG_RTCM)).unwrap();
+
+    let mut rtcm_data = rtcm_decoder.get_rtcm_data();
+    let slip_epoch = rtcm_data.keys().nth(rtcm_data.len() / 2).unwrap().0;
+    let sv = *rtcm_data.iter().find(|(k, _)| k.0 == slip_epoch).unwrap().1.1.iter()
+        .find(|(sv, observations)| sv.constellation == Constellation::GPS && observations.contains_key(&Observable::Phase("L1C".to_string())))
+        .unwrap().0;
+
+    // 10 cycle slip on L1C the receiver didn't report
+    for ((epoch, _), (_, satellites)) in rtcm_data.iter_mut() {
+        if *epoch >= slip_epoch {
+            if let Some(observation) = satellites.get_mut(&sv).and_then(|o| o.get_mut(&Observable::Phase("L1C".to_string()))) {
+                observation.obs += 10.0;
+            }
+        }
+    }
+
+    let slips = rtcmlib::slip::SlipDetector::default().detect(&mut rtcm_data);
+    assert!(slips.iter().any(|slip| slip.epoch == slip_epoch && slip.sv == sv && slip.code == "1C"));
+
+    let phase = rtcm_data.iter().find(|(k, _)| k.0 == slip_epoch).unwrap().1.1.get(&sv).unwrap().get(&Observable::Phase("L1C".to_string())).unwrap();
+    assert!(phase.lli.unwrap().intersects(LliFlags::LOCK_LOSS));
+}
+
+#[test]
+fn signal_quality_matches_stored_signals() {
+    let mut rtcm_decoder = RtcmDecoder::new(false);
+    rtcm_decoder.set_collect_quality(true);
+    rtcm_decoder.load_file(std::path::Path::new(DEBUG_RTCM)).unwrap();
+
+    let rtcm_data = rtcm_decoder.get_rtcm_data();
+    let quality = rtcm_decoder.get_signal_quality();
+
+    // a quality row for every stored phase observation, and only for satellites that were stored
+    for ((epoch, _), (_, satellites)) in rtcm_data.iter() {
+        for (sv, observations) in satellites.iter() {
+            for observable in observations.keys() {
+                if let Observable::Phase(code) = observable {
+                    let key = (*epoch, *sv, code.trim_start_matches('L').to_string());
+                    assert!(quality.contains_key(&key), "no quality row for {:?}", key);
+                }
+            }
+        }
+    }
+
+    for ((epoch, sv, _), q) in quality.iter() {
+        assert!(rtcm_data.get(&(*epoch, EpochFlag::Ok)).unwrap().1.contains_key(sv));
+        if q.extended_lock_time {
+            assert!(q.extended_satellite_info.is_some());
+            assert!(q.lock_time_indicator <= 704);
+        }
+        else {
+            assert!(q.lock_time_indicator <= 15);
+        }
+    }
+
+    // without the flag nothing is kept
+    let mut plain_decoder = RtcmDecoder::new(false);
+    plain_decoder.load_file(std::path::Path::new(DEBUG_RTCM)).unwrap();
+    assert!(plain_decoder.get_signal_quality().is_empty());
+}
+
+// no ephemeris from the encoder, take the log's so the decoder has week numbers
+fn ephemeris_frames(rtcm_buffer:&[u8]) -> Vec<u8> {
+    let mut stream:Vec<u8> = Vec::new();
+    let mut frame_buffer = FrameBuffer::new();
+    frame_buffer.extend(rtcm_buffer);
+    while let Some(frame) = frame_buffer.next_raw_frame() {
+        if frame.crc_ok && matches!(frame.message_number(), Some(1019) | Some(1042) | Some(1046)) {
+            stream.extend(frame.data);
+        }
+    }
+    stream
+}
+
+// decodes debug.rtcm, re-encodes it as `msm` and decodes that again
+fn encoder_round_trip(msm:MsmType) -> (rtcmlib::RtcmData, rtcmlib::RtcmData) {
+    let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+
+    let mut rtcm_decoder = RtcmDecoder::new(false);
+    rtcm_decoder.decode_bytes(&rtcm_buffer).unwrap();
+    let rtcm_data = rtcm_decoder.get_rtcm_data();
+
+    let mut stream = ephemeris_frames(&rtcm_buffer);
+
+    let mut encoder = RtcmEncoder::new(0, msm);
+    for ((epoch, _), (_, satellites)) in rtcm_data.iter() {
+        for frame in encoder.encode_epoch(*epoch, satellites) {
+            stream.extend(frame);
+        }
+    }
+
+    let mut round_trip_decoder = RtcmDecoder::new(false);
+    round_trip_decoder.decode_bytes(&stream).unwrap();
+
+    (rtcm_data, round_trip_decoder.get_rtcm_data())
+}
+
+#[test]
+fn encoder_round_trip_matches_decoded() {
+    let (rtcm_data, round_trip) = encoder_round_trip(MsmType::Msm7);
+
+    assert_eq!(round_trip.len(), rtcm_data.len());
+
+    for (key, (_, satellites)) in rtcm_data.iter() {
+        for (sv, observations) in satellites.iter() {
+            for (observable, observation) in observations.iter() {
+                let decoded = round_trip.get(key).and_then(|(_, s)| s.get(sv)).and_then(|o| o.get(observable))
+                    .unwrap_or_else(|| panic!("{} {} {} missing after round trip", key.0, sv, observable));
+
+                let difference = decoded.obs - observation.obs;
+                match observable {
+                    // phase may come back shifted by whole cycles to fit the fine phase range
+                    Observable::Phase(_) => {
+                        assert!((difference - difference.round()).abs() < 1e-3, "{} {} {}: {}", key.0, sv, observable, difference);
+                        if observation.lli.map(|lli| lli.intersects(LliFlags::LOCK_LOSS)).unwrap_or(false) {
+                            assert!(decoded.lli.unwrap().intersects(LliFlags::LOCK_LOSS));
+                        }
+                    }
+                    _ => assert!(difference.abs() < 1e-3, "{} {} {}: {}", key.0, sv, observable, difference)
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn encoder_msm4_round_trip() {
+    let (rtcm_data, round_trip) = encoder_round_trip(MsmType::Msm4);
+
+    assert_eq!(round_trip.len(), rtcm_data.len());
+
+    for (key, (_, satellites)) in rtcm_data.iter() {
+        for (sv, observations) in satellites.iter() {
+            for (observable, observation) in observations.iter() {
+                let decoded = round_trip.get(key).and_then(|(_, s)| s.get(sv)).and_then(|o| o.get(observable));
+
+                // MSM4 has no phase range rate
+                if matches!(observable, Observable::Doppler(_)) {
+                    assert!(decoded.is_none());
+                    continue;
+                }
+                let decoded = decoded.unwrap_or_else(|| panic!("{} {} {} missing after round trip", key.0, sv, observable));
+
+                let difference = decoded.obs - observation.obs;
+                match observable {
+                    // 2^-24 ms fine pseudorange
+                    Observable::PseudoRange(_) => assert!(difference.abs() < 0.01, "{} {} {}: {}", key.0, sv, observable, difference),
+                    // 2^-29 ms fine phase, whole cycles may be taken off
+                    Observable::Phase(_) => {
+                        assert!((difference - difference.round()).abs() < 5e-3, "{} {} {}: {}", key.0, sv, observable, difference);
+                        if observation.lli.map(|lli| lli.intersects(LliFlags::LOCK_LOSS)).unwrap_or(false) {
+                            assert!(decoded.lli.unwrap().intersects(LliFlags::LOCK_LOSS));
+                        }
+                    }
+                    // whole dB-Hz
+                    _ => assert!(difference.abs() <= 0.5, "{} {} {}: {}", key.0, sv, observable, difference)
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn encoder_station_messages_round_trip() {
+    let station = StationInfo {
+        antenna_descriptor: Some("TRM59800.00".to_string()),
+        antenna_setup_id: Some(3),
+        antenna_serial_number: Some("5000118427".to_string()),
+        receiver_type: Some("SEPT POLARX5".to_string()),
+        receiver_firmware_version: Some("5.5.0".to_string()),
+        receiver_serial_number: Some("3047651".to_string()),
+        ..StationInfo::default()
+    };
+    let position = (4027894.0061, 307045.6004, 4919474.9102);
+    let constellations = [Constellation::GPS, Constellation::Galileo].into_iter().collect();
+
+    let encoder = RtcmEncoder::new(2003, MsmType::Msm7);
+    let mut stream = encoder.encode_1005(position, &constellations);
+    stream.extend(encoder.encode_1033(&station));
+
+    let mut message_frames = MsgFrameIter::new(stream.as_slice());
+    match message_frames.next().map(|message_frame| message_frame.get_message()) {
+        Some(Message::Msg1005(msg)) => {
+            assert_eq!(msg.reference_station_id, 2003);
+            assert!(approx_eq!(f64, msg.antenna_ref_point_ecef_x_m, position.0, epsilon = 1e-4));
+            assert!(approx_eq!(f64, msg.antenna_ref_point_ecef_y_m, position.1, epsilon = 1e-4));
+            assert!(approx_eq!(f64, msg.antenna_ref_point_ecef_z_m, position.2, epsilon = 1e-4));
+        }
+        _ => panic!("expected 1005")
+    }
+    match message_frames.next().map(|message_frame| message_frame.get_message()) {
+        Some(Message::Msg1033(msg)) => {
+            assert_eq!(msg.reference_station_id, 2003);
+            assert_eq!(msg.antenna_descriptor.to_string(), "TRM59800.00");
+            assert_eq!(msg.receiver_type_descriptor.to_string(), "SEPT POLARX5");
+        }
+        _ => panic!("expected 1033")
+    }
+
+    // and back into the decoder's station info
+    let mut rtcm_decoder = RtcmDecoder::new(false);
+    rtcm_decoder.decode_bytes(&stream).unwrap();
+
+    let decoded = rtcm_decoder.get_station_info();
+    assert_eq!(decoded.station_ids, [2003].into_iter().collect());
+    assert_eq!(decoded.antenna_descriptor, station.antenna_descriptor);
+    assert_eq!(decoded.antenna_setup_id, station.antenna_setup_id);
+    assert_eq!(decoded.antenna_serial_number, station.antenna_serial_number);
+    assert_eq!(decoded.receiver_type, station.receiver_type);
+    assert_eq!(decoded.receiver_firmware_version, station.receiver_firmware_version);
+    assert_eq!(decoded.receiver_serial_number, station.receiver_serial_number);
+    let decoded_position = decoded.reference_position.unwrap();
+    assert!(approx_eq!(f64, decoded_position.0, position.0, epsilon = 1e-4));
+    assert!(approx_eq!(f64, decoded_position.2, position.2, epsilon = 1e-4));
+}
+
+const SYNTHETIC_CODES:[&str;6] = ["1C", "1W", "2W", "2L", "5I", "5Q"];
+
+// 12 GPS satellites with six signals each (72 cells) in three epochs from debug.rtcm's first one, encoded as `msm` and decoded again;
+// the last epoch has a lock loss on G01 L1C and a half cycle ambiguity on G02 L2W
+fn synthetic_round_trip(msm:MsmType) -> (Vec<Vec<Vec<u8>>>, rtcmlib::RtcmData) {
+    let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+
+    let mut rtcm_decoder = RtcmDecoder::new(false);
+    rtcm_decoder.decode_bytes(&rtcm_buffer).unwrap();
+    let first_epoch = rtcm_decoder.get_first_epoch().unwrap();
+
+    let mut stream = ephemeris_frames(&rtcm_buffer);
+    let mut encoder = RtcmEncoder::new(0, msm);
+    let mut frames:Vec<Vec<Vec<u8>>> = Vec::new();
+
+    for i in 0..3 {
+        let mut satellites:BTreeMap<SV, HashMap<Observable, ObservationData>> = BTreeMap::new();
+        for prn in 1..=12u8 {
+            let sv = SV {constellation: Constellation::GPS, prn};
+            let range = 20_000_000.0 + prn as f64 * 10_000.0 + i as f64 * 100.0;
+            let mut observations:HashMap<Observable, ObservationData> = HashMap::new();
+            for code in SYNTHETIC_CODES {
+                let lambda = SPEED_OF_LIGHT / Carrier::from_observable(Constellation::GPS, &Observable::Phase(format!("L{}", code))).unwrap().frequency();
+                let lli = match (i, prn, code) {
+                    (2, 1, "1C") => Some(LliFlags::LOCK_LOSS),
+                    (2, 2, "2W") => Some(LliFlags::HALF_CYCLE_SLIP),
+                    _ => None
+                };
+                observations.insert(Observable::PseudoRange(format!("C{}", code)), ObservationData {obs: range, lli: None, snr: None});
+                observations.insert(Observable::Phase(format!("L{}", code)), ObservationData {obs: range / lambda, lli, snr: None});
+                observations.insert(Observable::Doppler(format!("D{}", code)), ObservationData {obs: -100.0 / lambda, lli: None, snr: None});
+                observations.insert(Observable::SSI(format!("S{}", code)), ObservationData {obs: 45.0, lli: None, snr: None});
+            }
+            satellites.insert(sv, observations);
+        }
+
+        let epoch_frames = encoder.encode_epoch(first_epoch + rinex::prelude::Duration::from_seconds(i as f64), &satellites);
+        for frame in epoch_frames.iter() {
+            stream.extend(frame);
+        }
+        frames.push(epoch_frames);
+    }
+
+    let mut round_trip_decoder = RtcmDecoder::new(false);
+    round_trip_decoder.decode_bytes(&stream).unwrap();
+
+    (frames, round_trip_decoder.get_rtcm_data())
+}
+
+fn synthetic_phase(round_trip:&rtcmlib::RtcmData, epoch:usize, prn:u8, code:&str) -> ObservationData {
+    let (_, satellites) = round_trip.values().nth(epoch).unwrap();
+    satellites.get(&SV {constellation: Constellation::GPS, prn}).unwrap().get(&Observable::Phase(format!("L{}", code))).unwrap().clone()
+}
+
+#[test]
+fn encoder_splits_above_64_cells() {
+    for msm in [MsmType::Msm4, MsmType::Msm7] {
+        let (frames, round_trip) = synthetic_round_trip(msm);
+
+        // 10 satellites x 6 signals fit in the first message, the multiple message bit (payload bit 54) is set on all but the last
+        for epoch_frames in frames.iter() {
+            assert_eq!(epoch_frames.len(), 2);
+            assert_eq!(epoch_frames[0][3 + 6] & 0x02, 0x02);
+            assert_eq!(epoch_frames[1][3 + 6] & 0x02, 0x00);
+        }
+
+        assert_eq!(round_trip.len(), 3);
+        for (_, (_, satellites)) in round_trip.iter() {
+            assert_eq!(satellites.len(), 12);
+            for observations in satellites.values() {
+                for code in SYNTHETIC_CODES {
+                    assert!(observations.contains_key(&Observable::PseudoRange(format!("C{}", code))));
+                    assert!(observations.contains_key(&Observable::Phase(format!("L{}", code))));
+                    assert_eq!(observations.contains_key(&Observable::Doppler(format!("D{}", code))), msm == MsmType::Msm7);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn encoder_lock_time_follows_lli() {
+    for msm in [MsmType::Msm4, MsmType::Msm7] {
+        let (_, round_trip) = synthetic_round_trip(msm);
+
+        let lock_loss = |epoch:usize, prn:u8, code:&str| synthetic_phase(&round_trip, epoch, prn, code).lli.unwrap_or(LliFlags::OK_OR_UNKNOWN).intersects(LliFlags::LOCK_LOSS);
+
+        // DF402/DF407 only drop for the signal that lost lock, the others keep counting up
+        for prn in 1..=12u8 {
+            for code in SYNTHETIC_CODES {
+                assert!(!lock_loss(1, prn, code), "{:?} G{:02} {} epoch 1", msm, prn, code);
+                assert_eq!(lock_loss(2, prn, code), prn == 1 && code == "1C", "{:?} G{:02} {} epoch 2", msm, prn, code);
+            }
+        }
+
+        assert!(synthetic_phase(&round_trip, 2, 2, "2W").lli.unwrap().intersects(LliFlags::HALF_CYCLE_SLIP));
+        assert!(!synthetic_phase(&round_trip, 1, 2, "2W").lli.unwrap_or(LliFlags::OK_OR_UNKNOWN).intersects(LliFlags::HALF_CYCLE_SLIP));
+    }
+}
+
+#[test]
+fn msm_time_of_week_matches_decoded_header() {
+    let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+
+    let mut frame_buffer = FrameBuffer::new();
+    frame_buffer.extend(&rtcm_buffer);
+
+    let mut msm_frames = 0;
+    while let Some(frame) = frame_buffer.next_raw_frame() {
+        let decoded = MsgFrameIter::new(frame.data.as_slice()).next().map(|message_frame| message_frame.get_message());
+        match decoded {
+            Some(Message::Msg1077(msg)) => {
+                assert_eq!(frame.msm_time_of_week_ms(), Some(msg.gps_epoch_time_ms as u64));
+                msm_frames += 1;
+            }
+            Some(Message::Msg1097(msg)) => {
+                assert_eq!(frame.msm_time_of_week_ms(), Some(msg.gal_epoch_time_ms as u64));
+                msm_frames += 1;
+            }
+            _ => assert_eq!(frame.msm_time_of_week_ms(), None)
+        }
+    }
+    assert!(msm_frames > 0);
+}
+
+#[test]
+fn station_id_matches_decoded_header() {
+    let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+
+    let mut frame_buffer = FrameBuffer::new();
+    frame_buffer.extend(&rtcm_buffer);
+
+    while let Some(frame) = frame_buffer.next_raw_frame() {
+        let decoded = MsgFrameIter::new(frame.data.as_slice()).next().map(|message_frame| message_frame.get_message());
+        match decoded {
+            Some(Message::Msg1077(msg)) => assert_eq!(frame.station_id(), Some(msg.reference_station_id)),
+            Some(Message::Msg1097(msg)) => assert_eq!(frame.station_id(), Some(msg.reference_station_id)),
+            // ephemeris has no station id
+            Some(Message::Msg1019(_)) | Some(Message::Msg1046(_)) => assert_eq!(frame.station_id(), None),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn resync_reports_garbage_and_nmea() {
+    let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+
+    let mut clean_decoder = RtcmDecoder::new(false);
+    clean_decoder.load_file(std::path::Path::new(DEBUG_RTCM)).unwrap();
+
+    // radio log: an NMEA sentence and a preamble that doesn't frame a message ahead of the frames, a truncated frame at the end
+    let sentence = "$GPGGA,092750.000,5321.6802,N,00630.3372,W,1,8,1.03,61.7,M,55.2,M,,*76";
+    let mut prefix:Vec<u8> = format!("{}\r\n", sentence).into_bytes();
+    prefix.extend_from_slice(&[0xD3, 0x00, 0x05, 0x01, 0x02, 0x03, 0x04, 0x05, 0x00, 0x00, 0x00]);
+
+    let mut corrupt_buffer = prefix.clone();
+    corrupt_buffer.extend_from_slice(&rtcm_buffer);
+    corrupt_buffer.extend_from_slice(&rtcm_buffer[..20]);
+
+    let corrupt_path = std::env::temp_dir().join("rtcm2rnx_corrupt.rtcm");
+    std::fs::write(&corrupt_path, &corrupt_buffer).unwrap();
+
+    let mut rtcm_decoder = RtcmDecoder::new(false);
+    rtcm_decoder.set_extract_nmea(true);
+    rtcm_decoder.load_file(&corrupt_path).unwrap();
+
+    assert_eq!(rtcm_decoder.get_rtcm_data(), clean_decoder.get_rtcm_data());
+    assert_eq!(rtcm_decoder.take_nmea_sentences(), vec![sentence.to_string()]);
+
+    let stats = rtcm_decoder.get_stats();
+    assert_eq!(stats.crc_failures, 1);
+    assert_eq!(stats.garbage_bytes, prefix.len() as u64 + 20);
+    assert_eq!(stats.discarded_ranges.len(), 2);
+    assert_eq!((stats.discarded_ranges[0].offset, stats.discarded_ranges[0].len, stats.discarded_ranges[0].nmea_sentences), (0, prefix.len() as u64, 1));
+    assert_eq!((stats.discarded_ranges[1].offset, stats.discarded_ranges[1].len), ((prefix.len() + rtcm_buffer.len()) as u64, 20));
+    assert_eq!(stats.discarded_range_count, 2);
+}
+
+#[test]
+fn discarded_ranges_are_capped() {
+    let rtcm_buffer = std::fs::read(DEBUG_RTCM).unwrap();
+
+    // noise before each copy of the 1019 frame
+    let mut noisy_buffer:Vec<u8> = Vec::new();
+    for _ in 0..250 {
+        noisy_buffer.extend_from_slice(b"noise");
+        noisy_buffer.extend_from_slice(&rtcm_buffer[..67]);
+    }
+
+    let mut rtcm_decoder = RtcmDecoder::new(false);
+    rtcm_decoder.decode_bytes(&noisy_buffer).unwrap();
+
+    let stats = rtcm_decoder.get_stats();
+    assert_eq!(stats.garbage_bytes, 250 * 5);
+    assert_eq!(stats.discarded_range_count, 250);
+    assert_eq!(stats.discarded_ranges.len(), rtcmlib::stats::MAX_DISCARDED_RANGES);
+    assert_eq!(stats.discarded_ranges[1].offset, 72);
+}
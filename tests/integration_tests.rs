@@ -189,7 +189,7 @@ fn process_rtcm() {
                         decode_msm7(rtcm.as_mut_ptr(), 0x01);
 
                         // calc rtcmlib values
-                        let rtcmlib_observations = rtcm_decoder.process_msm1077(msg1077, msm_epoch);
+                        rtcm_decoder.process_msm1077(msg1077, msm_epoch).unwrap();
 
                         let mut obs_stats = 0;
                         let rtk = rtklib_observations.assume_init();
@@ -236,7 +236,7 @@ fn process_rtcm() {
                         decode_msm7(rtcm.as_mut_ptr(), 0x08);
 
                         // calc rtcmlib values
-                        let rtcmlib_observations = rtcm_decoder.process_msm1097(msg1097, msm_epoch);
+                        rtcm_decoder.process_msm1097(msg1097, msm_epoch).unwrap();
 
                         let mut obs_stats = 0;
                         for rtklib_obs in rtklib_observations.assume_init() {